@@ -0,0 +1,547 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+use reqwest::Method;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{client::{send_with_retry, Cassette, sse_stream, ChatCompletionsStream, RetryPolicy}, error::OGptError, model::chat_completions};
+
+use super::{parse_data_url, unix_time, Capabilities, ChatBackend};
+
+pub const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+const API_KEY_HEADER: &str = "x-api-key";
+const VERSION_HEADER: &str = "anthropic-version";
+const API_VERSION: &str = "2023-06-01";
+// The Messages API requires max_tokens, this is used when the request leaves it unset.
+const DEFAULT_MAX_TOKENS: u64 = 1024;
+// Anthropic's temperature only goes up to 1, higher OpenAI temperatures have no equivalent.
+const MAX_TEMPERATURE: f64 = 1.0;
+
+// Adapter for Anthropic's Messages API.
+#[derive(Debug, Clone)]
+pub struct AnthropicBackend {
+    client: reqwest::Client,
+    api_key: String,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
+}
+
+#[derive(Serialize, Debug)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u64,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<AnthropicToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+}
+
+#[derive(Serialize, Debug)]
+struct AnthropicMessage {
+    role: &'static str,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text { text: String },
+    Image { source: ImageSource },
+    ToolUse { id: String, name: String, input: Value },
+    ToolResult { tool_use_id: String, content: String },
+    // e.g. thinking blocks, which have no chat completions equivalent.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Serialize, Debug)]
+struct AnthropicTool {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    input_schema: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct AnthropicToolChoice {
+    #[serde(rename = "type")]
+    choice_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_parallel_tool_use: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct Metadata {
+    user_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessagesResponse {
+    id: String,
+    model: String,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AnthropicUsage {
+    // Excludes the tokens read from or written to the prompt cache.
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+impl AnthropicUsage {
+    fn prompt_tokens(&self) -> u64 {
+        self.input_tokens + self.cache_creation_input_tokens + self.cache_read_input_tokens
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart { message: StreamMessage },
+    ContentBlockStart { index: u64, content_block: ContentBlock },
+    ContentBlockDelta { index: u64, delta: BlockDelta },
+    MessageDelta {
+        delta: MessageDeltaBody,
+        #[serde(default)]
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    Error { error: StreamError },
+    // ping and content_block_stop
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamMessage {
+    id: String,
+    model: String,
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta { text: String },
+    InputJsonDelta { partial_json: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessageDeltaBody {
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamError {
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelsPage {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelEntry {
+    id: String,
+}
+
+struct StreamState {
+    events: crate::client::SseStream,
+    id: String,
+    model: String,
+    created: u64,
+    // Chat completions number tool calls, Anthropic numbers all content blocks.
+    tool_indexes: HashMap<u64, u64>,
+    // Prompt usage arrives with the start of the message, output usage with its end.
+    usage: AnthropicUsage,
+    done: bool,
+}
+
+impl AnthropicBackend {
+    pub fn new(api_key: String) -> Self {
+        AnthropicBackend {
+            client: reqwest::Client::new(),
+            api_key,
+            base_url: String::from(DEFAULT_ANTHROPIC_BASE_URL),
+            retry_policy: RetryPolicy::default(),
+            cassette: None,
+        }
+    }
+
+    pub fn base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}/{}", self.base_url.trim_end_matches('/'), path))
+            .header(API_KEY_HEADER, &self.api_key)
+            .header(VERSION_HEADER, API_VERSION)
+    }
+}
+
+#[async_trait]
+impl ChatBackend for AnthropicBackend {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision: !(model.starts_with("claude-2") || model.starts_with("claude-instant") || model.starts_with("claude-3-5-haiku")),
+            json_schema: false,
+        }
+    }
+
+    async fn chat(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, OGptError> {
+        request.validate()?;
+        let body = messages_request(request, false)?;
+        let (response, attempts) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::POST, "messages").json(&body))).await?;
+
+        let response = response.json::<MessagesResponse>().await?;
+        let mut response = chat_response(response);
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    async fn stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, OGptError> {
+        request.validate()?;
+        let body = messages_request(request, true)?;
+        let (response, _) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::POST, "messages").json(&body))).await?;
+
+        let state = StreamState {
+            events: sse_stream(response),
+            id: String::new(),
+            model: request.model.to_owned(),
+            created: unix_time(),
+            tool_indexes: HashMap::new(),
+            usage: AnthropicUsage::default(),
+            done: false,
+        };
+        Ok(Box::pin(stream::unfold(state, next_chunk)))
+    }
+
+    async fn models(&self) -> Result<Vec<String>, OGptError> {
+        let (response, _) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::GET, "models").query(&[("limit", "1000")]))).await?;
+        let page = response.json::<ModelsPage>().await?;
+        Ok(page.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+fn messages_request(request: &chat_completions::ChatCompletionsRequest, stream: bool) -> Result<MessagesRequest<'_>, OGptError> {
+    if let Some(temperature) = request.temperature.filter(|&temperature| temperature > MAX_TEMPERATURE) {
+        return Err(OGptError::InvalidRequest(format!("temperature must be between 0 and {} for Anthropic models, got {}", MAX_TEMPERATURE, temperature)));
+    }
+
+    let mut system = vec![];
+    let mut messages: Vec<AnthropicMessage> = vec![];
+    for message in &request.messages {
+        let (role, content) = match message.role {
+            chat_completions::Role::System => {
+                system.push(message.text().into_owned());
+                continue;
+            },
+            chat_completions::Role::User => ("user", user_blocks(message)),
+            chat_completions::Role::Assistant => ("assistant", assistant_blocks(message)),
+            // Tool results are sent back as part of the next user turn.
+            chat_completions::Role::Tool | chat_completions::Role::Function => ("user", vec![ContentBlock::ToolResult {
+                tool_use_id: message.tool_call_id.to_owned().unwrap_or_default(),
+                content: message.text().into_owned(),
+            }]),
+        };
+        if content.is_empty() {
+            continue;
+        }
+
+        // Turns have to alternate, consecutive messages of one role are merged.
+        match messages.last_mut() {
+            Some(last) if last.role == role => last.content.extend(content),
+            _ => messages.push(AnthropicMessage { role, content }),
+        }
+    }
+
+    let tools: Vec<AnthropicTool> = request.tools
+        .iter()
+        .flatten()
+        .map(|tool| AnthropicTool {
+            name: tool.function.name.to_owned(),
+            description: tool.function.description.to_owned(),
+            input_schema: tool.function.parameters.clone(),
+        })
+        .collect();
+
+    Ok(MessagesRequest {
+        model: &request.model,
+        max_tokens: request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        messages,
+        system: if system.is_empty() { None } else { Some(system.join("\n\n")) },
+        temperature: request.temperature,
+        top_p: request.top_p,
+        stop_sequences: request.stop.as_ref().map(|stop| stop.sequences().into_iter().map(str::to_owned).collect()).unwrap_or_default(),
+        stream,
+        tool_choice: tool_choice(request, !tools.is_empty()),
+        tools,
+        metadata: request.user.to_owned().map(|user_id| Metadata { user_id }),
+    })
+}
+
+fn user_blocks(message: &chat_completions::Message) -> Vec<ContentBlock> {
+    let parts = match &message.content {
+        Some(chat_completions::Content::Text(text)) => return text_block(text).into_iter().collect(),
+        Some(chat_completions::Content::Parts(parts)) => parts,
+        None => return vec![],
+    };
+
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            chat_completions::ContentPart::Text { text } => text_block(text),
+            chat_completions::ContentPart::ImageUrl { image_url } => {
+                let source = match parse_data_url(&image_url.url) {
+                    Some((media_type, data)) => ImageSource::Base64 { media_type: media_type.to_owned(), data: data.to_owned() },
+                    None => ImageSource::Url { url: image_url.url.to_owned() },
+                };
+                Some(ContentBlock::Image { source })
+            },
+        })
+        .collect()
+}
+
+fn assistant_blocks(message: &chat_completions::Message) -> Vec<ContentBlock> {
+    let mut blocks: Vec<ContentBlock> = text_block(&message.text()).into_iter().collect();
+    for call in message.tool_calls.iter().flatten() {
+        // Arguments the model got wrong are replaced, the input has to be an object.
+        let input = serde_json::from_str::<Value>(&call.function.arguments)
+            .ok()
+            .filter(Value::is_object)
+            .unwrap_or_else(|| Value::Object(Default::default()));
+        blocks.push(ContentBlock::ToolUse { id: call.id.to_owned(), name: call.function.name.to_owned(), input });
+    }
+    blocks
+}
+
+// Empty text blocks are rejected.
+fn text_block(text: &str) -> Option<ContentBlock> {
+    if text.is_empty() {
+        None
+    } else {
+        Some(ContentBlock::Text { text: text.to_owned() })
+    }
+}
+
+fn tool_choice(request: &chat_completions::ChatCompletionsRequest, has_tools: bool) -> Option<AnthropicToolChoice> {
+    if !has_tools {
+        return None;
+    }
+
+    let (choice_type, name) = match &request.tool_choice {
+        Some(chat_completions::ToolChoice::Mode(chat_completions::ToolChoiceMode::None)) => ("none", None),
+        Some(chat_completions::ToolChoice::Mode(chat_completions::ToolChoiceMode::Required)) => ("any", None),
+        Some(chat_completions::ToolChoice::Named(named)) => ("tool", Some(named.function.name.to_owned())),
+        Some(chat_completions::ToolChoice::Mode(chat_completions::ToolChoiceMode::Auto)) => ("auto", None),
+        None if request.parallel_tool_calls.is_some() => ("auto", None),
+        None => return None,
+    };
+    let disable_parallel_tool_use = match choice_type {
+        "none" => None,
+        _ => request.parallel_tool_calls.map(|parallel| !parallel),
+    };
+    Some(AnthropicToolChoice { choice_type, name, disable_parallel_tool_use })
+}
+
+fn finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "max_tokens" => String::from("length"),
+        "tool_use" => String::from("tool_calls"),
+        "refusal" => String::from("content_filter"),
+        _ => String::from("stop"),
+    }
+}
+
+fn chat_response(response: MessagesResponse) -> chat_completions::ChatCompletionsResponse {
+    let mut text = String::new();
+    let mut tool_calls = vec![];
+    for block in response.content {
+        match block {
+            ContentBlock::Text { text: block_text } => text.push_str(&block_text),
+            ContentBlock::ToolUse { id, name, input } => tool_calls.push(chat_completions::ToolCall {
+                id,
+                tool_type: chat_completions::ToolType::Function,
+                function: chat_completions::FunctionCall { name, arguments: input.to_string() },
+            }),
+            _ => {},
+        }
+    }
+
+    let message = chat_completions::Message {
+        content: if text.is_empty() && !tool_calls.is_empty() { None } else { Some(chat_completions::Content::Text(text)) },
+        tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+        ..chat_completions::Message::assistant(String::new())
+    };
+
+    chat_completions::ChatCompletionsResponse {
+        id: response.id,
+        object: String::from("chat.completion"),
+        created: unix_time(),
+        model: response.model,
+        usage: chat_completions::Usage::new(response.usage.prompt_tokens(), response.usage.output_tokens)
+            .cached_tokens(response.usage.cache_read_input_tokens),
+        choices: vec![chat_completions::Choice {
+            message,
+            finish_reason: finish_reason(response.stop_reason.as_deref().unwrap_or_default()),
+            index: 0,
+            logprobs: None,
+        }],
+        system_fingerprint: None,
+        attempts: 0,
+    }
+}
+
+// Translates Messages stream events into chat completion chunks until the message is complete.
+async fn next_chunk(mut state: StreamState) -> Option<(Result<chat_completions::ChatCompletionsChunk, OGptError>, StreamState)> {
+    loop {
+        if state.done {
+            return None;
+        }
+
+        let data = match state.events.next().await? {
+            Ok(data) => data,
+            Err(err) => {
+                state.done = true;
+                return Some((Err(err), state));
+            },
+        };
+        let event = match serde_json::from_str::<StreamEvent>(&data) {
+            Ok(event) => event,
+            Err(err) => {
+                state.done = true;
+                return Some((Err(OGptError::from(err)), state));
+            },
+        };
+
+        let mut delta = chat_completions::Delta::default();
+        let mut finish = None;
+        let mut usage = None;
+        match event {
+            StreamEvent::MessageStart { message } => {
+                state.id = message.id;
+                state.model = message.model;
+                state.usage = message.usage;
+                delta.role = Some(chat_completions::Role::Assistant);
+            },
+            StreamEvent::ContentBlockStart { index, content_block } => match content_block {
+                ContentBlock::Text { text } if !text.is_empty() => delta.content = Some(text),
+                ContentBlock::ToolUse { id, name, .. } => {
+                    let tool_index = state.tool_indexes.len() as u64;
+                    state.tool_indexes.insert(index, tool_index);
+                    delta.tool_calls = Some(vec![chat_completions::ToolCallDelta {
+                        index: tool_index,
+                        id: Some(id),
+                        tool_type: Some(chat_completions::ToolType::Function),
+                        function: Some(chat_completions::FunctionCallDelta { name: Some(name), arguments: Some(String::new()) }),
+                    }]);
+                },
+                _ => continue,
+            },
+            StreamEvent::ContentBlockDelta { index, delta: block_delta } => match block_delta {
+                BlockDelta::TextDelta { text } => delta.content = Some(text),
+                BlockDelta::InputJsonDelta { partial_json } => {
+                    let tool_index = match state.tool_indexes.get(&index) {
+                        Some(tool_index) => *tool_index,
+                        None => continue,
+                    };
+                    delta.tool_calls = Some(vec![chat_completions::ToolCallDelta {
+                        index: tool_index,
+                        id: None,
+                        tool_type: None,
+                        function: Some(chat_completions::FunctionCallDelta { name: None, arguments: Some(partial_json) }),
+                    }]);
+                },
+                BlockDelta::Other => continue,
+            },
+            StreamEvent::MessageDelta { delta: message_delta, usage: delta_usage } => {
+                finish = message_delta.stop_reason.as_deref().map(finish_reason);
+                state.usage.output_tokens = delta_usage.output_tokens;
+                usage = Some(chat_completions::Usage::new(state.usage.prompt_tokens(), state.usage.output_tokens)
+                    .cached_tokens(state.usage.cache_read_input_tokens));
+            },
+            StreamEvent::MessageStop => return None,
+            StreamEvent::Error { error } => {
+                state.done = true;
+                let err = OGptError::Api {
+                    status: 200,
+                    error_type: Some(error.error_type),
+                    code: None,
+                    message: error.message,
+                    retry_after: None,
+                };
+                return Some((Err(err), state));
+            },
+            StreamEvent::Other => continue,
+        }
+
+        let chunk = chat_completions::ChatCompletionsChunk {
+            id: state.id.to_owned(),
+            object: String::from("chat.completion.chunk"),
+            created: state.created,
+            model: state.model.to_owned(),
+            choices: vec![chat_completions::ChunkChoice { delta, finish_reason: finish, index: 0, logprobs: None }],
+            usage,
+        };
+        return Some((Ok(chunk), state));
+    }
+}
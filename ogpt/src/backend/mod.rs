@@ -0,0 +1,63 @@
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::{client::ChatCompletionsStream, error::OGptError, model::{audio, chat_completions, images, speech}};
+
+mod anthropic;
+mod ollama;
+mod openai;
+
+pub use anthropic::{AnthropicBackend, DEFAULT_ANTHROPIC_BASE_URL};
+pub use ollama::{OllamaBackend, DEFAULT_OLLAMA_BASE_URL};
+
+// What a backend supports for a model, so callers can leave out what it would reject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub streaming: bool,
+    pub tools: bool,
+    pub vision: bool,
+    // Whether `response_format` json_schema is honoured rather than just JSON mode.
+    pub json_schema: bool,
+}
+
+// A chat model provider. Requests and responses use the OpenAI chat completions types, which
+// adapters for other APIs translate to and from their native formats.
+#[async_trait]
+pub trait ChatBackend: fmt::Debug + Send + Sync {
+    // Short name used in logs and config, e.g. "openai".
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self, model: &str) -> Capabilities;
+
+    async fn chat(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, OGptError>;
+
+    async fn stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, OGptError>;
+
+    // Ids of the models the backend serves.
+    async fn models(&self) -> Result<Vec<String>, OGptError>;
+}
+
+// A provider of image generation, speech and transcription, which only OpenAI compatible APIs offer.
+#[async_trait]
+pub trait MediaBackend: fmt::Debug + Send + Sync {
+    async fn images(&self, request: &images::ImagesRequest) -> Result<images::ImagesResponse, OGptError>;
+
+    // The spoken audio, in the request's response format.
+    async fn speech(&self, request: &speech::SpeechRequest) -> Result<Vec<u8>, OGptError>;
+
+    async fn transcription(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, OGptError>;
+}
+
+// Splits a `data:` URL into its mime type and base64 data.
+fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (mime_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    Some((mime_type, data))
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,419 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use async_trait::async_trait;
+use base64::Engine;
+use futures_util::{Stream, StreamExt, stream};
+use reqwest::Method;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{client::{send_with_retry, Cassette, ChatCompletionsStream, RetryPolicy}, error::OGptError, model::chat_completions};
+
+use super::{parse_data_url, unix_time, Capabilities, ChatBackend};
+
+pub const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+// Model families that accept images.
+const VISION_MODELS: [&str; 6] = ["llava", "bakllava", "vision", "minicpm-v", "gemma3", "qwen2.5vl"];
+
+// Adapter for Ollama's native chat API, which unlike its OpenAI compatible endpoint takes the model
+// options and structured output schemas directly.
+#[derive(Debug, Clone)]
+pub struct OllamaBackend {
+    client: reqwest::Client,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    cassette: Option<Arc<Cassette>>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage>,
+    stream: bool,
+    // "json" or a JSON schema.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    options: Options,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<&'a [chat_completions::Tool]>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct OllamaMessage {
+    role: String,
+    #[serde(default)]
+    content: String,
+    // Base64 encoded, without a data URL prefix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OllamaFunctionCall {
+    name: String,
+    arguments: Value,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatResponse {
+    model: String,
+    #[serde(default)]
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: u64,
+    #[serde(default)]
+    eval_count: u64,
+    // Errors after a stream has started arrive as a line of their own.
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagsResponse {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Tag {
+    name: String,
+}
+
+struct StreamState<S> {
+    body: S,
+    buffer: Vec<u8>,
+    lines: VecDeque<String>,
+    id: String,
+    created: u64,
+    // Index of the next tool call, Ollama sends each call whole so they are numbered as they arrive.
+    tool_calls: u64,
+    done: bool,
+}
+
+impl OllamaBackend {
+    pub fn new() -> Self {
+        OllamaBackend {
+            client: reqwest::Client::new(),
+            base_url: String::from(DEFAULT_OLLAMA_BASE_URL),
+            retry_policy: RetryPolicy::default(),
+            cassette: None,
+        }
+    }
+
+    pub fn base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        self.client.request(method, format!("{}/{}", self.base_url.trim_end_matches('/'), path))
+    }
+
+    async fn chat_request<'a>(&self, request: &'a chat_completions::ChatCompletionsRequest, stream: bool) -> Result<ChatRequest<'a>, OGptError> {
+        let mut messages = vec![];
+        for message in &request.messages {
+            messages.push(self.message(message).await?);
+        }
+
+        let format = match &request.response_format {
+            Some(chat_completions::ResponseFormat::JsonObject) => Some(Value::String(String::from("json"))),
+            Some(chat_completions::ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema.clone()),
+            Some(chat_completions::ResponseFormat::Text) | None => None,
+        };
+
+        Ok(ChatRequest {
+            model: &request.model,
+            messages,
+            stream,
+            format,
+            options: Options {
+                temperature: request.temperature,
+                top_p: request.top_p,
+                num_predict: request.max_tokens,
+                stop: request.stop.as_ref().map(|stop| stop.sequences().into_iter().map(str::to_owned).collect()),
+                seed: request.seed,
+                presence_penalty: request.presence_penalty,
+                frequency_penalty: request.frequency_penalty,
+            },
+            tools: request.tools.as_deref(),
+        })
+    }
+
+    async fn message(&self, message: &chat_completions::Message) -> Result<OllamaMessage, OGptError> {
+        let role = match &message.role {
+            chat_completions::Role::Function => "tool",
+            role => role.as_str(),
+        };
+
+        let mut images = vec![];
+        for image in message.images() {
+            images.push(self.image_data(&image.url).await?);
+        }
+
+        let tool_calls = message.tool_calls
+            .iter()
+            .flatten()
+            .map(|call| OllamaToolCall {
+                function: OllamaFunctionCall {
+                    name: call.function.name.to_owned(),
+                    arguments: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| Value::Object(Default::default())),
+                },
+            })
+            .collect();
+
+        Ok(OllamaMessage {
+            role: role.to_owned(),
+            content: message.text().into_owned(),
+            images,
+            tool_calls,
+        })
+    }
+
+    // Ollama only takes inline image data, so images given by URL are downloaded.
+    async fn image_data(&self, url: &str) -> Result<String, OGptError> {
+        if let Some((_, data)) = parse_data_url(url) {
+            return Ok(data.to_owned());
+        }
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        let bytes = response.bytes().await?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+}
+
+impl Default for OllamaBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ChatBackend for OllamaBackend {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision: VISION_MODELS.iter().any(|family| model.contains(family)),
+            json_schema: true,
+        }
+    }
+
+    async fn chat(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, OGptError> {
+        request.validate()?;
+        let body = self.chat_request(request, false).await?;
+        let (response, attempts) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::POST, "api/chat").json(&body))).await?;
+
+        let response = response.json::<ChatResponse>().await?;
+        if let Some(err) = response.error {
+            return Err(OGptError::InvalidResponse(err));
+        }
+        let mut response = chat_response(response);
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    async fn stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, OGptError> {
+        request.validate()?;
+        let body = self.chat_request(request, true).await?;
+        let (response, _) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::POST, "api/chat").json(&body))).await?;
+
+        let created = unix_time();
+        let state = StreamState {
+            body: response.bytes_stream(),
+            buffer: vec![],
+            lines: VecDeque::new(),
+            id: format!("chatcmpl-ollama-{}", created),
+            created,
+            tool_calls: 0,
+            done: false,
+        };
+        Ok(Box::pin(stream::unfold(state, next_chunk)))
+    }
+
+    async fn models(&self) -> Result<Vec<String>, OGptError> {
+        let (response, _) = send_with_retry(&self.retry_policy, self.cassette.as_deref(), || Ok(self.request(Method::GET, "api/tags"))).await?;
+        let tags = response.json::<TagsResponse>().await?;
+        Ok(tags.models.into_iter().map(|model| model.name).collect())
+    }
+}
+
+fn tool_calls(calls: Vec<OllamaToolCall>, first_index: u64) -> Vec<chat_completions::ToolCall> {
+    calls
+        .into_iter()
+        .zip(first_index..)
+        .map(|(call, index)| chat_completions::ToolCall {
+            // Ollama doesn't give calls ids, but the results have to refer to them.
+            id: format!("call_{}", index),
+            tool_type: chat_completions::ToolType::Function,
+            function: chat_completions::FunctionCall { name: call.function.name, arguments: call.function.arguments.to_string() },
+        })
+        .collect()
+}
+
+fn finish_reason(done_reason: Option<&str>, called_tools: bool) -> String {
+    match done_reason {
+        _ if called_tools => String::from("tool_calls"),
+        Some("length") => String::from("length"),
+        _ => String::from("stop"),
+    }
+}
+
+fn chat_response(response: ChatResponse) -> chat_completions::ChatCompletionsResponse {
+    let message = response.message.unwrap_or_default();
+    let tool_calls = tool_calls(message.tool_calls, 0);
+    let created = unix_time();
+
+    chat_completions::ChatCompletionsResponse {
+        id: format!("chatcmpl-ollama-{}", created),
+        object: String::from("chat.completion"),
+        created,
+        model: response.model,
+        usage: chat_completions::Usage::new(response.prompt_eval_count, response.eval_count),
+        choices: vec![chat_completions::Choice {
+            finish_reason: finish_reason(response.done_reason.as_deref(), !tool_calls.is_empty()),
+            message: chat_completions::Message {
+                content: if message.content.is_empty() && !tool_calls.is_empty() { None } else { Some(chat_completions::Content::Text(message.content)) },
+                tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+                ..chat_completions::Message::assistant(String::new())
+            },
+            index: 0,
+            logprobs: None,
+        }],
+        system_fingerprint: None,
+        attempts: 0,
+    }
+}
+
+// Streams are newline delimited JSON, one partial response per line and a final one marked done.
+async fn next_chunk<S, B>(mut state: StreamState<S>) -> Option<(Result<chat_completions::ChatCompletionsChunk, OGptError>, StreamState<S>)>
+where
+    S: Stream<Item = reqwest::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    loop {
+        if let Some(line) = state.lines.pop_front() {
+            let response = match serde_json::from_str::<ChatResponse>(&line) {
+                Ok(response) => response,
+                Err(err) => {
+                    state.done = true;
+                    state.lines.clear();
+                    return Some((Err(OGptError::from(err)), state));
+                },
+            };
+            if let Some(err) = response.error {
+                state.done = true;
+                state.lines.clear();
+                return Some((Err(OGptError::InvalidResponse(err)), state));
+            }
+
+            let message = response.message.unwrap_or_default();
+            let first_index = state.tool_calls;
+            let calls: Vec<chat_completions::ToolCallDelta> = tool_calls(message.tool_calls, first_index)
+                .into_iter()
+                .zip(first_index..)
+                .map(|(call, index)| chat_completions::ToolCallDelta {
+                    index,
+                    id: Some(call.id),
+                    tool_type: Some(call.tool_type),
+                    function: Some(chat_completions::FunctionCallDelta { name: Some(call.function.name), arguments: Some(call.function.arguments) }),
+                })
+                .collect();
+            state.tool_calls += calls.len() as u64;
+
+            let delta = chat_completions::Delta {
+                role: None,
+                content: if message.content.is_empty() { None } else { Some(message.content) },
+                tool_calls: if calls.is_empty() { None } else { Some(calls) },
+            };
+            let finish = if response.done {
+                state.done = true;
+                state.lines.clear();
+                Some(finish_reason(response.done_reason.as_deref(), state.tool_calls > 0))
+            } else {
+                None
+            };
+
+            let chunk = chat_completions::ChatCompletionsChunk {
+                id: state.id.to_owned(),
+                object: String::from("chat.completion.chunk"),
+                created: state.created,
+                model: response.model,
+                choices: vec![chat_completions::ChunkChoice { delta, finish_reason: finish, index: 0, logprobs: None }],
+                usage: if response.done { Some(chat_completions::Usage::new(response.prompt_eval_count, response.eval_count)) } else { None },
+            };
+            return Some((Ok(chunk), state));
+        }
+
+        if state.done {
+            return None;
+        }
+
+        match state.body.next().await {
+            Some(Ok(bytes)) => {
+                state.buffer.extend_from_slice(bytes.as_ref());
+                drain_lines(&mut state.buffer, &mut state.lines);
+            },
+            Some(Err(err)) => {
+                state.done = true;
+                return Some((Err(OGptError::from(err)), state));
+            },
+            None => {
+                state.done = true;
+                state.buffer.push(b'\n');
+                drain_lines(&mut state.buffer, &mut state.lines);
+            },
+        }
+    }
+}
+
+fn drain_lines(buffer: &mut Vec<u8>, lines: &mut VecDeque<String>) {
+    while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line).trim().to_owned();
+        if !line.is_empty() {
+            lines.push_back(line);
+        }
+    }
+}
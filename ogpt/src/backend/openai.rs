@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+
+use crate::{client::{ChatCompletionsStream, OGptAsyncClient}, error::OGptError, model::{audio, chat_completions, images, speech}, model_info::ModelInfo};
+
+use super::{Capabilities, ChatBackend, MediaBackend};
+
+#[async_trait]
+impl ChatBackend for OGptAsyncClient {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn capabilities(&self, model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision: ModelInfo::for_model(model).supports_vision,
+            json_schema: true,
+        }
+    }
+
+    async fn chat(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, OGptError> {
+        self.chat_completion_async(request).await
+    }
+
+    async fn stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, OGptError> {
+        self.chat_completion_stream(request).await
+    }
+
+    async fn models(&self) -> Result<Vec<String>, OGptError> {
+        let response = self.models_async().await?;
+        Ok(response.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+#[async_trait]
+impl MediaBackend for OGptAsyncClient {
+    async fn images(&self, request: &images::ImagesRequest) -> Result<images::ImagesResponse, OGptError> {
+        self.images_async(request).await
+    }
+
+    async fn speech(&self, request: &speech::SpeechRequest) -> Result<Vec<u8>, OGptError> {
+        self.speech_async(request).await
+    }
+
+    async fn transcription(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, OGptError> {
+        self.transcription_async(request).await
+    }
+}
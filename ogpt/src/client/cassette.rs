@@ -0,0 +1,241 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, sync::Mutex};
+
+use base64::{engine::general_purpose, Engine};
+use reqwest::{header::HeaderMap, Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::OGptError;
+
+// Response headers worth keeping, the rest may identify the account (organization, request ids, cookies).
+const RECORDED_HEADERS: [&str; 2] = ["content-type", "retry-after"];
+const RECORDED_HEADER_PREFIX: &str = "x-ratelimit-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    // Requests are sent and each request/response pair is appended to the cassette file.
+    Record,
+    // Requests are answered from the cassette file, nothing is sent.
+    Replay,
+}
+
+// What a recorded request is matched on. Request headers are never recorded, so cassettes don't hold
+// API keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    // Path and query of the url, without scheme and host, so cassettes replay against any base url.
+    pub path: String,
+    // The JSON body, normalized so key order and formatting don't matter. None for bodies that aren't
+    // JSON, e.g. multipart uploads whose boundary changes on every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+impl RecordedRequest {
+    pub fn new(method: &Method, url: &Url, body: Option<&[u8]>) -> RecordedRequest {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+        RecordedRequest {
+            method: method.as_str().to_owned(),
+            path,
+            body: body.and_then(|body| serde_json::from_slice(body).ok()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    // Whether `body` is base64, for binary responses such as speech.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub base64: bool,
+}
+
+impl RecordedResponse {
+    pub fn new(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> RecordedResponse {
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| RECORDED_HEADERS.contains(&name.as_str()) || name.as_str().starts_with(RECORDED_HEADER_PREFIX))
+            .filter_map(|(name, value)| Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned())))
+            .collect();
+
+        let (body, base64) = match std::str::from_utf8(body) {
+            Ok(body) => (body.to_owned(), false),
+            Err(_) => (general_purpose::STANDARD.encode(body), true),
+        };
+        RecordedResponse { status: status.as_u16(), headers, body, base64 }
+    }
+
+    pub fn body_bytes(&self) -> Result<Vec<u8>, OGptError> {
+        if self.base64 {
+            general_purpose::STANDARD
+                .decode(&self.body)
+                .map_err(|err| OGptError::Cassette(format!("Invalid base64 body - {}", err)))
+        } else {
+            Ok(self.body.as_bytes().to_vec())
+        }
+    }
+
+    pub fn to_http(&self) -> Result<http::Response<Vec<u8>>, OGptError> {
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        builder
+            .body(self.body_bytes()?)
+            .map_err(|err| OGptError::Cassette(format!("Invalid recorded response - {}", err)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    // Which interactions have been replayed, so repeated requests (e.g. retries) get their responses in order.
+    replayed: Vec<bool>,
+}
+
+// Request/response pairs stored in a JSON file, for testing the client offline. Share one cassette between
+// clients with an `Arc`.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    state: Mutex<CassetteState>,
+}
+
+impl Cassette {
+    // Starts an empty cassette, overwriting `path` as interactions are recorded.
+    pub fn record<P: AsRef<Path>>(path: P) -> Cassette {
+        Cassette {
+            path: path.as_ref().to_owned(),
+            mode: CassetteMode::Record,
+            state: Mutex::new(CassetteState::default()),
+        }
+    }
+
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Cassette, OGptError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| OGptError::Cassette(format!("Failed to read {} - {}", path.display(), err)))?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&contents)?;
+
+        Ok(Cassette {
+            path: path.to_owned(),
+            mode: CassetteMode::Replay,
+            state: Mutex::new(CassetteState { replayed: vec![false; interactions.len()], interactions }),
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, mode: CassetteMode) -> Result<Cassette, OGptError> {
+        match mode {
+            CassetteMode::Record => Ok(Cassette::record(path)),
+            CassetteMode::Replay => Cassette::replay(path),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.lock().unwrap().interactions.clone()
+    }
+
+    // Interactions that haven't been replayed yet, to check a test made every request it was expected to.
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().replayed.iter().filter(|replayed| !**replayed).count()
+    }
+
+    // The first interaction matching `request` that hasn't been replayed yet.
+    pub fn find(&self, request: &RecordedRequest) -> Result<RecordedResponse, OGptError> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let index = state.interactions
+            .iter()
+            .zip(&state.replayed)
+            .position(|(interaction, replayed)| !replayed && interaction.request == *request)
+            .ok_or_else(|| OGptError::Cassette(format!("No recorded response for {} {} in {}", request.method, request.path, self.path.display())))?;
+
+        state.replayed[index] = true;
+        Ok(state.interactions[index].response.clone())
+    }
+
+    // Appends an interaction and rewrites the cassette file, so a recording that is cut short keeps
+    // everything up to that point.
+    pub fn push(&self, interaction: Interaction) -> Result<(), OGptError> {
+        let mut state = self.state.lock().unwrap();
+        state.interactions.push(interaction);
+        state.replayed.push(true);
+
+        let contents = serde_json::to_string_pretty(&state.interactions)?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|err| OGptError::Cassette(format!("Failed to create {} - {}", parent.display(), err)))?;
+        }
+        fs::write(&self.path, contents)
+            .map_err(|err| OGptError::Cassette(format!("Failed to write {} - {}", self.path.display(), err)))
+    }
+}
+
+// Sends `builder` through `cassette`: answered from the recording when replaying, sent and recorded when
+// recording, and simply sent without a cassette.
+pub async fn execute(cassette: Option<&Cassette>, builder: reqwest::RequestBuilder) -> Result<reqwest::Response, OGptError> {
+    let (client, request) = builder.build_split();
+    let request = request?;
+    let cassette = match cassette {
+        Some(cassette) => cassette,
+        None => return Ok(client.execute(request).await?),
+    };
+
+    let recorded = RecordedRequest::new(request.method(), request.url(), request.body().and_then(|body| body.as_bytes()));
+    let response = match cassette.mode() {
+        CassetteMode::Replay => cassette.find(&recorded)?,
+        CassetteMode::Record => {
+            let response = client.execute(request).await?;
+            let (status, headers) = (response.status(), response.headers().clone());
+            let body = response.bytes().await?;
+            let response = RecordedResponse::new(status, &headers, &body);
+            cassette.push(Interaction { request: recorded, response: response.clone() })?;
+            response
+        },
+    };
+    Ok(reqwest::Response::from(response.to_http()?))
+}
+
+pub fn execute_blocking(cassette: Option<&Cassette>, client: &reqwest::blocking::Client, builder: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response, OGptError> {
+    let request = builder.build()?;
+    let cassette = match cassette {
+        Some(cassette) => cassette,
+        None => return Ok(client.execute(request)?),
+    };
+
+    let recorded = RecordedRequest::new(request.method(), request.url(), request.body().and_then(|body| body.as_bytes()));
+    let response = match cassette.mode() {
+        CassetteMode::Replay => cassette.find(&recorded)?,
+        CassetteMode::Record => {
+            let response = client.execute(request)?;
+            let (status, headers) = (response.status(), response.headers().clone());
+            let body = response.bytes()?;
+            let response = RecordedResponse::new(status, &headers, &body);
+            cassette.push(Interaction { request: recorded, response: response.clone() })?;
+            response
+        },
+    };
+    Ok(reqwest::blocking::Response::from(response.to_http()?))
+}
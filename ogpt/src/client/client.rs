@@ -1,40 +1,209 @@
-use crate::{model::{chat_completions, models}, error};
+use std::thread;
 
-const CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
-const MODELS_URL: &str = "https://api.openai.com/v1/models";
+use reqwest::{header::CONTENT_TYPE, Method};
 
+use crate::{model::{audio, chat_completions, embeddings, images, models, moderations, speech}, error, limiter::{self, RatePermit}};
+
+use super::cassette;
+use super::config::{ClientConfig, Endpoint, OGptClientBuilder};
+use super::multipart;
+use super::response::check_status_blocking;
+use super::retry;
+use super::stream::{self, ChatCompletionsStream};
+
+// Cheap to clone, clones share the connection pool and rate limiter.
+#[derive(Debug, Clone)]
 pub struct OGptAsyncClient {
-    api_key: String,
+    config: ClientConfig,
     client: reqwest::Client,
 }
 
 impl OGptAsyncClient {
     pub fn new(api_key: String) -> OGptAsyncClient {
         OGptAsyncClient {
-            api_key,
+            config: ClientConfig::new(api_key),
             client: reqwest::Client::new(),
         }
     }
 
+    pub fn builder(api_key: String) -> OGptClientBuilder {
+        OGptClientBuilder::new(api_key)
+    }
+
+    pub fn from_config(config: ClientConfig) -> Result<OGptAsyncClient, error::OGptError> {
+        config.default_headers()?;
+        config.url(Endpoint::ChatCompletions)?;
+
+        Ok(OGptAsyncClient {
+            config,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    fn request(&self, method: Method, endpoint: Endpoint) -> Result<reqwest::RequestBuilder, error::OGptError> {
+        Ok(self.client
+            .request(method, self.config.url(endpoint)?)
+            .headers(self.config.default_headers()?))
+    }
+
+    async fn acquire(&self, estimated_tokens: u64) -> Result<Option<RatePermit>, error::OGptError> {
+        match self.config.rate_limiter() {
+            Some(rate_limiter) => Ok(Some(rate_limiter.acquire(estimated_tokens).await?)),
+            None => Ok(None),
+        }
+    }
+
+    fn reconcile(&self, permit: Option<RatePermit>, actual_tokens: u64) {
+        if let (Some(rate_limiter), Some(permit)) = (self.config.rate_limiter(), permit) {
+            rate_limiter.reconcile(permit, actual_tokens);
+        }
+    }
+
+    // Sends the request built by `build`, retrying according to the client's retry policy. Returns the
+    // successful response together with the number of attempts it took.
+    async fn send<F>(&self, build: F) -> Result<(reqwest::Response, u32), error::OGptError>
+    where F: Fn() -> Result<reqwest::RequestBuilder, error::OGptError> {
+        retry::send_with_retry(self.config.retry_policy(), self.config.cassette(), build).await
+    }
+
     pub async fn chat_completion_async(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, error::OGptError> {
-        let response = self.client
-            .post(CHAT_COMPLETIONS_URL)
-            .header("Context-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", &self.api_key))
-            .json(request)
-            .send()
+        request.validate()?;
+        let permit = self.acquire(limiter::estimate_request_tokens(request)).await?;
+        let result = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::ChatCompletions)?.json(request)))
+            .await;
+
+        let (response, attempts) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.reconcile(permit, 0);
+                return Err(err);
+            }
+        };
+
+        let mut response = response.json::<chat_completions::ChatCompletionsResponse>().await?;
+        response.attempts = attempts;
+        self.reconcile(permit, response.usage.total_tokens);
+        Ok(response)
+    }
+
+    // Retries only cover establishing the stream, chunks that fail mid-way are returned as errors. If the
+    // client was built with `stream_usage` and the request doesn't say otherwise, the stream ends with a
+    // chunk carrying the usage.
+    pub async fn chat_completion_stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, error::OGptError> {
+        let mut request = request.clone().stream(true);
+        if request.stream_options.is_none() && self.config.stream_usage() {
+            request = request.stream_options(chat_completions::StreamOptions { include_usage: true });
+        }
+        request.validate()?;
+        let permit = self.acquire(limiter::estimate_request_tokens(&request)).await?;
+        let result = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::ChatCompletions)?.json(&request)))
+            .await;
+
+        let (response, _) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.reconcile(permit, 0);
+                return Err(err);
+            }
+        };
+
+        let chunks = stream::chat_completions_stream(response);
+        match (self.config.shared_rate_limiter(), permit) {
+            (Some(rate_limiter), Some(permit)) => Ok(stream::reconcile_usage(chunks, rate_limiter, permit)),
+            _ => Ok(chunks),
+        }
+    }
+
+    pub async fn embeddings_async(&self, request: &embeddings::EmbeddingsRequest) -> Result<embeddings::EmbeddingsResponse, error::OGptError> {
+        let permit = self.acquire(limiter::estimate_embeddings_tokens(request)).await?;
+        let result = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::Embeddings)?.json(request)))
+            .await;
+
+        let (response, attempts) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.reconcile(permit, 0);
+                return Err(err);
+            }
+        };
+
+        let mut response = response.json::<embeddings::EmbeddingsResponse>().await?;
+        response.attempts = attempts;
+        self.reconcile(permit, response.usage.total_tokens);
+        Ok(response)
+    }
+
+    pub async fn images_async(&self, request: &images::ImagesRequest) -> Result<images::ImagesResponse, error::OGptError> {
+        self.acquire(0).await?;
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::ImagesGenerations)?.json(request)))
+            .await?;
+
+        let mut response = response.json::<images::ImagesResponse>().await?;
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    // The generated audio, in the request's response format (mp3 by default).
+    pub async fn speech_async(&self, request: &speech::SpeechRequest) -> Result<Vec<u8>, error::OGptError> {
+        self.acquire(0).await?;
+        let (response, _) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::AudioSpeech)?.json(request)))
+            .await?;
+
+        let audio = response.bytes().await?;
+        Ok(audio.to_vec())
+    }
+
+    pub async fn transcription_async(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.audio_async(Endpoint::AudioTranscriptions, request).await
+    }
+
+    // Transcribes the audio and translates it into English.
+    pub async fn translation_async(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.audio_async(Endpoint::AudioTranslations, request).await
+    }
+
+    async fn audio_async(&self, endpoint: Endpoint, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.acquire(0).await?;
+        let form = multipart::audio_form(request, endpoint == Endpoint::AudioTranslations);
+        let content_type = form.content_type();
+        let body = form.finish();
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, endpoint)?.header(CONTENT_TYPE, content_type.as_str()).body(body.clone())))
+            .await?;
+
+        let mut response = if request.returns_json() {
+            response.json::<audio::AudioResponse>().await?
+        } else {
+            audio::AudioResponse { text: response.text().await?, language: None, duration: None, attempts: 0 }
+        };
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    pub async fn moderations_async(&self, request: &moderations::ModerationsRequest) -> Result<moderations::ModerationsResponse, error::OGptError> {
+        self.acquire(0).await?;
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::Moderations)?.json(request)))
             .await?;
 
-        let response = response.json::<chat_completions::ChatCompletionsResponse>().await?;
+        let mut response = response.json::<moderations::ModerationsResponse>().await?;
+        response.attempts = attempts;
         Ok(response)
     }
 
     pub async fn models_async(&self) -> Result<models::ModelsResponse, error::OGptError> {
-        let response = self.client
-            .get(MODELS_URL)
-            .header("Context-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", &self.api_key))
-            .send()
+        self.acquire(0).await?;
+        let (response, _) = self
+            .send(|| self.request(Method::GET, Endpoint::Models))
             .await?;
 
         let response = response.json::<models::ModelsResponse>().await?;
@@ -42,39 +211,183 @@ impl OGptAsyncClient {
     }
 }
 
+// Blocks the calling thread for the whole request, including the backoff between retries and waits for
+// the rate limiter. Don't use it on an async runtime's worker threads, OGptAsyncClient waits without
+// blocking.
 pub struct OGptSyncClient {
-    api_key: String,
+    config: ClientConfig,
     client: reqwest::blocking::Client,
 }
 
 impl OGptSyncClient {
     pub fn new(api_key: String) -> OGptSyncClient {
         OGptSyncClient {
-            api_key,
+            config: ClientConfig::new(api_key),
             client: reqwest::blocking::Client::new(),
         }
     }
 
-    pub async fn chat_completion_sync(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, error::OGptError> {
-        let response = self.client
-            .post(CHAT_COMPLETIONS_URL)
-            .header("Context-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", &self.api_key))
-            .json(request)
-            .send()?;
+    pub fn builder(api_key: String) -> OGptClientBuilder {
+        OGptClientBuilder::new(api_key)
+    }
+
+    pub fn from_config(config: ClientConfig) -> Result<OGptSyncClient, error::OGptError> {
+        config.default_headers()?;
+        config.url(Endpoint::ChatCompletions)?;
+
+        Ok(OGptSyncClient {
+            config,
+            client: reqwest::blocking::Client::new(),
+        })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    fn request(&self, method: Method, endpoint: Endpoint) -> Result<reqwest::blocking::RequestBuilder, error::OGptError> {
+        Ok(self.client
+            .request(method, self.config.url(endpoint)?)
+            .headers(self.config.default_headers()?))
+    }
+
+    fn acquire(&self, estimated_tokens: u64) -> Result<Option<RatePermit>, error::OGptError> {
+        match self.config.rate_limiter() {
+            Some(rate_limiter) => Ok(Some(rate_limiter.acquire_blocking(estimated_tokens)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn reconcile(&self, permit: Option<RatePermit>, actual_tokens: u64) {
+        if let (Some(rate_limiter), Some(permit)) = (self.config.rate_limiter(), permit) {
+            rate_limiter.reconcile(permit, actual_tokens);
+        }
+    }
+
+    fn send<F>(&self, build: F) -> Result<(reqwest::blocking::Response, u32), error::OGptError>
+    where F: Fn() -> Result<reqwest::blocking::RequestBuilder, error::OGptError> {
+        let mut attempt = 1;
+        loop {
+            let result = match cassette::execute_blocking(self.config.cassette(), &self.client, build()?) {
+                Ok(response) => check_status_blocking(response),
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(response) => return Ok((response, attempt)),
+                Err(err) => match self.config.retry_policy().next_delay(attempt, &err) {
+                    Some(delay) => {
+                        // Blocks the caller's thread, see OGptSyncClient.
+                        thread::sleep(delay);
+                        attempt += 1;
+                    },
+                    None => return Err(err.with_attempts(attempt)),
+                },
+            }
+        }
+    }
+
+    pub fn chat_completion_sync(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, error::OGptError> {
+        request.validate()?;
+        let permit = self.acquire(limiter::estimate_request_tokens(request))?;
+        let result = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::ChatCompletions)?.json(request)));
+
+        let (response, attempts) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.reconcile(permit, 0);
+                return Err(err);
+            }
+        };
+
+        let mut response = response.json::<chat_completions::ChatCompletionsResponse>()?;
+        response.attempts = attempts;
+        self.reconcile(permit, response.usage.total_tokens);
+        Ok(response)
+    }
+
+    pub fn embeddings_sync(&self, request: &embeddings::EmbeddingsRequest) -> Result<embeddings::EmbeddingsResponse, error::OGptError> {
+        let permit = self.acquire(limiter::estimate_embeddings_tokens(request))?;
+        let result = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::Embeddings)?.json(request)));
 
-        let response = response.json::<chat_completions::ChatCompletionsResponse>()?;
+        let (response, attempts) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.reconcile(permit, 0);
+                return Err(err);
+            }
+        };
+
+        let mut response = response.json::<embeddings::EmbeddingsResponse>()?;
+        response.attempts = attempts;
+        self.reconcile(permit, response.usage.total_tokens);
         Ok(response)
     }
 
-    pub async fn models_sync(&self) -> Result<models::ModelsResponse, error::OGptError> {
-        let response = self.client
-            .get(MODELS_URL)
-            .header("Context-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", &self.api_key))
-            .send()?;
+    pub fn images_sync(&self, request: &images::ImagesRequest) -> Result<images::ImagesResponse, error::OGptError> {
+        self.acquire(0)?;
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::ImagesGenerations)?.json(request)))?;
+
+        let mut response = response.json::<images::ImagesResponse>()?;
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    // The generated audio, in the request's response format (mp3 by default).
+    pub fn speech_sync(&self, request: &speech::SpeechRequest) -> Result<Vec<u8>, error::OGptError> {
+        self.acquire(0)?;
+        let (response, _) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::AudioSpeech)?.json(request)))?;
+
+        let audio = response.bytes()?;
+        Ok(audio.to_vec())
+    }
+
+    pub fn transcription_sync(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.audio_sync(Endpoint::AudioTranscriptions, request)
+    }
+
+    // Transcribes the audio and translates it into English.
+    pub fn translation_sync(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.audio_sync(Endpoint::AudioTranslations, request)
+    }
+
+    fn audio_sync(&self, endpoint: Endpoint, request: &audio::AudioRequest) -> Result<audio::AudioResponse, error::OGptError> {
+        self.acquire(0)?;
+        let form = multipart::audio_form(request, endpoint == Endpoint::AudioTranslations);
+        let content_type = form.content_type();
+        let body = form.finish();
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, endpoint)?.header(CONTENT_TYPE, content_type.as_str()).body(body.clone())))?;
+
+        let mut response = if request.returns_json() {
+            response.json::<audio::AudioResponse>()?
+        } else {
+            audio::AudioResponse { text: response.text()?, language: None, duration: None, attempts: 0 }
+        };
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    pub fn moderations_sync(&self, request: &moderations::ModerationsRequest) -> Result<moderations::ModerationsResponse, error::OGptError> {
+        self.acquire(0)?;
+        let (response, attempts) = self
+            .send(|| Ok(self.request(Method::POST, Endpoint::Moderations)?.json(request)))?;
+
+        let mut response = response.json::<moderations::ModerationsResponse>()?;
+        response.attempts = attempts;
+        Ok(response)
+    }
+
+    pub fn models_sync(&self) -> Result<models::ModelsResponse, error::OGptError> {
+        self.acquire(0)?;
+        let (response, _) = self
+            .send(|| self.request(Method::GET, Endpoint::Models))?;
 
         let response = response.json::<models::ModelsResponse>()?;
         Ok(response)
     }
-}
\ No newline at end of file
+}
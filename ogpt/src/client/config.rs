@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION}, Url};
+
+use crate::{error::OGptError, limiter::RateLimiter};
+
+use super::cassette::Cassette;
+use super::retry::RetryPolicy;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+const ORGANIZATION_HEADER: &str = "OpenAI-Organization";
+const PROJECT_HEADER: &str = "OpenAI-Project";
+const AZURE_API_KEY_HEADER: &str = "api-key";
+const AZURE_API_VERSION_PARAM: &str = "api-version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    AudioSpeech,
+    AudioTranscriptions,
+    AudioTranslations,
+    ChatCompletions,
+    Embeddings,
+    ImagesGenerations,
+    Models,
+    Moderations,
+}
+
+impl Endpoint {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Endpoint::AudioSpeech => "audio/speech",
+            Endpoint::AudioTranscriptions => "audio/transcriptions",
+            Endpoint::AudioTranslations => "audio/translations",
+            Endpoint::ChatCompletions => "chat/completions",
+            Endpoint::Embeddings => "embeddings",
+            Endpoint::ImagesGenerations => "images/generations",
+            Endpoint::Models => "models",
+            Endpoint::Moderations => "moderations",
+        }
+    }
+
+    // Azure serves model invocations under a deployment, everything else at the resource level.
+    fn is_deployment_scoped(&self) -> bool {
+        match self {
+            Endpoint::AudioSpeech => true,
+            Endpoint::AudioTranscriptions => true,
+            Endpoint::AudioTranslations => true,
+            Endpoint::ChatCompletions => true,
+            Endpoint::Embeddings => true,
+            Endpoint::ImagesGenerations => true,
+            Endpoint::Models => false,
+            Endpoint::Moderations => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AzureDeployment {
+    deployment: String,
+    api_version: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    base_url: String,
+    api_key: String,
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+    azure: Option<AzureDeployment>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    cassette: Option<Arc<Cassette>>,
+    stream_usage: bool,
+}
+
+impl ClientConfig {
+    pub fn new(api_key: String) -> ClientConfig {
+        ClientConfig {
+            base_url: String::from(DEFAULT_BASE_URL),
+            api_key,
+            headers: vec![],
+            query: vec![],
+            azure: None,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            cassette: None,
+            stream_usage: false,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_deref()
+    }
+
+    pub(crate) fn shared_rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.rate_limiter.clone()
+    }
+
+    pub fn cassette(&self) -> Option<&Cassette> {
+        self.cassette.as_deref()
+    }
+
+    pub fn stream_usage(&self) -> bool {
+        self.stream_usage
+    }
+
+    pub fn url(&self, endpoint: Endpoint) -> Result<Url, OGptError> {
+        let base_url = self.base_url.trim_end_matches('/');
+        let url = match &self.azure {
+            Some(azure) if endpoint.is_deployment_scoped() => format!("{}/openai/deployments/{}/{}", base_url, azure.deployment, endpoint.path()),
+            Some(_) => format!("{}/openai/{}", base_url, endpoint.path()),
+            None => format!("{}/{}", base_url, endpoint.path()),
+        };
+
+        let mut url = Url::parse(&url).map_err(|err| OGptError::InvalidConfig(format!("Invalid url {} - {}", url, err)))?;
+        if !self.query.is_empty() || self.azure.is_some() {
+            let mut pairs = url.query_pairs_mut();
+            if let Some(azure) = &self.azure {
+                pairs.append_pair(AZURE_API_VERSION_PARAM, &azure.api_version);
+            }
+            for (key, value) in &self.query {
+                pairs.append_pair(key, value);
+            }
+        }
+        Ok(url)
+    }
+
+    pub fn default_headers(&self) -> Result<HeaderMap, OGptError> {
+        let mut headers = HeaderMap::new();
+
+        if !self.api_key.is_empty() {
+            let (name, value) = match self.azure {
+                Some(_) => (HeaderName::from_static(AZURE_API_KEY_HEADER), self.api_key.to_owned()),
+                None => (AUTHORIZATION, format!("Bearer {}", self.api_key)),
+            };
+            let mut value = header_value(&value)?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| OGptError::InvalidConfig(format!("Invalid header name {} - {}", name, err)))?;
+            headers.insert(name, header_value(value)?);
+        }
+        Ok(headers)
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, OGptError> {
+    HeaderValue::from_str(value).map_err(|err| OGptError::InvalidConfig(format!("Invalid header value - {}", err)))
+}
+
+#[derive(Debug, Clone)]
+pub struct OGptClientBuilder {
+    config: ClientConfig,
+}
+
+impl OGptClientBuilder {
+    pub fn new(api_key: String) -> OGptClientBuilder {
+        OGptClientBuilder {
+            config: ClientConfig::new(api_key),
+        }
+    }
+
+    // Points the client at any OpenAI-compatible server, e.g. `http://localhost:11434/v1` for Ollama.
+    pub fn base_url(mut self, base_url: String) -> Self {
+        self.config.base_url = base_url;
+        self
+    }
+
+    pub fn organization(self, organization: String) -> Self {
+        self.header(String::from(ORGANIZATION_HEADER), organization)
+    }
+
+    pub fn project(self, project: String) -> Self {
+        self.header(String::from(PROJECT_HEADER), project)
+    }
+
+    pub fn header(mut self, name: String, value: String) -> Self {
+        self.config.headers.push((name, value));
+        self
+    }
+
+    pub fn query_param(mut self, key: String, value: String) -> Self {
+        self.config.query.push((key, value));
+        self
+    }
+
+    // Uses Azure OpenAI routing: `base_url` is the resource endpoint (`https://<resource>.openai.azure.com`),
+    // requests go to the given deployment with an `api-version` query parameter and `api-key` auth.
+    pub fn azure_deployment(mut self, deployment: String, api_version: String) -> Self {
+        self.config.azure = Some(AzureDeployment { deployment, api_version });
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.config.retry_policy = retry_policy;
+        self
+    }
+
+    // The limiter is shared so that several clients drawing on the same organisation budget can use one.
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.config.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    // Records requests to, or replays them from, the cassette instead of only talking to the server.
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.config.cassette = Some(cassette);
+        self
+    }
+
+    // Asks for the usage at the end of every stream with `stream_options.include_usage`. OpenAI supports
+    // it, but Azure before api-version 2024-09-01-preview and many compatible servers reject the option.
+    pub fn stream_usage(mut self, stream_usage: bool) -> Self {
+        self.config.stream_usage = stream_usage;
+        self
+    }
+
+    pub fn config(self) -> ClientConfig {
+        self.config
+    }
+
+    pub fn build_async(self) -> Result<super::OGptAsyncClient, OGptError> {
+        super::OGptAsyncClient::from_config(self.config)
+    }
+
+    pub fn build_sync(self) -> Result<super::OGptSyncClient, OGptError> {
+        super::OGptSyncClient::from_config(self.config)
+    }
+}
@@ -1,4 +1,17 @@
+mod cassette;
+#[allow(clippy::module_inception)]
 mod client;
+mod config;
+mod multipart;
+mod response;
+mod retry;
+mod stream;
 
+pub use cassette::{Cassette, CassetteMode, Interaction, RecordedRequest, RecordedResponse};
 pub use client::OGptAsyncClient;
-pub use client::OGptSyncClient;
\ No newline at end of file
+pub use client::OGptSyncClient;
+pub use config::{ClientConfig, Endpoint, OGptClientBuilder, DEFAULT_BASE_URL};
+pub use retry::RetryPolicy;
+pub(crate) use retry::send_with_retry;
+pub use stream::ChatCompletionsStream;
+pub(crate) use stream::{sse_stream, SseStream};
\ No newline at end of file
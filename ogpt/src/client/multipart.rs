@@ -0,0 +1,85 @@
+use crate::model::audio;
+
+// A multipart/form-data body built up front, so the same bytes can be sent again when a request is retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartForm {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl MultipartForm {
+    pub fn new() -> Self {
+        MultipartForm {
+            boundary: format!("ogpt-boundary-{:016x}", rand::random::<u64>()),
+            body: vec![],
+        }
+    }
+
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.part_header(name, None, None);
+        self.body.extend_from_slice(value.as_bytes());
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    pub fn file(mut self, name: &str, filename: &str, content_type: &str, data: &[u8]) -> Self {
+        self.part_header(name, Some(filename), Some(content_type));
+        self.body.extend_from_slice(data);
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        self.body
+    }
+
+    fn part_header(&mut self, name: &str, filename: Option<&str>, content_type: Option<&str>) {
+        let mut header = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", self.boundary, escape(name));
+        if let Some(filename) = filename {
+            header.push_str(&format!("; filename=\"{}\"", escape(filename)));
+        }
+        header.push_str("\r\n");
+        if let Some(content_type) = content_type {
+            header.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        header.push_str("\r\n");
+        self.body.extend_from_slice(header.as_bytes());
+    }
+}
+
+impl Default for MultipartForm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Quotes and line breaks would end the header value early, so they are percent encoded like browsers do.
+fn escape(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+// The form for a transcription or translation, translations take no language.
+pub fn audio_form(request: &audio::AudioRequest, translation: bool) -> MultipartForm {
+    let mut form = MultipartForm::new()
+        .file("file", &request.filename, request.content_type(), &request.file)
+        .text("model", &request.model);
+
+    if let (Some(language), false) = (&request.language, translation) {
+        form = form.text("language", language);
+    }
+    if let Some(prompt) = &request.prompt {
+        form = form.text("prompt", prompt);
+    }
+    if let Some(response_format) = request.response_format_str() {
+        form = form.text("response_format", response_format);
+    }
+    if let Some(temperature) = request.temperature {
+        form = form.text("temperature", &temperature.to_string());
+    }
+    form
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use reqwest::{header::HeaderMap, StatusCode};
+
+use crate::{model::api_error::ApiErrorResponse, error::OGptError};
+
+const RETRY_AFTER_MS_HEADER: &str = "retry-after-ms";
+const RETRY_AFTER_HEADER: &str = "retry-after";
+const RATELIMIT_HEADERS: [(&str, &str); 2] = [
+    ("x-ratelimit-remaining-requests", "x-ratelimit-reset-requests"),
+    ("x-ratelimit-remaining-tokens", "x-ratelimit-reset-tokens"),
+];
+
+pub async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, OGptError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.text().await?;
+    Err(api_error(status, &headers, &body))
+}
+
+pub fn check_status_blocking(response: reqwest::blocking::Response) -> Result<reqwest::blocking::Response, OGptError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.text()?;
+    Err(api_error(status, &headers, &body))
+}
+
+pub fn api_error(status: StatusCode, headers: &HeaderMap, body: &str) -> OGptError {
+    let retry_after = match status {
+        StatusCode::TOO_MANY_REQUESTS => retry_after(headers).or_else(|| ratelimit_reset(headers)),
+        _ => retry_after(headers),
+    };
+
+    match serde_json::from_str::<ApiErrorResponse>(body) {
+        Ok(response) => OGptError::Api {
+            status: status.as_u16(),
+            code: response.error.code_string(),
+            error_type: response.error.error_type,
+            message: response.error.message,
+            retry_after,
+        },
+        Err(_) => OGptError::Api {
+            status: status.as_u16(),
+            error_type: None,
+            code: None,
+            message: match body.trim() {
+                "" => status.canonical_reason().unwrap_or("Unknown error").to_owned(),
+                body => body.to_owned(),
+            },
+            retry_after,
+        },
+    }
+}
+
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+
+    if let Some(millis) = header(RETRY_AFTER_MS_HEADER) {
+        return duration_from_secs(millis / 1000.0);
+    }
+    duration_from_secs(header(RETRY_AFTER_HEADER)?)
+}
+
+// The time until every exhausted rate limit bucket has been replenished.
+pub fn ratelimit_reset(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok().map(str::trim);
+
+    RATELIMIT_HEADERS
+        .iter()
+        .filter(|(remaining, _)| header(remaining) == Some("0"))
+        .filter_map(|(_, reset)| parse_reset_duration(header(reset)?))
+        .max()
+}
+
+// Parses Go-style durations as sent in the `x-ratelimit-reset-*` headers, e.g. `20ms`, `1.5s` or `6m0s`.
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0_f64;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let number = rest[..number_end].parse::<f64>().ok()?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "h" => 3600_f64,
+            "m" => 60_f64,
+            "s" | "" => 1_f64,
+            "ms" => 0.001,
+            "us" | "µs" => 0.000_001,
+            "ns" => 0.000_000_001,
+            _ => return None,
+        };
+        rest = &rest[unit_end..];
+        total += number * seconds;
+    }
+    duration_from_secs(total)
+}
+
+fn duration_from_secs(secs: f64) -> Option<Duration> {
+    if secs.is_finite() && secs >= 0.0 {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
\ No newline at end of file
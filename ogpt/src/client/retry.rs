@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error::OGptError;
+
+use super::cassette::{self, Cassette};
+use super::response::check_status;
+
+const INSUFFICIENT_QUOTA_CODE: &str = "insufficient_quota";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: bool,
+    // Server requested waits longer than this are not worth blocking a reply on.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2_f64,
+            jitter: true,
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    pub fn is_retryable(err: &OGptError) -> bool {
+        match err {
+            OGptError::Api { status, code, .. } => match status {
+                429 => code.as_deref() != Some(INSUFFICIENT_QUOTA_CODE),
+                408 | 409 => true,
+                status => *status >= 500,
+            },
+            OGptError::Reqwest(err) => err.is_timeout() || err.is_connect() || err.is_request(),
+            _ => false,
+        }
+    }
+
+    // Returns how long to wait before attempt `attempt + 1`, or None if the error should not be retried.
+    pub fn next_delay(&self, attempt: u32, err: &OGptError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !Self::is_retryable(err) {
+            return None;
+        }
+
+        match err.retry_after() {
+            Some(retry_after) if retry_after > self.max_retry_after => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+
+        let backoff = if self.jitter {
+            backoff * rand::thread_rng().gen_range(0.5..=1.0)
+        } else {
+            backoff
+        };
+        Duration::from_secs_f64(backoff.max(0_f64))
+    }
+}
+
+// Sends the request built by `build` through `cassette`, retrying according to `policy`. Returns the
+// successful response together with the number of attempts it took.
+pub async fn send_with_retry<F>(policy: &RetryPolicy, cassette: Option<&Cassette>, build: F) -> Result<(reqwest::Response, u32), OGptError>
+where F: Fn() -> Result<reqwest::RequestBuilder, OGptError> {
+    let mut attempt = 1;
+    loop {
+        let result = match cassette::execute(cassette, build()?).await {
+            Ok(response) => check_status(response).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(response) => return Ok((response, attempt)),
+            Err(err) => match policy.next_delay(attempt, &err) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                },
+                None => return Err(err.with_attempts(attempt)),
+            },
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, pin::Pin, sync::Arc};
+
+use futures_util::{Stream, StreamExt, stream};
+
+use crate::{model::{api_error::ApiErrorResponse, chat_completions::ChatCompletionsChunk}, error::OGptError, limiter::{RateLimiter, RatePermit}};
+
+const DONE_MARKER: &str = "[DONE]";
+
+pub type ChatCompletionsStream = Pin<Box<dyn Stream<Item = Result<ChatCompletionsChunk, OGptError>> + Send>>;
+
+pub(crate) type SseStream = Pin<Box<dyn Stream<Item = Result<String, OGptError>> + Send>>;
+
+struct SseState<S> {
+    body: S,
+    buffer: Vec<u8>,
+    events: VecDeque<String>,
+    done: bool,
+}
+
+// Parses a server-sent-events body into chat completion chunks, stopping at the `[DONE]` marker.
+pub fn chat_completions_stream(response: reqwest::Response) -> ChatCompletionsStream {
+    let chunks = stream::unfold((sse_stream(response), false), |(mut events, done)| async move {
+        if done {
+            return None;
+        }
+
+        match events.next().await? {
+            Ok(data) if data == DONE_MARKER => None,
+            Ok(data) => {
+                let chunk = parse_chunk(&data);
+                let done = chunk.is_err();
+                Some((chunk, (events, done)))
+            },
+            Err(err) => Some((Err(err), (events, true))),
+        }
+    });
+
+    Box::pin(chunks)
+}
+
+// Settles the rate limiter reservation of a stream with the usage in its final chunk. Streams that end
+// without usage keep their estimate.
+pub(crate) fn reconcile_usage(chunks: ChatCompletionsStream, rate_limiter: Arc<RateLimiter>, permit: RatePermit) -> ChatCompletionsStream {
+    let mut permit = Some(permit);
+    Box::pin(chunks.inspect(move |chunk| {
+        if let Ok(ChatCompletionsChunk { usage: Some(usage), .. }) = chunk {
+            if let Some(permit) = permit.take() {
+                rate_limiter.reconcile(permit, usage.total_tokens);
+            }
+        }
+    }))
+}
+
+// Splits a server-sent-events body into the data of its events, ending after the first error.
+pub(crate) fn sse_stream(response: reqwest::Response) -> SseStream {
+    let state = SseState {
+        body: response.bytes_stream(),
+        buffer: Vec::new(),
+        events: VecDeque::new(),
+        done: false,
+    };
+
+    let events = stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(data) = state.events.pop_front() {
+                return Some((Ok(data), state));
+            }
+
+            if state.done {
+                return None;
+            }
+
+            match state.body.next().await {
+                Some(Ok(bytes)) => {
+                    state.buffer.extend_from_slice(&bytes);
+                    drain_events(&mut state.buffer, &mut state.events);
+                },
+                Some(Err(err)) => {
+                    state.done = true;
+                    return Some((Err(OGptError::from(err)), state));
+                },
+                None => {
+                    state.done = true;
+                    state.buffer.extend_from_slice(b"\n\n");
+                    drain_events(&mut state.buffer, &mut state.events);
+                },
+            }
+        }
+    });
+
+    Box::pin(events)
+}
+
+// Errors that happen after the response has started arrive as an `{"error": ...}` event instead of a chunk.
+fn parse_chunk(data: &str) -> Result<ChatCompletionsChunk, OGptError> {
+    match serde_json::from_str::<ChatCompletionsChunk>(data) {
+        Ok(chunk) => Ok(chunk),
+        Err(err) => match serde_json::from_str::<ApiErrorResponse>(data) {
+            Ok(response) => Err(OGptError::Api {
+                status: 200,
+                code: response.error.code_string(),
+                error_type: response.error.error_type,
+                message: response.error.message,
+                retry_after: None,
+            }),
+            Err(_) => Err(OGptError::from(err)),
+        },
+    }
+}
+
+fn drain_events(buffer: &mut Vec<u8>, events: &mut VecDeque<String>) {
+    while let Some(end) = find_event_end(buffer) {
+        let event: Vec<u8> = buffer.drain(..end).collect();
+        let event = String::from_utf8_lossy(&event);
+        let data = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| data.strip_prefix(' ').unwrap_or(data))
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        if !data.is_empty() {
+            events.push_back(data);
+        }
+    }
+}
+
+// Returns the index just past the blank line terminating the first complete event, if any.
+fn find_event_end(buffer: &[u8]) -> Option<usize> {
+    let mut newlines = 0;
+    for (i, byte) in buffer.iter().enumerate() {
+        match byte {
+            b'\n' => {
+                newlines += 1;
+                if newlines == 2 {
+                    return Some(i + 1);
+                }
+            },
+            b'\r' => {},
+            _ => newlines = 0,
+        }
+    }
+    None
+}
@@ -1,9 +1,70 @@
-use std::{error, fmt};
+use std::{error, fmt, time::Duration};
 
 #[derive(Debug)]
 pub enum OGptError {
     Reqwest(reqwest::Error),
-    SerdeJsonError(serde_json::Error)
+    SerdeJsonError(serde_json::Error),
+    InvalidConfig(String),
+    InvalidRequest(String),
+    InvalidResponse(String),
+    Cassette(String),
+    Api {
+        status: u16,
+        error_type: Option<String>,
+        code: Option<String>,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    RetriesExhausted {
+        attempts: u32,
+        last_error: Box<OGptError>,
+    },
+    BudgetExhausted {
+        limit: String,
+        retry_after: Duration,
+    },
+    ToolLoopLimit {
+        iterations: usize,
+    },
+}
+
+impl OGptError {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            OGptError::Api { status, .. } => Some(*status),
+            OGptError::Reqwest(err) => err.status().map(|status| status.as_u16()),
+            OGptError::RetriesExhausted { last_error, .. } => last_error.status(),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OGptError::Api { retry_after, .. } => *retry_after,
+            OGptError::RetriesExhausted { last_error, .. } => last_error.retry_after(),
+            OGptError::BudgetExhausted { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        match self {
+            OGptError::RetriesExhausted { attempts, .. } => *attempts,
+            _ => 1,
+        }
+    }
+
+    pub fn with_attempts(self, attempts: u32) -> Self {
+        if attempts > 1 {
+            OGptError::RetriesExhausted { attempts, last_error: Box::new(self) }
+        } else {
+            self
+        }
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.status() == Some(429)
+    }
 }
 
 impl fmt::Display for OGptError {
@@ -11,6 +72,27 @@ impl fmt::Display for OGptError {
         match self {
             OGptError::Reqwest(err) => write!(f, "Reqwest error: {}", err),
             OGptError::SerdeJsonError(err) => write!(f, "Serde json error: {}", err),
+            OGptError::InvalidConfig(err) => write!(f, "Invalid client config: {}", err),
+            OGptError::InvalidRequest(err) => write!(f, "Invalid request: {}", err),
+            OGptError::InvalidResponse(err) => write!(f, "Invalid response: {}", err),
+            OGptError::Cassette(err) => write!(f, "Cassette error: {}", err),
+            OGptError::Api { status, code, message, retry_after, .. } => {
+                match status {
+                    401 => write!(f, "Authentication failed: {}", message)?,
+                    429 => write!(f, "Rate limited: {}", message)?,
+                    _ => write!(f, "API error {}: {}", status, message)?,
+                }
+                if let Some(code) = code {
+                    write!(f, " ({})", code)?;
+                }
+                if let Some(retry_after) = retry_after {
+                    write!(f, " - try again in {}s", retry_after.as_secs_f64().ceil())?;
+                }
+                Ok(())
+            },
+            OGptError::RetriesExhausted { attempts, last_error } => write!(f, "{} (gave up after {} attempts)", last_error, attempts),
+            OGptError::BudgetExhausted { limit, retry_after } => write!(f, "Request budget exhausted ({}) - try again in {}s", limit, retry_after.as_secs_f64().ceil()),
+            OGptError::ToolLoopLimit { iterations } => write!(f, "Model was still calling tools after {} rounds", iterations),
         }
     }
 }
@@ -32,7 +114,15 @@ impl error::Error for OGptError {
     fn cause(&self) -> Option<&dyn error::Error> {
         match self {
             OGptError::Reqwest(err) => Some(err),
-            OGptError::SerdeJsonError(err) => Some(err)
+            OGptError::SerdeJsonError(err) => Some(err),
+            OGptError::InvalidConfig(_) => None,
+            OGptError::InvalidRequest(_) => None,
+            OGptError::InvalidResponse(_) => None,
+            OGptError::Cassette(_) => None,
+            OGptError::Api { .. } => None,
+            OGptError::RetriesExhausted { last_error, .. } => Some(last_error.as_ref()),
+            OGptError::BudgetExhausted { .. } => None,
+            OGptError::ToolLoopLimit { .. } => None,
         }
     }
 
@@ -40,6 +130,14 @@ impl error::Error for OGptError {
         match self {
             OGptError::Reqwest(err) => err.source(),
             OGptError::SerdeJsonError(err) => err.source(),
+            OGptError::InvalidConfig(_) => None,
+            OGptError::InvalidRequest(_) => None,
+            OGptError::InvalidResponse(_) => None,
+            OGptError::Cassette(_) => None,
+            OGptError::Api { .. } => None,
+            OGptError::RetriesExhausted { last_error, .. } => Some(last_error.as_ref()),
+            OGptError::BudgetExhausted { .. } => None,
+            OGptError::ToolLoopLimit { .. } => None,
         }
     }
-}
\ No newline at end of file
+}
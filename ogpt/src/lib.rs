@@ -1,4 +1,13 @@
 pub mod model;
+pub mod backend;
 pub mod client;
 pub mod error;
+pub mod limiter;
+pub mod model_info;
+pub mod pricing;
+pub mod structured;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tokenizer;
+pub mod tools;
 pub mod utils;
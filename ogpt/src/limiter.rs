@@ -0,0 +1,187 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use crate::{model::{chat_completions, embeddings}, error::OGptError, tokenizer};
+
+// Completion size assumed for requests that don't set `max_tokens`.
+pub const DEFAULT_COMPLETION_ESTIMATE: u64 = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimits {
+    // A limit of None or 0 disables it.
+    pub requests_per_minute: Option<u64>,
+    pub tokens_per_minute: Option<u64>,
+    // How long a call may queue for budget before it is rejected. Zero rejects immediately.
+    pub max_wait: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            max_wait: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+}
+
+impl TokenBucket {
+    // `limit` must not be 0, an empty bucket that never refills would wait forever.
+    fn per_minute(limit: u64) -> TokenBucket {
+        TokenBucket {
+            capacity: limit as f64,
+            available: limit as f64,
+            refill_per_sec: limit as f64 / 60_f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.available = (self.available + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+    }
+
+    // Time until the bucket is back out of debt.
+    fn deficit_wait(&self) -> Duration {
+        if self.available >= 0_f64 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / self.refill_per_sec)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+    last_refill: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatePermit {
+    estimated_tokens: u64,
+}
+
+impl RatePermit {
+    pub fn estimated_tokens(&self) -> u64 {
+        self.estimated_tokens
+    }
+}
+
+// Token buckets for requests and tokens per minute. Callers reserve their estimated cost up front and
+// wait until the buckets are back out of debt, so queued calls are served in arrival order.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            buckets: Mutex::new(Buckets {
+                requests: limits.requests_per_minute.filter(|&limit| limit > 0).map(TokenBucket::per_minute),
+                tokens: limits.tokens_per_minute.filter(|&limit| limit > 0).map(TokenBucket::per_minute),
+                last_refill: Instant::now(),
+            }),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    pub async fn acquire(&self, estimated_tokens: u64) -> Result<RatePermit, OGptError> {
+        let (permit, wait) = self.reserve(estimated_tokens)?;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(permit)
+    }
+
+    pub fn acquire_blocking(&self, estimated_tokens: u64) -> Result<RatePermit, OGptError> {
+        let (permit, wait) = self.reserve(estimated_tokens)?;
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        Ok(permit)
+    }
+
+    // Corrects the token bucket once the real cost of a request is known.
+    pub fn reconcile(&self, permit: RatePermit, actual_tokens: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(tokens) = buckets.tokens.as_mut() {
+            let difference = actual_tokens as f64 - permit.estimated_tokens as f64;
+            tokens.available = (tokens.available - difference).min(tokens.capacity);
+        }
+    }
+
+    fn reserve(&self, estimated_tokens: u64) -> Result<(RatePermit, Duration), OGptError> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(buckets.last_refill);
+        buckets.last_refill = now;
+
+        // A single request larger than the whole budget could never be admitted, so cap its reservation.
+        let estimated_tokens = match &buckets.tokens {
+            Some(tokens) => estimated_tokens.min(tokens.capacity as u64),
+            None => estimated_tokens,
+        };
+
+        let mut wait = Duration::ZERO;
+        let mut exhausted = None;
+        if let Some(requests) = buckets.requests.as_mut() {
+            requests.refill(elapsed);
+            requests.available -= 1_f64;
+            if requests.deficit_wait() > wait {
+                wait = requests.deficit_wait();
+                exhausted = Some("requests per minute");
+            }
+        }
+        if let Some(tokens) = buckets.tokens.as_mut() {
+            tokens.refill(elapsed);
+            tokens.available -= estimated_tokens as f64;
+            if tokens.deficit_wait() > wait {
+                wait = tokens.deficit_wait();
+                exhausted = Some("tokens per minute");
+            }
+        }
+
+        if wait > self.limits.max_wait {
+            if let Some(requests) = buckets.requests.as_mut() {
+                requests.available += 1_f64;
+            }
+            if let Some(tokens) = buckets.tokens.as_mut() {
+                tokens.available += estimated_tokens as f64;
+            }
+            return Err(OGptError::BudgetExhausted {
+                limit: exhausted.unwrap_or_default().to_owned(),
+                retry_after: wait,
+            });
+        }
+
+        Ok((RatePermit { estimated_tokens }, wait))
+    }
+}
+
+// Cost of a request before it is sent: the prompt tokens plus the completion allowance.
+pub fn estimate_request_tokens(request: &chat_completions::ChatCompletionsRequest) -> u64 {
+    let prompt_tokens = tokenizer::count_tokens(&request.model, &request.messages) as u64;
+    let completion_tokens = request.max_tokens.unwrap_or(DEFAULT_COMPLETION_ESTIMATE) * request.n.unwrap_or(1);
+    prompt_tokens + completion_tokens
+}
+
+pub fn estimate_embeddings_tokens(request: &embeddings::EmbeddingsRequest) -> u64 {
+    request
+        .input
+        .texts()
+        .into_iter()
+        .map(|text| tokenizer::count_text_tokens(&request.model, text) as u64)
+        .sum()
+}
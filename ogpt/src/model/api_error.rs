@@ -0,0 +1,26 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiErrorResponse {
+    pub error: ApiErrorBody,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiErrorBody {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: Option<String>,
+    pub param: Option<String>,
+    pub code: Option<serde_json::Value>,
+}
+
+impl ApiErrorBody {
+    // `code` is a string for most errors but some OpenAI-compatible servers send numbers.
+    pub fn code_string(&self) -> Option<String> {
+        match &self.code {
+            Some(serde_json::Value::String(code)) => Some(code.to_owned()),
+            Some(serde_json::Value::Null) | None => None,
+            Some(code) => Some(code.to_string()),
+        }
+    }
+}
\ No newline at end of file
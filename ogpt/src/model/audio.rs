@@ -0,0 +1,103 @@
+use serde::{Serialize, Deserialize};
+
+// A transcription or translation request. The audio is sent as a multipart upload rather than JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioRequest {
+    pub file: Vec<u8>,
+    // Used by the API to detect the audio format, so it needs the right extension, e.g. "voice-message.ogg".
+    pub filename: String,
+    pub model: String,
+    // ISO-639-1 language of the audio. Only used by transcriptions, translations are always into English.
+    pub language: Option<String>,
+    // Text to guide the style or continue a previous segment.
+    pub prompt: Option<String>,
+    pub response_format: Option<AudioResponseFormat>,
+    pub temperature: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioResponseFormat {
+    Json,
+    Text,
+    Srt,
+    VerboseJson,
+    Vtt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AudioResponse {
+    pub text: String,
+    // Only returned with the verbose_json format.
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    // Number of HTTP attempts the client needed to get this response.
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+impl AudioRequest {
+    pub fn new(model: String, filename: String, file: Vec<u8>) -> Self {
+        AudioRequest {
+            file,
+            filename,
+            model,
+            language: None,
+            prompt: None,
+            response_format: None,
+            temperature: None,
+        }
+    }
+
+    pub fn language(mut self, language: String) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    pub fn prompt(mut self, prompt: String) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    pub fn response_format(mut self, response_format: AudioResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    // The text, srt and vtt formats return the transcript as a plain body instead of JSON.
+    pub fn returns_json(&self) -> bool {
+        matches!(self.response_format, None | Some(AudioResponseFormat::Json) | Some(AudioResponseFormat::VerboseJson))
+    }
+
+    pub fn response_format_str(&self) -> Option<&'static str> {
+        self.response_format.map(|format| match format {
+            AudioResponseFormat::Json => "json",
+            AudioResponseFormat::Text => "text",
+            AudioResponseFormat::Srt => "srt",
+            AudioResponseFormat::VerboseJson => "verbose_json",
+            AudioResponseFormat::Vtt => "vtt",
+        })
+    }
+
+    // Content type of the upload, from the file extension.
+    pub fn content_type(&self) -> &'static str {
+        let extension = self.filename.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("ogg" | "oga" | "opus") => "audio/ogg",
+            Some("mp3" | "mpga" | "mpeg") => "audio/mpeg",
+            Some("m4a") => "audio/mp4",
+            Some("mp4") => "video/mp4",
+            Some("wav") => "audio/wav",
+            Some("webm") => "audio/webm",
+            Some("flac") => "audio/flac",
+            _ => "application/octet-stream",
+        }
+    }
+}
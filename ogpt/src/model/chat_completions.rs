@@ -1,17 +1,102 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use base64::Engine;
 use serde::{Serialize, Deserialize};
 
+use crate::error::OGptError;
+
+const MAX_STOP_SEQUENCES: usize = 4;
+const MAX_TOP_LOGPROBS: u32 = 20;
+const MAX_SCHEMA_NAME_LENGTH: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ChatCompletionsRequest {
     pub model: String,
     pub messages: Vec<Message>,
+    // 0 to 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f64>,
+    // 0 to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    // Only allowed together with `stream`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u64>,
+    // Up to 4 sequences where generation stops.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    // -2 to 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    // -2 to 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    // Bias from -100 to 100 added to the logits of the given token ids.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<BTreeMap<u32, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    // Stable end-user id the API uses to detect abuse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    // 0 to 20, requires `logprobs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    // Only allowed together with `tools`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Stop {
+    Single(String),
+    Multiple(Vec<String>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamOptions {
+    // Sends the usage of the whole request in a final chunk without choices.
+    pub include_usage: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    // Any valid JSON object. The messages must ask for JSON too or the model may never stop.
+    JsonObject,
+    // JSON matching a schema.
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonSchema {
+    // Up to 64 letters, digits, underscores and dashes.
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: serde_json::Value,
+    // Whether the output must follow the schema exactly, which only supports a subset of JSON schema.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ChatCompletionsResponse {
     pub id: String,
     pub object: String,
@@ -19,6 +104,12 @@ pub struct ChatCompletionsResponse {
     pub model: String,
     pub usage: Usage,
     pub choices: Vec<Choice>,
+    // Identifies the backend configuration, responses with the same seed are only reproducible while it is unchanged.
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
+    // Number of HTTP attempts the client needed to get this response.
+    #[serde(skip)]
+    pub attempts: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -26,19 +117,135 @@ pub struct Usage {
     pub prompt_tokens: u64,
     pub completion_tokens: u64,
     pub total_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct PromptTokensDetails {
+    // Prompt tokens read from the prompt cache, which are billed at a discount.
+    #[serde(default)]
+    pub cached_tokens: u64,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            prompt_tokens_details: None,
+        }
+    }
+
+    pub fn cached_tokens(mut self, cached_tokens: u64) -> Self {
+        self.prompt_tokens_details = Some(PromptTokensDetails { cached_tokens });
+        self
+    }
+
+    pub fn cached_prompt_tokens(&self) -> u64 {
+        self.prompt_tokens_details.as_ref().map_or(0, |details| details.cached_tokens)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Choice {
     pub message: Message,
     pub finish_reason: String,
     pub index: u64,
+    // Only returned if the request asked for logprobs.
+    #[serde(default)]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChoiceLogprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+    // The most likely tokens at this position, as many as `top_logprobs` asked for.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatCompletionsChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    // Only in the final chunk, and only if the request asked for it in `stream_options`.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChunkChoice {
+    pub delta: Delta,
+    pub finish_reason: Option<String>,
+    pub index: u64,
+    // The logprobs of the tokens in this delta, if the request asked for them.
+    #[serde(default)]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Delta {
+    pub role: Option<Role>,
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
+// Fragment of a tool call in a streamed delta. `index` identifies the call the fragment belongs to,
+// `id` and `function.name` only come with the first fragment and the arguments arrive in pieces.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ToolCallDelta {
+    pub index: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub tool_type: Option<ToolType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+// Assistant messages that only call tools have no content, so it is nullable.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Message {
     pub role: Role,
-    pub content: String
+    #[serde(default)]
+    pub content: Option<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -46,7 +253,268 @@ pub struct Message {
 pub enum Role {
     Assistant,
     System,
-    User
+    User,
+    Tool,
+    // Legacy role of results for the deprecated `functions` API.
+    Function,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Assistant => "assistant",
+            Role::System => "system",
+            Role::User => "user",
+            Role::Tool => "tool",
+            Role::Function => "function",
+        }
+    }
+}
+
+// Either plain text or a list of parts mixing text and images.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageUrl {
+    // An http(s) URL or a `data:` URL with base64 encoded image data.
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ImageDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    Auto,
+    Low,
+    High,
+}
+
+impl Content {
+    // The text of the content, with the text parts joined by newlines.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Content::Text(text) => Cow::Borrowed(text),
+            Content::Parts(parts) => {
+                let texts: Vec<&str> = parts.iter().filter_map(ContentPart::as_text).collect();
+                match texts.as_slice() {
+                    [text] => Cow::Borrowed(text),
+                    texts => Cow::Owned(texts.join("\n")),
+                }
+            },
+        }
+    }
+
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        match self {
+            Content::Text(_) => vec![],
+            Content::Parts(parts) => parts.iter().filter_map(ContentPart::as_image_url).collect(),
+        }
+    }
+
+    // The content as a single string, if it has no images and at most one text part.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Content::Text(text) => Some(text),
+            Content::Parts(parts) => match parts.as_slice() {
+                [] => Some(""),
+                [ContentPart::Text { text }] => Some(text),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Content::Text(text)
+    }
+}
+
+impl ContentPart {
+    pub fn text(text: String) -> Self {
+        ContentPart::Text { text }
+    }
+
+    pub fn image_url(url: String, detail: Option<ImageDetail>) -> Self {
+        ContentPart::ImageUrl { image_url: ImageUrl { url, detail } }
+    }
+
+    // Embeds image bytes as a base64 `data:` URL, for images the API can't fetch itself.
+    pub fn image_base64(mime_type: &str, data: &[u8], detail: Option<ImageDetail>) -> Self {
+        let url = format!("data:{};base64,{}", mime_type, base64::engine::general_purpose::STANDARD.encode(data));
+        ContentPart::image_url(url, detail)
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ContentPart::Text { text } => Some(text),
+            ContentPart::ImageUrl { .. } => None,
+        }
+    }
+
+    pub fn as_image_url(&self) -> Option<&ImageUrl> {
+        match self {
+            ContentPart::Text { .. } => None,
+            ContentPart::ImageUrl { image_url } => Some(image_url),
+        }
+    }
+}
+
+impl Message {
+    pub fn new(role: Role, content: String) -> Self {
+        Message::with_content(role, Content::Text(content))
+    }
+
+    pub fn with_parts(role: Role, parts: Vec<ContentPart>) -> Self {
+        Message::with_content(role, Content::Parts(parts))
+    }
+
+    pub fn with_content(role: Role, content: Content) -> Self {
+        Message {
+            role,
+            content: Some(content),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: String) -> Self {
+        Message::new(Role::System, content)
+    }
+
+    pub fn user(content: String) -> Self {
+        Message::new(Role::User, content)
+    }
+
+    pub fn assistant(content: String) -> Self {
+        Message::new(Role::Assistant, content)
+    }
+
+    // Result of the tool call `tool_call_id`, sent back to the model.
+    pub fn tool(tool_call_id: String, content: String) -> Self {
+        Message {
+            tool_call_id: Some(tool_call_id),
+            ..Message::new(Role::Tool, content)
+        }
+    }
+
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn text(&self) -> Cow<'_, str> {
+        match &self.content {
+            Some(content) => content.text(),
+            None => Cow::Borrowed(""),
+        }
+    }
+
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        self.content.as_ref().map(Content::images).unwrap_or_default()
+    }
+
+    pub fn has_tool_calls(&self) -> bool {
+        self.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolType {
+    #[default]
+    Function,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionDefinition,
+}
+
+impl Tool {
+    pub fn function(name: String, description: Option<String>, parameters: serde_json::Value) -> Self {
+        Tool {
+            tool_type: ToolType::Function,
+            function: FunctionDefinition { name, description, parameters },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // JSON schema of the arguments object.
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCall {
+    pub name: String,
+    // JSON encoded arguments as generated by the model, which may not be valid.
+    pub arguments: String,
+}
+
+// Serialized either as "none" / "auto" / "required" or as a named function to force.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(ToolChoiceMode),
+    Named(NamedToolChoice),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChoiceMode {
+    None,
+    Auto,
+    Required,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NamedToolChoice {
+    #[serde(rename = "type")]
+    pub tool_type: ToolType,
+    pub function: NamedFunction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NamedFunction {
+    pub name: String,
+}
+
+impl ToolChoice {
+    pub fn function(name: String) -> Self {
+        ToolChoice::Named(NamedToolChoice {
+            tool_type: ToolType::Function,
+            function: NamedFunction { name },
+        })
+    }
 }
 
 impl ChatCompletionsRequest {
@@ -58,35 +526,50 @@ impl ChatCompletionsRequest {
             top_p: None,
             n: None,
             stream: None,
-            max_tokens: None
+            stream_options: None,
+            max_tokens: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            seed: None,
+            user: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
         }
     }
 
     pub fn default(model: String, messages: Vec<Message>) -> Self {
         ChatCompletionsRequest {
-            model,
-            messages,
             temperature: Some(1_f64),
-            top_p: None,
             n: Some(1_u64),
             stream: Some(false),
-            max_tokens: None
+            ..ChatCompletionsRequest::new(model, messages)
         }
     }
 
-    pub fn temperature(mut self, temperature: f64) -> Self {
+    pub fn temperature(mut self, temperature: f64) -> Result<Self, OGptError> {
+        check_range("temperature", temperature, 0.0, 2.0)?;
         self.temperature = Some(temperature);
-        self
+        Ok(self)
     }
 
-    pub fn top_p(mut self, top_p: f64) -> Self {
+    pub fn top_p(mut self, top_p: f64) -> Result<Self, OGptError> {
+        check_range("top_p", top_p, 0.0, 1.0)?;
         self.top_p = Some(top_p);
-        self
+        Ok(self)
     }
 
-    pub fn n(mut self, n: u64) -> Self {
+    pub fn n(mut self, n: u64) -> Result<Self, OGptError> {
+        if n == 0 {
+            return Err(invalid("n must be at least 1"));
+        }
         self.n = Some(n);
-        self
+        Ok(self)
     }
 
     pub fn stream(mut self, stream: bool) -> Self {
@@ -94,8 +577,197 @@ impl ChatCompletionsRequest {
         self
     }
 
-    pub fn max_tokens(mut self, max_tokens: u64) -> Self {
+    pub fn stream_options(mut self, stream_options: StreamOptions) -> Self {
+        self.stream_options = Some(stream_options);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u64) -> Result<Self, OGptError> {
+        if max_tokens == 0 {
+            return Err(invalid("max_tokens must be at least 1"));
+        }
         self.max_tokens = Some(max_tokens);
+        Ok(self)
+    }
+
+    pub fn stop(mut self, stop: Stop) -> Result<Self, OGptError> {
+        check_stop(&stop)?;
+        self.stop = Some(stop);
+        Ok(self)
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: f64) -> Result<Self, OGptError> {
+        check_range("presence_penalty", presence_penalty, -2.0, 2.0)?;
+        self.presence_penalty = Some(presence_penalty);
+        Ok(self)
+    }
+
+    pub fn frequency_penalty(mut self, frequency_penalty: f64) -> Result<Self, OGptError> {
+        check_range("frequency_penalty", frequency_penalty, -2.0, 2.0)?;
+        self.frequency_penalty = Some(frequency_penalty);
+        Ok(self)
+    }
+
+    pub fn logit_bias(mut self, logit_bias: BTreeMap<u32, i32>) -> Result<Self, OGptError> {
+        check_logit_bias(&logit_bias)?;
+        self.logit_bias = Some(logit_bias);
+        Ok(self)
+    }
+
+    pub fn seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn user(mut self, user: String) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Result<Self, OGptError> {
+        check_response_format(&response_format)?;
+        self.response_format = Some(response_format);
+        Ok(self)
+    }
+
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.logprobs = Some(logprobs);
+        self
+    }
+
+    // Also turns on `logprobs`, which top_logprobs requires.
+    pub fn top_logprobs(mut self, top_logprobs: u32) -> Result<Self, OGptError> {
+        if top_logprobs > MAX_TOP_LOGPROBS {
+            return Err(invalid(&format!("top_logprobs must be at most {}, got {}", MAX_TOP_LOGPROBS, top_logprobs)));
+        }
+        self.logprobs = Some(true);
+        self.top_logprobs = Some(top_logprobs);
+        Ok(self)
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+        self.tools = Some(tools);
         self
     }
+
+    pub fn tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    pub fn parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
+    // Checks everything the setters check, for fields that were set directly, and the constraints
+    // between fields. The clients call this before sending so mistakes don't cost a round trip.
+    pub fn validate(&self) -> Result<(), OGptError> {
+        if self.messages.is_empty() {
+            return Err(invalid("messages must not be empty"));
+        }
+        if let Some(temperature) = self.temperature {
+            check_range("temperature", temperature, 0.0, 2.0)?;
+        }
+        if let Some(top_p) = self.top_p {
+            check_range("top_p", top_p, 0.0, 1.0)?;
+        }
+        if self.n == Some(0) {
+            return Err(invalid("n must be at least 1"));
+        }
+        if self.max_tokens == Some(0) {
+            return Err(invalid("max_tokens must be at least 1"));
+        }
+        if let Some(stop) = &self.stop {
+            check_stop(stop)?;
+        }
+        if let Some(presence_penalty) = self.presence_penalty {
+            check_range("presence_penalty", presence_penalty, -2.0, 2.0)?;
+        }
+        if let Some(frequency_penalty) = self.frequency_penalty {
+            check_range("frequency_penalty", frequency_penalty, -2.0, 2.0)?;
+        }
+        if let Some(logit_bias) = &self.logit_bias {
+            check_logit_bias(logit_bias)?;
+        }
+        if let Some(response_format) = &self.response_format {
+            check_response_format(response_format)?;
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(invalid(&format!("top_logprobs must be at most {}, got {}", MAX_TOP_LOGPROBS, top_logprobs)));
+            }
+            if self.logprobs != Some(true) {
+                return Err(invalid("top_logprobs requires logprobs to be true"));
+            }
+        }
+        if self.parallel_tool_calls.is_some() && self.tools.as_ref().is_none_or(Vec::is_empty) {
+            return Err(invalid("parallel_tool_calls is only allowed together with tools"));
+        }
+        if self.stream_options.is_some() && self.stream != Some(true) {
+            return Err(invalid("stream_options is only allowed together with stream"));
+        }
+        Ok(())
+    }
+}
+
+impl Stop {
+    pub fn sequences(&self) -> Vec<&str> {
+        match self {
+            Stop::Single(sequence) => vec![sequence],
+            Stop::Multiple(sequences) => sequences.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl ResponseFormat {
+    pub fn json_schema(name: String, schema: serde_json::Value, strict: bool) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchema { name, description: None, schema, strict: Some(strict) },
+        }
+    }
+}
+
+fn invalid(message: &str) -> OGptError {
+    OGptError::InvalidRequest(message.to_owned())
+}
+
+fn check_range(param: &str, value: f64, min: f64, max: f64) -> Result<(), OGptError> {
+    // Written so NaN is out of range too.
+    if value >= min && value <= max {
+        Ok(())
+    } else {
+        Err(invalid(&format!("{} must be between {} and {}, got {}", param, min, max, value)))
+    }
+}
+
+fn check_stop(stop: &Stop) -> Result<(), OGptError> {
+    let sequences = stop.sequences();
+    if sequences.len() > MAX_STOP_SEQUENCES {
+        return Err(invalid(&format!("stop takes at most {} sequences, got {}", MAX_STOP_SEQUENCES, sequences.len())));
+    }
+    if sequences.iter().any(|sequence| sequence.is_empty()) {
+        return Err(invalid("stop sequences must not be empty"));
+    }
+    Ok(())
+}
+
+fn check_logit_bias(logit_bias: &BTreeMap<u32, i32>) -> Result<(), OGptError> {
+    match logit_bias.iter().find(|(_, bias)| !(-100..=100).contains(*bias)) {
+        Some((token, bias)) => Err(invalid(&format!("logit_bias must be between -100 and 100, got {} for token {}", bias, token))),
+        None => Ok(()),
+    }
+}
+
+fn check_response_format(response_format: &ResponseFormat) -> Result<(), OGptError> {
+    if let ResponseFormat::JsonSchema { json_schema } = response_format {
+        let name = &json_schema.name;
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_SCHEMA_NAME_LENGTH
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            return Err(invalid(&format!("json_schema name must be 1 to {} letters, digits, underscores or dashes, got \"{}\"", MAX_SCHEMA_NAME_LENGTH, name)));
+        }
+    }
+    Ok(())
 }
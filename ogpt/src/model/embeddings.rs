@@ -0,0 +1,140 @@
+use base64::Engine;
+use serde::{Serialize, Deserialize};
+
+use crate::error::OGptError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<EncodingFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    Float,
+    // Little-endian f32 values, base64 encoded. Much smaller responses for large batches.
+    Base64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmbeddingsResponse {
+    pub object: String,
+    pub data: Vec<Embedding>,
+    pub model: String,
+    pub usage: EmbeddingsUsage,
+    // Number of HTTP attempts the client needed to get this response.
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Embedding {
+    pub object: String,
+    pub index: u64,
+    pub embedding: EmbeddingVector,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl EmbeddingsRequest {
+    pub fn new(model: String, input: EmbeddingInput) -> Self {
+        EmbeddingsRequest {
+            model,
+            input,
+            dimensions: None,
+            encoding_format: None,
+            user: None,
+        }
+    }
+
+    pub fn dimensions(mut self, dimensions: u64) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn encoding_format(mut self, encoding_format: EncodingFormat) -> Self {
+        self.encoding_format = Some(encoding_format);
+        self
+    }
+
+    pub fn user(mut self, user: String) -> Self {
+        self.user = Some(user);
+        self
+    }
+}
+
+impl EmbeddingInput {
+    pub fn texts(&self) -> Vec<&str> {
+        match self {
+            EmbeddingInput::Text(text) => vec![text],
+            EmbeddingInput::Batch(texts) => texts.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl From<String> for EmbeddingInput {
+    fn from(text: String) -> Self {
+        EmbeddingInput::Text(text)
+    }
+}
+
+impl From<Vec<String>> for EmbeddingInput {
+    fn from(texts: Vec<String>) -> Self {
+        EmbeddingInput::Batch(texts)
+    }
+}
+
+impl EmbeddingVector {
+    // The embedding as floats, decoding it if it was requested as base64.
+    pub fn to_vec(&self) -> Result<Vec<f32>, OGptError> {
+        match self {
+            EmbeddingVector::Float(values) => Ok(values.clone()),
+            EmbeddingVector::Base64(data) => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .map_err(|err| OGptError::InvalidResponse(format!("invalid base64 embedding: {}", err)))?;
+                if bytes.len() % 4 != 0 {
+                    return Err(OGptError::InvalidResponse(format!("base64 embedding has {} bytes, not a multiple of 4", bytes.len())));
+                }
+                Ok(bytes
+                    .chunks_exact(4)
+                    .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect())
+            },
+        }
+    }
+}
+
+impl EmbeddingsResponse {
+    // All embeddings as floats, ordered like the input.
+    pub fn vectors(&self) -> Result<Vec<Vec<f32>>, OGptError> {
+        let mut data: Vec<&Embedding> = self.data.iter().collect();
+        data.sort_by_key(|embedding| embedding.index);
+        data.into_iter().map(|embedding| embedding.embedding.to_vec()).collect()
+    }
+}
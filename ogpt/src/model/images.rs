@@ -0,0 +1,108 @@
+use base64::Engine;
+use serde::{Serialize, Deserialize};
+
+use crate::error::OGptError;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImagesRequest {
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u64>,
+    // e.g. "1024x1024", the supported sizes depend on the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    // "standard" or "hd" for dall-e-3, "low", "medium" or "high" for gpt-image models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    // Not accepted by gpt-image models, which always return base64 data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ImageResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    Url,
+    B64Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImagesResponse {
+    pub created: u64,
+    pub data: Vec<ImageData>,
+    // Number of HTTP attempts the client needed to get this response.
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b64_json: Option<String>,
+    // The prompt the model actually used, dall-e-3 rewrites prompts before generating.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revised_prompt: Option<String>,
+}
+
+impl ImagesRequest {
+    pub fn new(prompt: String) -> Self {
+        ImagesRequest {
+            prompt,
+            model: None,
+            n: None,
+            size: None,
+            quality: None,
+            response_format: None,
+            user: None,
+        }
+    }
+
+    pub fn model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn n(mut self, n: u64) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    pub fn size(mut self, size: String) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn quality(mut self, quality: String) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn response_format(mut self, response_format: ImageResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn user(mut self, user: String) -> Self {
+        self.user = Some(user);
+        self
+    }
+}
+
+impl ImageData {
+    // The decoded image bytes, or None if the image was returned as a URL.
+    pub fn bytes(&self) -> Result<Option<Vec<u8>>, OGptError> {
+        match &self.b64_json {
+            Some(data) => base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map(Some)
+                .map_err(|err| OGptError::InvalidResponse(format!("invalid base64 image: {}", err))),
+            None => Ok(None),
+        }
+    }
+}
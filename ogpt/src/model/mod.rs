@@ -1,2 +1,8 @@
+pub mod api_error;
+pub mod audio;
 pub mod chat_completions;
-pub mod models;
\ No newline at end of file
+pub mod embeddings;
+pub mod images;
+pub mod models;
+pub mod moderations;
+pub mod speech;
\ No newline at end of file
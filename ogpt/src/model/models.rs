@@ -12,8 +12,12 @@ pub struct Data {
     pub object: String,
     pub created: u64,
     pub owned_by: String,
+    // No longer returned by the OpenAI API.
+    #[serde(default)]
     pub permission: Vec<Permission>,
+    #[serde(default)]
     pub root: String,
+    #[serde(default)]
     pub parent: Option<String>,
 }
 
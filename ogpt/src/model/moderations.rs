@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationsRequest {
+    pub input: ModerationInput,
+    // e.g. "omni-moderation-latest", the API picks its default model if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ModerationInput {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationsResponse {
+    pub id: String,
+    pub model: String,
+    // One result per input, in input order.
+    pub results: Vec<ModerationResult>,
+    // Number of HTTP attempts the client needed to get this response.
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+// Categories are keyed by their API names, e.g. "harassment/threatening" or "self-harm/intent", since
+// new ones are added over time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: HashMap<String, bool>,
+    pub category_scores: HashMap<String, f64>,
+}
+
+impl ModerationsRequest {
+    pub fn new(input: ModerationInput) -> Self {
+        ModerationsRequest {
+            input,
+            model: None,
+        }
+    }
+
+    pub fn model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+}
+
+impl From<String> for ModerationInput {
+    fn from(text: String) -> Self {
+        ModerationInput::Text(text)
+    }
+}
+
+impl From<Vec<String>> for ModerationInput {
+    fn from(texts: Vec<String>) -> Self {
+        ModerationInput::Batch(texts)
+    }
+}
+
+impl ModerationResult {
+    pub fn score(&self, category: &str) -> f64 {
+        self.category_scores.get(category).copied().unwrap_or(0.0)
+    }
+
+    // Names of the categories the API flagged, sorted.
+    pub fn flagged_categories(&self) -> Vec<&str> {
+        let mut categories: Vec<&str> = self.categories
+            .iter()
+            .filter(|(_, flagged)| **flagged)
+            .map(|(category, _)| category.as_str())
+            .collect();
+        categories.sort_unstable();
+        categories
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpeechRequest {
+    pub model: String,
+    // At most 4096 characters.
+    pub input: String,
+    // e.g. "alloy", "nova" or "onyx".
+    pub voice: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<SpeechFormat>,
+    // 0.25 to 4.0, defaults to 1.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    // Tone and style directions, not supported by tts-1 and tts-1-hd.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    // Raw 24kHz 16-bit signed little-endian mono samples.
+    Pcm,
+}
+
+impl SpeechRequest {
+    pub fn new(model: String, input: String, voice: String) -> Self {
+        SpeechRequest {
+            model,
+            input,
+            voice,
+            response_format: None,
+            speed: None,
+            instructions: None,
+        }
+    }
+
+    pub fn response_format(mut self, response_format: SpeechFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    pub fn instructions(mut self, instructions: String) -> Self {
+        self.instructions = Some(instructions);
+        self
+    }
+}
+
+impl SpeechFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpeechFormat::Mp3 => "mp3",
+            SpeechFormat::Opus => "opus",
+            SpeechFormat::Aac => "aac",
+            SpeechFormat::Flac => "flac",
+            SpeechFormat::Wav => "wav",
+            SpeechFormat::Pcm => "pcm",
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// Static facts about known chat models. Unknown models get conservative defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub context_window: usize,
+    pub max_output_tokens: usize,
+    // Whether messages may contain image content parts.
+    pub supports_vision: bool,
+}
+
+const DEFAULT_MODEL_INFO: ModelInfo = ModelInfo {
+    context_window: 4_096,
+    max_output_tokens: 4_096,
+    supports_vision: false,
+};
+
+// Ordered so that more specific prefixes come before the families they belong to.
+const KNOWN_MODELS: &[(&str, ModelInfo)] = &[
+    ("gpt-4o-mini", ModelInfo { context_window: 128_000, max_output_tokens: 16_384, supports_vision: true }),
+    ("gpt-4o", ModelInfo { context_window: 128_000, max_output_tokens: 16_384, supports_vision: true }),
+    ("chatgpt-4o", ModelInfo { context_window: 128_000, max_output_tokens: 16_384, supports_vision: true }),
+    ("gpt-4.1", ModelInfo { context_window: 1_047_576, max_output_tokens: 32_768, supports_vision: true }),
+    ("gpt-4-turbo", ModelInfo { context_window: 128_000, max_output_tokens: 4_096, supports_vision: true }),
+    ("gpt-4-1106", ModelInfo { context_window: 128_000, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-4-0125", ModelInfo { context_window: 128_000, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-4-vision", ModelInfo { context_window: 128_000, max_output_tokens: 4_096, supports_vision: true }),
+    ("gpt-4-32k", ModelInfo { context_window: 32_768, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-4", ModelInfo { context_window: 8_192, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-3.5-turbo-instruct", ModelInfo { context_window: 4_096, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-3.5-turbo-16k", ModelInfo { context_window: 16_385, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-3.5-turbo-0613", ModelInfo { context_window: 4_096, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-3.5-turbo-0301", ModelInfo { context_window: 4_096, max_output_tokens: 4_096, supports_vision: false }),
+    ("gpt-3.5-turbo", ModelInfo { context_window: 16_385, max_output_tokens: 4_096, supports_vision: false }),
+    ("o1-mini", ModelInfo { context_window: 128_000, max_output_tokens: 65_536, supports_vision: false }),
+    ("o1-preview", ModelInfo { context_window: 128_000, max_output_tokens: 32_768, supports_vision: false }),
+    ("o1", ModelInfo { context_window: 200_000, max_output_tokens: 100_000, supports_vision: true }),
+    ("o3-mini", ModelInfo { context_window: 200_000, max_output_tokens: 100_000, supports_vision: false }),
+    ("o3", ModelInfo { context_window: 200_000, max_output_tokens: 100_000, supports_vision: true }),
+    ("o4-mini", ModelInfo { context_window: 200_000, max_output_tokens: 100_000, supports_vision: true }),
+];
+
+impl ModelInfo {
+    pub fn for_model(model: &str) -> ModelInfo {
+        KNOWN_MODELS
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, info)| *info)
+            .unwrap_or(DEFAULT_MODEL_INFO)
+    }
+
+    pub fn is_known(model: &str) -> bool {
+        KNOWN_MODELS.iter().any(|(prefix, _)| model.starts_with(prefix))
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::model::chat_completions::Usage;
+
+const TOKENS_PER_PRICE_UNIT: f64 = 1_000_000.0;
+const CHARACTERS_PER_PRICE_UNIT: f64 = 1_000_000.0;
+const SECONDS_PER_MINUTE: f64 = 60.0;
+
+// List prices of a chat model in US dollars per million tokens.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    // Price of prompt tokens read from the prompt cache, the input price if the model has no discount.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input: Option<f64>,
+}
+
+// Ordered so that more specific prefixes come before the families they belong to.
+const KNOWN_PRICES: &[(&str, ModelPrice)] = &[
+    ("gpt-4o-mini", ModelPrice { input: 0.15, output: 0.60, cached_input: Some(0.075) }),
+    ("gpt-4o", ModelPrice { input: 2.50, output: 10.00, cached_input: Some(1.25) }),
+    ("chatgpt-4o", ModelPrice { input: 5.00, output: 15.00, cached_input: None }),
+    ("gpt-4.1-nano", ModelPrice { input: 0.10, output: 0.40, cached_input: Some(0.025) }),
+    ("gpt-4.1-mini", ModelPrice { input: 0.40, output: 1.60, cached_input: Some(0.10) }),
+    ("gpt-4.1", ModelPrice { input: 2.00, output: 8.00, cached_input: Some(0.50) }),
+    ("gpt-4-turbo", ModelPrice { input: 10.00, output: 30.00, cached_input: None }),
+    ("gpt-4-1106", ModelPrice { input: 10.00, output: 30.00, cached_input: None }),
+    ("gpt-4-0125", ModelPrice { input: 10.00, output: 30.00, cached_input: None }),
+    ("gpt-4-vision", ModelPrice { input: 10.00, output: 30.00, cached_input: None }),
+    ("gpt-4-32k", ModelPrice { input: 60.00, output: 120.00, cached_input: None }),
+    ("gpt-4", ModelPrice { input: 30.00, output: 60.00, cached_input: None }),
+    ("gpt-3.5-turbo-instruct", ModelPrice { input: 1.50, output: 2.00, cached_input: None }),
+    ("gpt-3.5-turbo-16k", ModelPrice { input: 3.00, output: 4.00, cached_input: None }),
+    ("gpt-3.5-turbo", ModelPrice { input: 0.50, output: 1.50, cached_input: None }),
+    ("o1-mini", ModelPrice { input: 1.10, output: 4.40, cached_input: Some(0.55) }),
+    ("o1-preview", ModelPrice { input: 15.00, output: 60.00, cached_input: Some(7.50) }),
+    ("o1", ModelPrice { input: 15.00, output: 60.00, cached_input: Some(7.50) }),
+    ("o3-mini", ModelPrice { input: 1.10, output: 4.40, cached_input: Some(0.55) }),
+    ("o3", ModelPrice { input: 2.00, output: 8.00, cached_input: Some(0.50) }),
+    ("o4-mini", ModelPrice { input: 1.10, output: 4.40, cached_input: Some(0.275) }),
+    ("claude-3-haiku", ModelPrice { input: 0.25, output: 1.25, cached_input: Some(0.03) }),
+    ("claude-3-5-haiku", ModelPrice { input: 0.80, output: 4.00, cached_input: Some(0.08) }),
+    ("claude-haiku-4-5", ModelPrice { input: 1.00, output: 5.00, cached_input: Some(0.10) }),
+    ("claude-3-5-sonnet", ModelPrice { input: 3.00, output: 15.00, cached_input: Some(0.30) }),
+    ("claude-3-7-sonnet", ModelPrice { input: 3.00, output: 15.00, cached_input: Some(0.30) }),
+    ("claude-sonnet-4", ModelPrice { input: 3.00, output: 15.00, cached_input: Some(0.30) }),
+    ("claude-opus-4-5", ModelPrice { input: 5.00, output: 25.00, cached_input: Some(0.50) }),
+    ("claude-3-opus", ModelPrice { input: 15.00, output: 75.00, cached_input: Some(1.50) }),
+    ("claude-opus-4", ModelPrice { input: 15.00, output: 75.00, cached_input: Some(1.50) }),
+    ("text-embedding-3-small", ModelPrice { input: 0.02, output: 0.0, cached_input: None }),
+    ("text-embedding-3-large", ModelPrice { input: 0.13, output: 0.0, cached_input: None }),
+    ("text-embedding-ada-002", ModelPrice { input: 0.10, output: 0.0, cached_input: None }),
+    // Moderation is free, priced so that its requests are counted without a missing price.
+    ("omni-moderation", ModelPrice { input: 0.0, output: 0.0, cached_input: None }),
+    ("text-moderation", ModelPrice { input: 0.0, output: 0.0, cached_input: None }),
+];
+
+// Prices per image in US dollars by model prefix, quality and size. dall-e-2 has a single quality.
+const IMAGE_PRICES: &[(&str, &str, &str, f64)] = &[
+    ("dall-e-3", "standard", "1024x1024", 0.040),
+    ("dall-e-3", "standard", "1024x1792", 0.080),
+    ("dall-e-3", "standard", "1792x1024", 0.080),
+    ("dall-e-3", "hd", "1024x1024", 0.080),
+    ("dall-e-3", "hd", "1024x1792", 0.120),
+    ("dall-e-3", "hd", "1792x1024", 0.120),
+    ("dall-e-2", "standard", "256x256", 0.016),
+    ("dall-e-2", "standard", "512x512", 0.018),
+    ("dall-e-2", "standard", "1024x1024", 0.020),
+    ("gpt-image-1", "low", "1024x1024", 0.011),
+    ("gpt-image-1", "low", "1024x1536", 0.016),
+    ("gpt-image-1", "low", "1536x1024", 0.016),
+    ("gpt-image-1", "medium", "1024x1024", 0.042),
+    ("gpt-image-1", "medium", "1024x1536", 0.063),
+    ("gpt-image-1", "medium", "1536x1024", 0.063),
+    ("gpt-image-1", "high", "1024x1024", 0.167),
+    ("gpt-image-1", "high", "1024x1536", 0.250),
+    ("gpt-image-1", "high", "1536x1024", 0.250),
+];
+
+// Prices of speech in US dollars per million input characters.
+const SPEECH_PRICES: &[(&str, f64)] = &[
+    ("tts-1-hd", 30.00),
+    ("tts-1", 15.00),
+];
+
+// Prices of transcription in US dollars per minute of audio.
+const TRANSCRIPTION_PRICES: &[(&str, f64)] = &[
+    ("whisper-1", 0.006),
+    ("gpt-4o-mini-transcribe", 0.003),
+    ("gpt-4o-transcribe", 0.006),
+];
+
+impl ModelPrice {
+    // None for models without a known price, e.g. local models.
+    pub fn for_model(model: &str) -> Option<ModelPrice> {
+        KNOWN_PRICES
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, price)| *price)
+    }
+
+    // Cost of `usage` in US dollars.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cached = usage.cached_prompt_tokens().min(usage.prompt_tokens);
+        let uncached = usage.prompt_tokens - cached;
+
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input.unwrap_or(self.input)
+            + usage.completion_tokens as f64 * self.output) / TOKENS_PER_PRICE_UNIT
+    }
+}
+
+// Cost of `n` images in US dollars. Without a quality the model's default is assumed, "standard" for dall-e
+// models and "high" for gpt-image models, which is what they bill when asked for "auto".
+pub fn image_cost(model: &str, size: &str, quality: Option<&str>, n: u64) -> Option<f64> {
+    let quality = match quality {
+        Some("auto") | None if model.starts_with("gpt-image") => "high",
+        Some(quality) => quality,
+        None => "standard",
+    };
+    let quality = if model.starts_with("dall-e-2") { "standard" } else { quality };
+    IMAGE_PRICES
+        .iter()
+        .find(|(prefix, price_quality, price_size, _)| model.starts_with(prefix) && *price_quality == quality && *price_size == size)
+        .map(|(_, _, _, price)| price * n as f64)
+}
+
+// Cost of speaking `characters` characters in US dollars.
+pub fn speech_cost(model: &str, characters: usize) -> Option<f64> {
+    SPEECH_PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, price)| characters as f64 * price / CHARACTERS_PER_PRICE_UNIT)
+}
+
+// Cost of transcribing `seconds` of audio in US dollars.
+pub fn transcription_cost(model: &str, seconds: f64) -> Option<f64> {
+    TRANSCRIPTION_PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, price)| seconds / SECONDS_PER_MINUTE * price)
+}
+
+// The known prices with overrides for negotiated rates, fine-tunes or models missing from the list.
+// Overrides are matched by prefix like the known prices. The longest matching prefix of either wins, so
+// overriding a family leaves the more specific known prices in it alone, and an override wins a tie.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PriceTable {
+    overrides: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new() -> PriceTable {
+        PriceTable::default()
+    }
+
+    pub fn with_overrides(overrides: HashMap<String, ModelPrice>) -> PriceTable {
+        PriceTable { overrides }
+    }
+
+    pub fn set(&mut self, model: String, price: ModelPrice) {
+        self.overrides.insert(model, price);
+    }
+
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        let overrides = self.overrides.iter().map(|(prefix, price)| (prefix.as_str(), price, true));
+        let known = KNOWN_PRICES.iter().map(|(prefix, price)| (*prefix, price, false));
+        overrides
+            .chain(known)
+            .filter(|(prefix, _, _)| model.starts_with(prefix))
+            .max_by_key(|(prefix, _, is_override)| (prefix.len(), *is_override))
+            .map(|(_, price, _)| *price)
+    }
+
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
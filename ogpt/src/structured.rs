@@ -0,0 +1,52 @@
+use serde::de::DeserializeOwned;
+
+use crate::{backend::ChatBackend, error::OGptError, model::chat_completions, utils};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructuredResponse<T> {
+    pub value: T,
+    // The response the value was parsed from.
+    pub response: chat_completions::ChatCompletionsResponse,
+    // Whether the first answer didn't parse and the model had to correct it.
+    pub retried: bool,
+}
+
+// Sends `request` asking for JSON matching `schema` and parses the first choice into `T`. If the answer
+// doesn't parse, the model is shown the error and asked once to correct it. Backends that can't enforce
+// a schema are given it in a system message instead.
+pub async fn chat_completion_json<T: DeserializeOwned>(backend: &dyn ChatBackend, mut request: chat_completions::ChatCompletionsRequest, schema: chat_completions::JsonSchema) -> Result<StructuredResponse<T>, OGptError> {
+    if !backend.capabilities(&request.model).json_schema {
+        request.messages.push(chat_completions::Message::system(format!(
+            "Reply with only a JSON value matching this JSON schema, without any other text:\n{}", schema.schema)));
+    }
+    let mut request = request.response_format(chat_completions::ResponseFormat::JsonSchema { json_schema: schema })?;
+
+    let response = backend.chat(&request).await?;
+    let content = utils::get_chat_message(&response, 0).unwrap_or_default().to_owned();
+    let err = match parse_json(&content) {
+        Ok(value) => return Ok(StructuredResponse { value, response, retried: false }),
+        Err(err) => err,
+    };
+
+    request.messages.push(chat_completions::Message::assistant(content));
+    request.messages.push(chat_completions::Message::user(format!(
+        "That is not valid JSON for the schema: {}. Reply with only the corrected JSON.", err)));
+
+    let response = backend.chat(&request).await?;
+    let content = utils::get_chat_message(&response, 0).unwrap_or_default();
+    match parse_json(content) {
+        Ok(value) => Ok(StructuredResponse { value, response, retried: true }),
+        Err(err) => Err(OGptError::InvalidResponse(format!("model returned invalid JSON twice: {}", err))),
+    }
+}
+
+// Parses a JSON answer, tolerating the markdown code fence some models wrap it in.
+pub fn parse_json<T: DeserializeOwned>(content: &str) -> Result<T, serde_json::Error> {
+    let content = content.trim();
+    let unfenced = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"));
+
+    serde_json::from_str(unfenced.unwrap_or(content).trim())
+}
@@ -0,0 +1,381 @@
+use std::{collections::VecDeque, io::{BufRead, BufReader, Write}, net::{SocketAddr, TcpListener, TcpStream}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::Duration};
+
+use async_trait::async_trait;
+use futures_util::stream;
+use serde_json::Value;
+
+use crate::{backend::{Capabilities, ChatBackend, MediaBackend}, client::ChatCompletionsStream, error::OGptError, model::{audio, chat_completions, images, speech}, tokenizer};
+
+// A request received by the mock server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_slice(&self.body).ok()
+    }
+}
+
+// A scripted response. The body is written chunk by chunk, so streams can be slowed down or cut short.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub chunks: Vec<Vec<u8>>,
+    pub chunk_delay: Duration,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            headers: vec![],
+            chunks: vec![],
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn json(status: u16, body: Value) -> MockResponse {
+        MockResponse::new(status)
+            .header("content-type", "application/json")
+            .chunk(body.to_string().into_bytes())
+    }
+
+    // An OpenAI style error response.
+    pub fn error(status: u16, error_type: &str, message: &str) -> MockResponse {
+        MockResponse::json(status, serde_json::json!({
+            "error": { "message": message, "type": error_type, "param": null, "code": null }
+        }))
+    }
+
+    // A server-sent events stream with one `data:` event per entry, e.g. chat completion chunks followed by `[DONE]`.
+    pub fn sse<S: AsRef<str>>(events: &[S]) -> MockResponse {
+        events.iter().fold(
+            MockResponse::new(200).header("content-type", "text/event-stream"),
+            |response, event| response.chunk(format!("data: {}\n\n", event.as_ref()).into_bytes()))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn chunk(mut self, chunk: Vec<u8>) -> Self {
+        self.chunks.push(chunk);
+        self
+    }
+
+    pub fn chunk_delay(mut self, chunk_delay: Duration) -> Self {
+        self.chunk_delay = chunk_delay;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: VecDeque<MockResponse>,
+    requests: Vec<MockRequest>,
+}
+
+// A tiny HTTP server on localhost for testing clients against error and streaming scenarios. Responses
+// are served in the order they were queued, one per connection; a request with nothing queued gets a 500.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    pub fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+        let addr = listener.local_addr().expect("Failed to get mock server address");
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (thread_state, thread_stopped) = (state.clone(), stopped.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = thread_state.clone();
+                    thread::spawn(move || handle(stream, &state));
+                }
+            }
+        });
+
+        MockServer { addr, state, stopped }
+    }
+
+    // Base url without a path, append the API prefix as needed, e.g. `/v1`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn enqueue(&self, response: MockResponse) -> &Self {
+        self.state.lock().unwrap().responses.push_back(response);
+        self
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn handle(stream: TcpStream, state: &Mutex<MockState>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(_) => return,
+    });
+    let request = match read_request(&mut reader) {
+        Some(request) => request,
+        None => return,
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request);
+        state.responses.pop_front()
+    };
+    let response = response.unwrap_or_else(|| MockResponse::error(500, "server_error", "No mock response queued"));
+    let _ = write_response(stream, &response);
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Option<MockRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let path = parts.next()?.to_owned();
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(MockRequest { method, path, headers, body })
+}
+
+// Writes the response without a content length and closes the connection, which ends the body. That
+// lets streams be written as they go and cut off by simply leaving out their final event.
+fn write_response(mut stream: TcpStream, response: &MockResponse) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\nconnection: close\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.flush()?;
+
+    for chunk in &response.chunks {
+        if !response.chunk_delay.is_zero() {
+            thread::sleep(response.chunk_delay);
+        }
+        stream.write_all(chunk)?;
+        stream.flush()?;
+    }
+    stream.shutdown(std::net::Shutdown::Both)
+}
+
+// A request received by the fake backend's media endpoints.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FakeMediaRequest {
+    Images(images::ImagesRequest),
+    Speech(speech::SpeechRequest),
+    Transcription(audio::AudioRequest),
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    replies: VecDeque<Result<String, OGptError>>,
+    chat_requests: Vec<chat_completions::ChatCompletionsRequest>,
+    media_requests: Vec<FakeMediaRequest>,
+    transcript: String,
+}
+
+// An in-memory backend for testing code written against `ChatBackend` and `MediaBackend` without a
+// server. Chat replies are served in the order they were queued, a request with nothing queued fails.
+// Usage is counted with the tokenizer, and streams end with a usage chunk like OpenAI's `include_usage`.
+#[derive(Debug, Default)]
+pub struct FakeBackend {
+    state: Mutex<FakeState>,
+}
+
+impl FakeBackend {
+    pub fn new() -> FakeBackend {
+        FakeBackend::default()
+    }
+
+    pub fn reply(&self, content: &str) -> &Self {
+        self.state.lock().unwrap().replies.push_back(Ok(content.to_owned()));
+        self
+    }
+
+    pub fn fail(&self, err: OGptError) -> &Self {
+        self.state.lock().unwrap().replies.push_back(Err(err));
+        self
+    }
+
+    // Text every transcription returns.
+    pub fn transcript(&self, text: &str) -> &Self {
+        self.state.lock().unwrap().transcript = text.to_owned();
+        self
+    }
+
+    pub fn chat_requests(&self) -> Vec<chat_completions::ChatCompletionsRequest> {
+        self.state.lock().unwrap().chat_requests.clone()
+    }
+
+    pub fn media_requests(&self) -> Vec<FakeMediaRequest> {
+        self.state.lock().unwrap().media_requests.clone()
+    }
+
+    fn next_reply(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<(String, chat_completions::Usage), OGptError> {
+        let mut state = self.state.lock().unwrap();
+        state.chat_requests.push(request.clone());
+        let content = state.replies
+            .pop_front()
+            .unwrap_or_else(|| Err(OGptError::InvalidResponse(String::from("No fake reply queued"))))?;
+        let usage = chat_completions::Usage::new(
+            tokenizer::count_tokens(&request.model, &request.messages) as u64,
+            tokenizer::count_text_tokens(&request.model, &content) as u64);
+        Ok((content, usage))
+    }
+}
+
+fn fake_chunk(model: &str, choices: Vec<chat_completions::ChunkChoice>, usage: Option<chat_completions::Usage>) -> chat_completions::ChatCompletionsChunk {
+    chat_completions::ChatCompletionsChunk {
+        id: String::from("chatcmpl-fake"),
+        object: String::from("chat.completion.chunk"),
+        created: 0,
+        model: model.to_owned(),
+        choices,
+        usage,
+    }
+}
+
+#[async_trait]
+impl ChatBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn capabilities(&self, _model: &str) -> Capabilities {
+        Capabilities {
+            streaming: true,
+            tools: true,
+            vision: true,
+            json_schema: true,
+        }
+    }
+
+    async fn chat(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<chat_completions::ChatCompletionsResponse, OGptError> {
+        let (content, usage) = self.next_reply(request)?;
+        Ok(chat_completions::ChatCompletionsResponse {
+            id: String::from("chatcmpl-fake"),
+            object: String::from("chat.completion"),
+            created: 0,
+            model: request.model.to_owned(),
+            usage,
+            choices: vec![chat_completions::Choice {
+                message: chat_completions::Message::assistant(content),
+                finish_reason: String::from("stop"),
+                index: 0,
+                logprobs: None,
+            }],
+            system_fingerprint: None,
+            attempts: 1,
+        })
+    }
+
+    // Streams the reply a word at a time.
+    async fn stream(&self, request: &chat_completions::ChatCompletionsRequest) -> Result<ChatCompletionsStream, OGptError> {
+        let (content, usage) = self.next_reply(request)?;
+        let choice = |content: Option<&str>, finish_reason: Option<&str>| chat_completions::ChunkChoice {
+            delta: chat_completions::Delta { content: content.map(str::to_owned), ..chat_completions::Delta::default() },
+            finish_reason: finish_reason.map(str::to_owned),
+            index: 0,
+            logprobs: None,
+        };
+
+        let mut chunks: Vec<chat_completions::ChatCompletionsChunk> = content
+            .split_inclusive(' ')
+            .map(|word| fake_chunk(&request.model, vec![choice(Some(word), None)], None))
+            .collect();
+        chunks.push(fake_chunk(&request.model, vec![choice(None, Some("stop"))], None));
+        chunks.push(fake_chunk(&request.model, vec![], Some(usage)));
+        Ok(Box::pin(stream::iter(chunks.into_iter().map(Ok))))
+    }
+
+    async fn models(&self) -> Result<Vec<String>, OGptError> {
+        Ok(vec![])
+    }
+}
+
+#[async_trait]
+impl MediaBackend for FakeBackend {
+    // Images come back as base64 data, one per requested image.
+    async fn images(&self, request: &images::ImagesRequest) -> Result<images::ImagesResponse, OGptError> {
+        self.state.lock().unwrap().media_requests.push(FakeMediaRequest::Images(request.clone()));
+        let image = images::ImageData { url: None, b64_json: Some(String::from("ZmFrZQ==")), revised_prompt: None };
+        Ok(images::ImagesResponse {
+            created: 0,
+            data: vec![image; request.n.unwrap_or(1) as usize],
+            attempts: 1,
+        })
+    }
+
+    // The "audio" is the input text.
+    async fn speech(&self, request: &speech::SpeechRequest) -> Result<Vec<u8>, OGptError> {
+        self.state.lock().unwrap().media_requests.push(FakeMediaRequest::Speech(request.clone()));
+        Ok(request.input.as_bytes().to_vec())
+    }
+
+    async fn transcription(&self, request: &audio::AudioRequest) -> Result<audio::AudioResponse, OGptError> {
+        let mut state = self.state.lock().unwrap();
+        state.media_requests.push(FakeMediaRequest::Transcription(request.clone()));
+        Ok(audio::AudioResponse {
+            text: state.transcript.to_owned(),
+            language: None,
+            duration: None,
+            attempts: 1,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use fancy_regex::Regex;
+
+// Byte-level BPE as used by tiktoken: text is split into pieces by `pattern`, and each piece is
+// merged from single bytes upwards, always merging the adjacent pair with the lowest rank first.
+pub struct CoreBpe {
+    encoder: HashMap<Vec<u8>, u32>,
+    decoder: HashMap<u32, Vec<u8>>,
+    pattern: Regex,
+}
+
+impl CoreBpe {
+    // `ranks` is a tiktoken file: one `<base64 token> <rank>` pair per line.
+    pub fn new(ranks: &str, pattern: &str) -> CoreBpe {
+        let mut encoder = HashMap::new();
+        for line in ranks.lines() {
+            let mut parts = line.split(' ');
+            let (Some(token), Some(rank)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let token = STANDARD.decode(token).expect("Invalid token in bundled rank file");
+            let rank = rank.parse::<u32>().expect("Invalid rank in bundled rank file");
+            encoder.insert(token, rank);
+        }
+
+        let decoder = encoder
+            .iter()
+            .map(|(token, rank)| (*rank, token.clone()))
+            .collect();
+
+        CoreBpe {
+            encoder,
+            decoder,
+            pattern: Regex::new(pattern).expect("Invalid bundled split pattern"),
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = vec![];
+        for piece in self.pattern.find_iter(text) {
+            // Only fails when the backtracking limit is exceeded, which the bundled patterns can't hit.
+            let Ok(piece) = piece else {
+                break;
+            };
+            let piece = piece.as_str().as_bytes();
+
+            match self.encoder.get(piece) {
+                Some(token) => tokens.push(*token),
+                None => tokens.extend(self.byte_pair_encode(piece)),
+            }
+        }
+        tokens
+    }
+
+    pub fn decode_bytes(&self, tokens: &[u32]) -> Vec<u8> {
+        tokens
+            .iter()
+            .filter_map(|token| self.decoder.get(token))
+            .flat_map(|bytes| bytes.iter().copied())
+            .collect()
+    }
+
+    fn rank(&self, bytes: &[u8]) -> u32 {
+        self.encoder.get(bytes).copied().unwrap_or(u32::MAX)
+    }
+
+    fn byte_pair_encode(&self, piece: &[u8]) -> Vec<u32> {
+        if piece.len() == 1 {
+            return vec![self.rank(piece)];
+        }
+
+        // Each part is (start offset, rank of merging it with the next part).
+        let mut parts: Vec<(usize, u32)> = (0..piece.len() - 1)
+            .map(|i| (i, self.rank(&piece[i..i + 2])))
+            .collect();
+        parts.push((piece.len() - 1, u32::MAX));
+        parts.push((piece.len(), u32::MAX));
+
+        let pair_rank = |parts: &Vec<(usize, u32)>, i: usize| {
+            if i + 3 < parts.len() {
+                self.rank(&piece[parts[i].0..parts[i + 3].0])
+            } else {
+                u32::MAX
+            }
+        };
+
+        loop {
+            let (index, rank) = parts[..parts.len() - 1]
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, rank))| *rank)
+                .map(|(index, (_, rank))| (index, *rank))
+                .unwrap();
+
+            if rank == u32::MAX {
+                break;
+            }
+
+            if index > 0 {
+                parts[index - 1].1 = pair_rank(&parts, index - 1);
+            }
+            parts[index].1 = pair_rank(&parts, index);
+            parts.remove(index + 1);
+        }
+
+        parts
+            .windows(2)
+            .map(|window| self.rank(&piece[window[0].0..window[1].0]))
+            .collect()
+    }
+}
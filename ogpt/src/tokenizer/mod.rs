@@ -0,0 +1,183 @@
+mod bpe;
+
+use std::sync::OnceLock;
+
+use crate::model::chat_completions;
+
+use bpe::CoreBpe;
+
+const CL100K_BASE_RANKS: &str = include_str!("../../assets/cl100k_base.tiktoken");
+const O200K_BASE_RANKS: &str = include_str!("../../assets/o200k_base.tiktoken");
+
+const CL100K_BASE_PATTERN: &str =
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const O200K_BASE_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n/]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+// Every reply is primed with `<|start|>assistant<|message|>`.
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Cl100kBase,
+    O200kBase,
+}
+
+impl Encoding {
+    pub fn for_model(model: &str) -> Encoding {
+        let o200k_prefixes = ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4", "chatgpt-4o"];
+        if o200k_prefixes.iter().any(|prefix| model.starts_with(prefix)) {
+            Encoding::O200kBase
+        } else {
+            Encoding::Cl100kBase
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::O200kBase => "o200k_base",
+        }
+    }
+}
+
+// The bundled rank tables are parsed on first use and kept for the lifetime of the process.
+pub struct Tokenizer {
+    encoding: Encoding,
+    bpe: CoreBpe,
+}
+
+impl Tokenizer {
+    pub fn get(encoding: Encoding) -> &'static Tokenizer {
+        static CL100K_BASE: OnceLock<Tokenizer> = OnceLock::new();
+        static O200K_BASE: OnceLock<Tokenizer> = OnceLock::new();
+
+        let (cell, ranks, pattern) = match encoding {
+            Encoding::Cl100kBase => (&CL100K_BASE, CL100K_BASE_RANKS, CL100K_BASE_PATTERN),
+            Encoding::O200kBase => (&O200K_BASE, O200K_BASE_RANKS, O200K_BASE_PATTERN),
+        };
+        cell.get_or_init(|| Tokenizer {
+            encoding,
+            bpe: CoreBpe::new(ranks, pattern),
+        })
+    }
+
+    pub fn for_model(model: &str) -> &'static Tokenizer {
+        Self::get(Encoding::for_model(model))
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.bpe.encode(text)
+    }
+
+    // Token boundaries don't always fall on character boundaries, so partial characters are replaced.
+    pub fn decode(&self, tokens: &[u32]) -> String {
+        String::from_utf8_lossy(&self.bpe.decode_bytes(tokens)).into_owned()
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    // Keeps the beginning of `text` up to `max_tokens` tokens.
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.encode(text);
+        if tokens.len() <= max_tokens {
+            return text.to_owned();
+        }
+        self.decode(&tokens[..max_tokens])
+    }
+}
+
+// Image cost depends on its size, which isn't known up front. Low detail images are a flat 85 tokens,
+// others are estimated as a 1024x1024 image split into four 512px tiles.
+const LOW_DETAIL_IMAGE_TOKENS: usize = 85;
+const IMAGE_TOKENS: usize = 765;
+
+fn image_tokens(image: &chat_completions::ImageUrl) -> usize {
+    match image.detail {
+        Some(chat_completions::ImageDetail::Low) => LOW_DETAIL_IMAGE_TOKENS,
+        _ => IMAGE_TOKENS,
+    }
+}
+
+// Every message is wrapped as `<|start|>{role}<|message|>{content}<|end|>`, the oldest snapshot used one more.
+fn tokens_per_message(model: &str) -> usize {
+    if model.starts_with("gpt-3.5-turbo-0301") {
+        4
+    } else {
+        3
+    }
+}
+
+pub fn count_message_tokens(model: &str, message: &chat_completions::Message) -> usize {
+    let tokenizer = Tokenizer::for_model(model);
+
+    // Tool calls are rendered as their function name and JSON arguments, which is a close estimate of
+    // how the API serializes them.
+    let tool_call_tokens: usize = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| tokenizer.count(&call.function.name) + tokenizer.count(&call.function.arguments))
+        .sum();
+
+    tokens_per_message(model)
+        + tokenizer.count(message.role.as_str())
+        + tokenizer.count(&message.text())
+        + message.images().into_iter().map(image_tokens).sum::<usize>()
+        + message.name.as_deref().map_or(0, |name| tokenizer.count(name) + 1)
+        + tool_call_tokens
+}
+
+// Shortens the text of `message` so the whole message fits in `max_tokens`, or None if not even the
+// role, framing and images fit. The text parts of multimodal content are merged into one.
+pub fn truncate_message(model: &str, message: &chat_completions::Message, max_tokens: usize) -> Option<chat_completions::Message> {
+    let tokenizer = Tokenizer::for_model(model);
+    let text = message.text();
+    let overhead = count_message_tokens(model, message) - tokenizer.count(&text);
+    let content_tokens = max_tokens.checked_sub(overhead)?;
+    let truncated = tokenizer.truncate(&text, content_tokens);
+
+    let content = message.content.as_ref().map(|content| match content {
+        chat_completions::Content::Text(_) => chat_completions::Content::Text(truncated),
+        chat_completions::Content::Parts(parts) => {
+            let mut truncated_parts = vec![chat_completions::ContentPart::text(truncated)];
+            truncated_parts.extend(parts.iter().filter(|part| part.as_image_url().is_some()).cloned());
+            chat_completions::Content::Parts(truncated_parts)
+        },
+    });
+
+    Some(chat_completions::Message {
+        content,
+        ..message.clone()
+    })
+}
+
+// Number of prompt tokens `messages` will use when sent to `model`, as reported in `Usage::prompt_tokens`.
+pub fn count_tokens(model: &str, messages: &[chat_completions::Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| count_message_tokens(model, message))
+        .sum::<usize>()
+        + REPLY_PRIMING_TOKENS
+}
+
+pub fn reply_priming_tokens() -> usize {
+    REPLY_PRIMING_TOKENS
+}
+
+pub fn count_text_tokens(model: &str, text: &str) -> usize {
+    Tokenizer::for_model(model).count(text)
+}
@@ -0,0 +1,142 @@
+use std::{collections::HashMap, fmt, future::Future, pin::Pin, sync::Arc};
+
+use futures_util::future::join_all;
+use serde_json::Value;
+
+use crate::{backend::ChatBackend, error::OGptError, model::chat_completions};
+
+// Upper bound on model round trips in `ToolRegistry::run` unless configured otherwise.
+pub const DEFAULT_MAX_TOOL_ROUNDS: usize = 8;
+
+// Handlers report failures as text, which is passed back to the model so it can recover.
+pub type ToolResult = Result<String, String>;
+
+type ToolFuture = Pin<Box<dyn Future<Output = ToolResult> + Send>>;
+type ToolHandler = Arc<dyn Fn(Value) -> ToolFuture + Send + Sync>;
+
+// Tools the model may call, each declared with a JSON schema for its arguments and dispatched to an
+// async handler receiving the parsed arguments.
+#[derive(Clone)]
+pub struct ToolRegistry {
+    tools: Vec<chat_completions::Tool>,
+    handlers: HashMap<String, ToolHandler>,
+    max_rounds: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolRun {
+    // The response carrying the final answer.
+    pub response: chat_completions::ChatCompletionsResponse,
+    // The whole conversation including tool calls, tool results and the final answer.
+    pub messages: Vec<chat_completions::Message>,
+    // Number of rounds in which the model called tools.
+    pub rounds: usize,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        ToolRegistry {
+            tools: vec![],
+            handlers: HashMap::new(),
+            max_rounds: DEFAULT_MAX_TOOL_ROUNDS,
+        }
+    }
+
+    pub fn register<F, Fut>(mut self, name: &str, description: &str, parameters: Value, handler: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolResult> + Send + 'static,
+    {
+        let description = if description.is_empty() { None } else { Some(description.to_owned()) };
+        self.tools.retain(|tool| tool.function.name != name);
+        self.tools.push(chat_completions::Tool::function(name.to_owned(), description, parameters));
+        self.handlers.insert(name.to_owned(), Arc::new(move |arguments| Box::pin(handler(arguments))));
+        self
+    }
+
+    pub fn max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    pub fn tools(&self) -> &[chat_completions::Tool] {
+        &self.tools
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    // Runs a single tool call and wraps the outcome in the tool message answering it.
+    pub async fn call(&self, call: &chat_completions::ToolCall) -> chat_completions::Message {
+        let content = match self.dispatch(call).await {
+            Ok(content) => content,
+            Err(err) => format!("Error: {}", err),
+        };
+        chat_completions::Message::tool(call.id.to_owned(), content)
+    }
+
+    async fn dispatch(&self, call: &chat_completions::ToolCall) -> ToolResult {
+        let handler = self
+            .handlers
+            .get(&call.function.name)
+            .ok_or_else(|| format!("unknown tool {}", call.function.name))?;
+
+        let arguments = match call.function.arguments.trim() {
+            "" => Value::Object(Default::default()),
+            arguments => serde_json::from_str(arguments)
+                .map_err(|err| format!("invalid JSON arguments for {}: {}", call.function.name, err))?,
+        };
+        handler(arguments).await
+    }
+
+    // Sends `request` with the registered tools and answers tool calls until the model replies without
+    // calling any. Calls made in the same round are run concurrently.
+    pub async fn run(&self, backend: &dyn ChatBackend, mut request: chat_completions::ChatCompletionsRequest) -> Result<ToolRun, OGptError> {
+        if request.tools.is_none() && !self.tools.is_empty() {
+            request.tools = Some(self.tools.clone());
+        }
+
+        for rounds in 0..=self.max_rounds {
+            let response = backend.chat(&request).await?;
+            let message = match response.choices.first() {
+                Some(choice) => choice.message.clone(),
+                None => return Ok(ToolRun { response, messages: request.messages, rounds }),
+            };
+
+            if !message.has_tool_calls() {
+                request.messages.push(message);
+                return Ok(ToolRun { response, messages: request.messages, rounds });
+            }
+            if rounds == self.max_rounds {
+                break;
+            }
+
+            let results = join_all(message.tool_calls.iter().flatten().map(|call| self.call(call))).await;
+            request.messages.push(message);
+            request.messages.extend(results);
+
+            // A forced tool choice would make the model call tools forever, so only apply it to the first round.
+            if let Some(chat_completions::ToolChoice::Named(_) | chat_completions::ToolChoice::Mode(chat_completions::ToolChoiceMode::Required)) = request.tool_choice {
+                request.tool_choice = None;
+            }
+        }
+
+        Err(OGptError::ToolLoopLimit { iterations: self.max_rounds })
+    }
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ToolRegistry")
+            .field("tools", &self.tools)
+            .field("max_rounds", &self.max_rounds)
+            .finish()
+    }
+}
@@ -1,9 +1,171 @@
-use crate::model::chat_completions;
+use futures_util::StreamExt;
+
+use crate::{model::chat_completions, client::ChatCompletionsStream, error::OGptError};
 
 pub fn get_chat_message(response: &chat_completions::ChatCompletionsResponse, index: usize) -> Option<&str> {
     let choice = response
         .choices
         .get(index)?;
 
-    Some(&choice.message.content)
-}
\ No newline at end of file
+    choice.message.content.as_ref()?.as_str()
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatStreamAccumulator {
+    choices: Vec<AccumulatedChoice>,
+    usage: Option<chat_completions::Usage>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct AccumulatedChoice {
+    role: Option<chat_completions::Role>,
+    content: String,
+    tool_calls: Vec<chat_completions::ToolCall>,
+    finish_reason: Option<String>,
+    logprobs: Option<Vec<chat_completions::TokenLogprob>>,
+}
+
+impl AccumulatedChoice {
+    fn push_tool_call(&mut self, delta: &chat_completions::ToolCallDelta) {
+        let index = delta.index as usize;
+        if self.tool_calls.len() <= index {
+            self.tool_calls.resize_with(index + 1, || chat_completions::ToolCall {
+                id: String::new(),
+                tool_type: chat_completions::ToolType::Function,
+                function: chat_completions::FunctionCall { name: String::new(), arguments: String::new() },
+            });
+        }
+
+        let call = &mut self.tool_calls[index];
+        if let Some(id) = &delta.id {
+            call.id.push_str(id);
+        }
+        if let Some(function) = &delta.function {
+            if let Some(name) = &function.name {
+                call.function.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    fn message(&self) -> chat_completions::Message {
+        chat_completions::Message {
+            role: self.role.clone().unwrap_or(chat_completions::Role::Assistant),
+            content: if self.content.is_empty() && !self.tool_calls.is_empty() { None } else { Some(chat_completions::Content::Text(self.content.clone())) },
+            name: None,
+            tool_calls: if self.tool_calls.is_empty() { None } else { Some(self.tool_calls.clone()) },
+            tool_call_id: None,
+        }
+    }
+}
+
+impl ChatStreamAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &chat_completions::ChatCompletionsChunk) {
+        if let Some(usage) = &chunk.usage {
+            self.usage = Some(usage.clone());
+        }
+        for choice in &chunk.choices {
+            let index = choice.index as usize;
+            if self.choices.len() <= index {
+                self.choices.resize_with(index + 1, AccumulatedChoice::default);
+            }
+
+            let accumulated = &mut self.choices[index];
+            if let Some(role) = &choice.delta.role {
+                accumulated.role = Some(role.clone());
+            }
+            if let Some(content) = &choice.delta.content {
+                accumulated.content.push_str(content);
+            }
+            for tool_call in choice.delta.tool_calls.iter().flatten() {
+                accumulated.push_tool_call(tool_call);
+            }
+            if let Some(finish_reason) = &choice.finish_reason {
+                accumulated.finish_reason = Some(finish_reason.clone());
+            }
+            if let Some(content) = choice.logprobs.as_ref().and_then(|logprobs| logprobs.content.as_ref()) {
+                accumulated.logprobs.get_or_insert_with(Vec::new).extend(content.iter().cloned());
+            }
+        }
+    }
+
+    pub fn content(&self, index: usize) -> Option<&str> {
+        self.choices.get(index).map(|choice| choice.content.as_str())
+    }
+
+    pub fn finish_reason(&self, index: usize) -> Option<&str> {
+        self.choices.get(index)?.finish_reason.as_deref()
+    }
+
+    pub fn is_finished(&self, index: usize) -> bool {
+        self.finish_reason(index).is_some()
+    }
+
+    pub fn message(&self, index: usize) -> Option<chat_completions::Message> {
+        self.choices.get(index).map(AccumulatedChoice::message)
+    }
+
+    pub fn tool_calls(&self, index: usize) -> Option<&[chat_completions::ToolCall]> {
+        self.choices.get(index).map(|choice| choice.tool_calls.as_slice())
+    }
+
+    // Logprobs of the content tokens so far, if the request asked for them.
+    pub fn logprobs(&self, index: usize) -> Option<&[chat_completions::TokenLogprob]> {
+        self.choices.get(index)?.logprobs.as_deref()
+    }
+
+    // Usage of the whole request, if the stream reported it.
+    pub fn usage(&self) -> Option<&chat_completions::Usage> {
+        self.usage.as_ref()
+    }
+
+    pub fn into_choices(self) -> Vec<chat_completions::Choice> {
+        self.choices
+            .into_iter()
+            .enumerate()
+            .map(|(index, choice)| chat_completions::Choice {
+                message: choice.message(),
+                finish_reason: choice.finish_reason.unwrap_or_default(),
+                index: index as u64,
+                logprobs: choice.logprobs.map(|content| chat_completions::ChoiceLogprobs { content: Some(content) }),
+            })
+            .collect()
+    }
+}
+
+// Drains a chat completions stream, folding every delta into the accumulated messages.
+pub async fn collect_chat_stream(mut stream: ChatCompletionsStream) -> Result<ChatStreamAccumulator, OGptError> {
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk?);
+    }
+    Ok(accumulator)
+}
+
+// Cosine similarity of two embeddings, 0 if either is all zeros or their lengths differ.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0_f32;
+    }
+
+    let mut dot = 0_f32;
+    let mut norm_a = 0_f32;
+    let mut norm_b = 0_f32;
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    if norm_a == 0_f32 || norm_b == 0_f32 {
+        0_f32
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+}
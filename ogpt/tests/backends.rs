@@ -0,0 +1,145 @@
+use futures_util::StreamExt;
+use ogpt::{backend::{AnthropicBackend, ChatBackend, OllamaBackend}, client::RetryPolicy, error::OGptError, model::chat_completions::{ChatCompletionsRequest, Message}, testing::{FakeBackend, MockResponse, MockServer}, utils::{self, ChatStreamAccumulator}};
+use serde_json::json;
+
+fn request(model: &str) -> ChatCompletionsRequest {
+    ChatCompletionsRequest::new(model.to_owned(), vec![
+        Message::system(String::from("Answer briefly.")),
+        Message::user(String::from("What is the capital of France?")),
+    ])
+}
+
+fn anthropic(server: &MockServer) -> AnthropicBackend {
+    AnthropicBackend::new(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .retry_policy(RetryPolicy::none())
+}
+
+fn ollama(server: &MockServer) -> OllamaBackend {
+    OllamaBackend::new()
+        .base_url(server.url())
+        .retry_policy(RetryPolicy::none())
+}
+
+#[tokio::test]
+async fn anthropic_chat() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::json(200, json!({
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4-5",
+        "content": [{ "type": "text", "text": "Paris." }],
+        "stop_reason": "end_turn",
+        "usage": { "input_tokens": 14, "output_tokens": 3 }
+    })));
+
+    let response = anthropic(&server).chat(&request("claude-sonnet-4-5")).await.unwrap();
+    assert_eq!(utils::get_chat_message(&response, 0), Some("Paris."));
+    assert_eq!(response.choices[0].finish_reason, "stop");
+    assert_eq!(response.usage.total_tokens, 17);
+
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/v1/messages");
+    assert_eq!(requests[0].header("x-api-key"), Some("test-key"));
+    let body = requests[0].json().unwrap();
+    assert_eq!(body["system"], "Answer briefly.");
+    assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+    assert_eq!(body["messages"][0]["role"], "user");
+}
+
+#[tokio::test]
+async fn anthropic_stream() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::sse(&[
+        json!({ "type": "message_start", "message": { "id": "msg_01", "model": "claude-sonnet-4-5", "usage": { "input_tokens": 10, "cache_read_input_tokens": 4, "output_tokens": 1 } } }),
+        json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+        json!({ "type": "ping" }),
+        json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Par" } }),
+        json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "is." } }),
+        json!({ "type": "content_block_stop", "index": 0 }),
+        json!({ "type": "message_delta", "delta": { "stop_reason": "max_tokens" }, "usage": { "output_tokens": 3 } }),
+        json!({ "type": "message_stop" }),
+    ].map(|event| event.to_string())));
+
+    let mut stream = anthropic(&server).stream(&request("claude-sonnet-4-5")).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("Paris."));
+    assert_eq!(accumulator.finish_reason(0), Some("length"));
+    let usage = accumulator.usage().unwrap();
+    assert_eq!((usage.prompt_tokens, usage.cached_prompt_tokens(), usage.completion_tokens), (14, 4, 3));
+    assert_eq!(server.requests()[0].json().unwrap()["stream"], true);
+}
+
+#[tokio::test]
+async fn anthropic_rejects_temperatures_above_one() {
+    let server = MockServer::start();
+    let request = request("claude-sonnet-4-5").temperature(1.5).unwrap();
+    let err = anthropic(&server).chat(&request).await.unwrap_err();
+    assert!(matches!(&err, OGptError::InvalidRequest(message) if message.contains("for Anthropic models")), "{:?}", err);
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn ollama_chat() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::json(200, json!({
+        "model": "llama3.1:8b",
+        "message": { "role": "assistant", "content": "Paris." },
+        "done": true,
+        "done_reason": "stop",
+        "prompt_eval_count": 20,
+        "eval_count": 3
+    })));
+
+    let response = ollama(&server).chat(&request("llama3.1:8b")).await.unwrap();
+    assert_eq!(utils::get_chat_message(&response, 0), Some("Paris."));
+    assert_eq!(response.usage.total_tokens, 23);
+
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/api/chat");
+    assert_eq!(requests[0].json().unwrap()["stream"], false);
+}
+
+#[tokio::test]
+async fn ollama_stream() {
+    let server = MockServer::start();
+    let lines = [
+        json!({ "model": "llama3.1:8b", "message": { "role": "assistant", "content": "Par" }, "done": false }),
+        json!({ "model": "llama3.1:8b", "message": { "role": "assistant", "content": "is." }, "done": false }),
+        json!({ "model": "llama3.1:8b", "message": { "role": "assistant", "content": "" }, "done": true, "done_reason": "stop", "prompt_eval_count": 20, "eval_count": 3 }),
+    ];
+    server.enqueue(lines.iter().fold(
+        MockResponse::new(200).header("content-type", "application/x-ndjson"),
+        |response, line| response.chunk(format!("{}\n", line).into_bytes())));
+
+    let mut stream = ollama(&server).stream(&request("llama3.1:8b")).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("Paris."));
+    assert_eq!(accumulator.finish_reason(0), Some("stop"));
+    assert_eq!(accumulator.usage().map(|usage| usage.total_tokens), Some(23));
+}
+
+#[tokio::test]
+async fn fake_backend_streams_its_replies_with_usage() {
+    let backend = FakeBackend::new();
+    backend.reply("Paris is the capital.");
+
+    let mut stream = backend.stream(&request("gpt-4o-mini")).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("Paris is the capital."));
+    assert_eq!(accumulator.finish_reason(0), Some("stop"));
+    assert_eq!(accumulator.usage().unwrap().completion_tokens, 5);
+    assert_eq!(backend.chat_requests().len(), 1);
+
+    assert!(backend.chat(&request("gpt-4o-mini")).await.is_err());
+}
@@ -0,0 +1,129 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use ogpt::{client::{Cassette, OGptAsyncClient, RetryPolicy}, error::OGptError, model::{chat_completions::{ChatCompletionsRequest, Message}, speech::SpeechRequest}, testing::{MockResponse, MockServer}, utils::{self, ChatStreamAccumulator}};
+use serde_json::json;
+
+const FIXTURE: &str = "tests/fixtures/chat_completions.json";
+// Nothing listens here, replayed requests must never reach the network.
+const UNREACHABLE_URL: &str = "http://127.0.0.1:9/v1";
+
+fn client(base_url: String, cassette: Arc<Cassette>) -> OGptAsyncClient {
+    OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(base_url)
+        .retry_policy(RetryPolicy::default().initial_backoff(Duration::from_millis(1)).jitter(false))
+        .cassette(cassette)
+        // The fixture's streams were recorded with their usage.
+        .stream_usage(true)
+        .build_async()
+        .unwrap()
+}
+
+fn request(question: &str) -> ChatCompletionsRequest {
+    ChatCompletionsRequest::default(String::from("gpt-4o-mini"), vec![
+        Message::system(String::from("You are a bot that answers questions accurately.")),
+        Message::user(question.to_owned()),
+    ])
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ogpt-{}-{}.json", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn replays_chat_completion() {
+    let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+    let client = client(String::from(UNREACHABLE_URL), cassette.clone());
+
+    let response = client.chat_completion_async(&request("What is the capital of France?")).await.unwrap();
+    assert_eq!(utils::get_chat_message(&response, 0), Some("The capital of France is Paris."));
+    assert_eq!(cassette.remaining(), 1);
+}
+
+#[tokio::test]
+async fn replays_stream() {
+    let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+    let client = client(String::from(UNREACHABLE_URL), cassette.clone());
+
+    let mut stream = client.chat_completion_stream(&request("Count to three.")).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("1, 2, 3."));
+    assert_eq!(accumulator.finish_reason(0), Some("stop"));
+    assert_eq!(accumulator.usage().map(|usage| usage.total_tokens), Some(32));
+}
+
+#[tokio::test]
+async fn unmatched_request_is_an_error() {
+    let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+    let client = client(String::from(UNREACHABLE_URL), cassette);
+
+    let err = client.chat_completion_async(&request("What is the capital of Spain?")).await.unwrap_err();
+    assert!(matches!(err, OGptError::Cassette(_)));
+}
+
+#[tokio::test]
+async fn each_interaction_replays_once() {
+    let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+    let client = client(String::from(UNREACHABLE_URL), cassette);
+
+    client.chat_completion_async(&request("What is the capital of France?")).await.unwrap();
+    let err = client.chat_completion_async(&request("What is the capital of France?")).await.unwrap_err();
+    assert!(matches!(err, OGptError::Cassette(_)));
+}
+
+#[tokio::test]
+async fn records_and_replays() {
+    let path = temp_path("record");
+    let server = MockServer::start();
+    server
+        .enqueue(MockResponse::error(500, "server_error", "The server had an error"))
+        .enqueue(MockResponse::json(200, json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-4o-mini",
+            "usage": { "prompt_tokens": 20, "completion_tokens": 2, "total_tokens": 22 },
+            "choices": [{ "index": 0, "finish_reason": "stop", "message": { "role": "assistant", "content": "Hi!" } }]
+        })).header("x-request-id", "req_123"));
+
+    let recorder = Arc::new(Cassette::record(&path));
+    let response = client(format!("{}/v1", server.url()), recorder.clone()).chat_completion_async(&request("Hello")).await.unwrap();
+    assert_eq!(response.attempts, 2);
+    drop(server);
+
+    // Only headers the client needs are kept.
+    let interactions = recorder.interactions();
+    assert_eq!(interactions.len(), 2);
+    assert_eq!(interactions[0].request.path, "/v1/chat/completions");
+    assert_eq!(interactions[1].response.headers.get("content-type").map(String::as_str), Some("application/json"));
+    assert!(!interactions[1].response.headers.contains_key("x-request-id"));
+
+    let replayer = Arc::new(Cassette::replay(&path).unwrap());
+    let replayed = client(String::from(UNREACHABLE_URL), replayer.clone()).chat_completion_async(&request("Hello")).await.unwrap();
+    assert_eq!(replayed, response);
+    assert_eq!(replayed.attempts, 2);
+    assert_eq!(replayer.remaining(), 0);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn records_binary_responses() {
+    let path = temp_path("binary");
+    let audio = vec![0xff, 0xfb, 0x90, 0x00, 0xc3];
+    let server = MockServer::start();
+    server.enqueue(MockResponse::new(200).header("content-type", "audio/mpeg").chunk(audio.clone()));
+
+    let request = SpeechRequest::new(String::from("tts-1"), String::from("Hello"), String::from("alloy"));
+    let recorder = Arc::new(Cassette::record(&path));
+    assert_eq!(client(format!("{}/v1", server.url()), recorder.clone()).speech_async(&request).await.unwrap(), audio);
+    assert!(recorder.interactions()[0].response.base64);
+
+    let replayer = Arc::new(Cassette::replay(&path).unwrap());
+    assert_eq!(client(String::from(UNREACHABLE_URL), replayer).speech_async(&request).await.unwrap(), audio);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use ogpt::{error::OGptError, model::chat_completions::{ChatCompletionsChunk, ChatCompletionsRequest, Message, ResponseFormat, Stop, StreamOptions, Tool}, utils::ChatStreamAccumulator};
+use serde_json::json;
+
+fn request() -> ChatCompletionsRequest {
+    ChatCompletionsRequest::new(String::from("gpt-4o-mini"), vec![Message::user(String::from("Say hello"))])
+}
+
+fn assert_invalid<T: std::fmt::Debug>(result: Result<T, OGptError>, message: &str) {
+    match result {
+        Err(OGptError::InvalidRequest(err)) => assert!(err.contains(message), "{:?} doesn't contain {:?}", err, message),
+        other => panic!("expected an invalid request error, got {:?}", other),
+    }
+}
+
+#[test]
+fn accepts_a_plain_request() {
+    request().validate().unwrap();
+    ChatCompletionsRequest::default(String::from("gpt-4o-mini"), vec![Message::user(String::from("Hi"))]).validate().unwrap();
+}
+
+#[test]
+fn requires_messages() {
+    assert_invalid(ChatCompletionsRequest::new(String::from("gpt-4o-mini"), vec![]).validate(), "messages must not be empty");
+}
+
+#[test]
+fn checks_ranges_inclusively() {
+    request().temperature(0.0).unwrap().temperature(2.0).unwrap().validate().unwrap();
+    assert_invalid(request().temperature(2.01), "temperature must be between 0 and 2");
+    assert_invalid(request().temperature(f64::NAN), "temperature");
+    assert_invalid(request().top_p(-0.1), "top_p must be between 0 and 1");
+    assert_invalid(request().presence_penalty(-2.5), "presence_penalty");
+    assert_invalid(request().frequency_penalty(2.5), "frequency_penalty");
+    request().presence_penalty(-2.0).unwrap().frequency_penalty(2.0).unwrap().validate().unwrap();
+}
+
+#[test]
+fn setters_and_validate_agree() {
+    assert_invalid(request().n(0), "n must be at least 1");
+    assert_invalid(request().max_tokens(0), "max_tokens must be at least 1");
+
+    let mut set_directly = request();
+    set_directly.n = Some(0);
+    assert_invalid(set_directly.validate(), "n must be at least 1");
+
+    let mut set_directly = request();
+    set_directly.max_tokens = Some(0);
+    assert_invalid(set_directly.validate(), "max_tokens must be at least 1");
+
+    let mut set_directly = request();
+    set_directly.temperature = Some(3.0);
+    assert_invalid(set_directly.validate(), "temperature");
+}
+
+#[test]
+fn checks_stop_sequences() {
+    let stop = |sequences: &[&str]| Stop::Multiple(sequences.iter().map(|sequence| sequence.to_string()).collect());
+    request().stop(stop(&["a", "b", "c", "d"])).unwrap();
+    assert_invalid(request().stop(stop(&["a", "b", "c", "d", "e"])), "stop takes at most 4 sequences");
+    assert_invalid(request().stop(Stop::Single(String::new())), "stop sequences must not be empty");
+}
+
+#[test]
+fn checks_logit_bias() {
+    request().logit_bias(BTreeMap::from([(1, -100), (2, 100)])).unwrap();
+    assert_invalid(request().logit_bias(BTreeMap::from([(1, 101)])), "got 101 for token 1");
+}
+
+#[test]
+fn checks_json_schema_names() {
+    let schema = json!({ "type": "object" });
+    request().response_format(ResponseFormat::json_schema(String::from("answer_v2-x"), schema.clone(), true)).unwrap();
+    assert_invalid(request().response_format(ResponseFormat::json_schema(String::new(), schema.clone(), true)), "json_schema name");
+    assert_invalid(request().response_format(ResponseFormat::json_schema(String::from("has spaces"), schema.clone(), true)), "json_schema name");
+    assert_invalid(request().response_format(ResponseFormat::json_schema("a".repeat(65), schema, true)), "json_schema name");
+}
+
+#[test]
+fn checks_constraints_between_fields() {
+    let with_top_logprobs = request().top_logprobs(5).unwrap();
+    assert_eq!(with_top_logprobs.logprobs, Some(true));
+    with_top_logprobs.validate().unwrap();
+    assert_invalid(request().top_logprobs(21), "top_logprobs must be at most 20");
+
+    let mut without_logprobs = request();
+    without_logprobs.top_logprobs = Some(5);
+    assert_invalid(without_logprobs.validate(), "top_logprobs requires logprobs");
+
+    assert_invalid(request().parallel_tool_calls(true).validate(), "parallel_tool_calls is only allowed together with tools");
+    request()
+        .tools(vec![Tool::function(String::from("lookup"), None, json!({ "type": "object" }))])
+        .parallel_tool_calls(false)
+        .validate()
+        .unwrap();
+
+    let stream_options = StreamOptions { include_usage: true };
+    assert_invalid(request().stream_options(stream_options.clone()).validate(), "stream_options is only allowed together with stream");
+    request().stream(true).stream_options(stream_options).validate().unwrap();
+}
+
+#[test]
+fn accumulates_streamed_logprobs() {
+    let chunk = |content: &str, logprob: f64| -> ChatCompletionsChunk {
+        serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1700000000,
+            "model": "gpt-4o-mini",
+            "choices": [{
+                "index": 0,
+                "finish_reason": null,
+                "delta": { "content": content },
+                "logprobs": { "content": [{ "token": content, "logprob": logprob, "bytes": null, "top_logprobs": [] }] }
+            }]
+        })).unwrap()
+    };
+
+    let mut accumulator = ChatStreamAccumulator::new();
+    accumulator.push(&chunk("Hel", -0.5));
+    accumulator.push(&chunk("lo", -0.25));
+    let logprobs = accumulator.logprobs(0).unwrap();
+    assert_eq!(logprobs.iter().map(|logprob| logprob.token.as_str()).collect::<Vec<&str>>(), vec!["Hel", "lo"]);
+    assert_eq!(logprobs[1].logprob, -0.25);
+
+    let choices = accumulator.into_choices();
+    assert_eq!(choices[0].logprobs.as_ref().unwrap().content.as_ref().unwrap().len(), 2);
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use ogpt::{client::{OGptAsyncClient, RetryPolicy}, error::OGptError, model::chat_completions::{ChatCompletionsRequest, Message}, testing::{MockResponse, MockServer}, utils::{self, ChatStreamAccumulator}};
+use serde_json::json;
+
+fn client(server: &MockServer, max_attempts: u32) -> OGptAsyncClient {
+    OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .retry_policy(RetryPolicy::default().max_attempts(max_attempts).initial_backoff(Duration::from_millis(1)).jitter(false))
+        .build_async()
+        .unwrap()
+}
+
+fn request() -> ChatCompletionsRequest {
+    ChatCompletionsRequest::new(String::from("gpt-4o-mini"), vec![Message::user(String::from("Say hello"))])
+}
+
+fn completion(content: &str) -> MockResponse {
+    MockResponse::json(200, json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "usage": { "prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12 },
+        "choices": [{ "index": 0, "finish_reason": "stop", "message": { "role": "assistant", "content": content } }]
+    }))
+}
+
+fn chunk(content: &str, finish_reason: Option<&str>) -> String {
+    json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "choices": [{ "index": 0, "finish_reason": finish_reason, "delta": { "role": null, "content": content } }]
+    }).to_string()
+}
+
+#[tokio::test]
+async fn sends_chat_completion() {
+    let server = MockServer::start();
+    server.enqueue(completion("Hello!"));
+
+    let response = client(&server, 1).chat_completion_async(&request()).await.unwrap();
+    assert_eq!(utils::get_chat_message(&response, 0), Some("Hello!"));
+    assert_eq!(response.usage.total_tokens, 12);
+    assert_eq!(response.attempts, 1);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v1/chat/completions");
+    assert_eq!(requests[0].header("authorization"), Some("Bearer test-key"));
+    assert_eq!(requests[0].json().unwrap()["messages"][0]["content"], "Say hello");
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let server = MockServer::start();
+    server
+        .enqueue(MockResponse::error(500, "server_error", "The server had an error"))
+        .enqueue(MockResponse::error(429, "requests", "Rate limit reached").header("retry-after", "0"))
+        .enqueue(completion("Hello!"));
+
+    let response = client(&server, 3).chat_completion_async(&request()).await.unwrap();
+    assert_eq!(utils::get_chat_message(&response, 0), Some("Hello!"));
+    assert_eq!(response.attempts, 3);
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let server = MockServer::start();
+    server
+        .enqueue(MockResponse::error(503, "server_error", "Overloaded"))
+        .enqueue(MockResponse::error(503, "server_error", "Still overloaded"));
+
+    let err = client(&server, 2).chat_completion_async(&request()).await.unwrap_err();
+    assert_eq!(err.attempts(), 2);
+    assert_eq!(err.status(), Some(503));
+    assert!(err.to_string().contains("Still overloaded"));
+}
+
+#[tokio::test]
+async fn does_not_retry_client_errors() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::error(401, "invalid_request_error", "Incorrect API key provided"));
+
+    let err = client(&server, 3).chat_completion_async(&request()).await.unwrap_err();
+    assert!(matches!(err, OGptError::Api { status: 401, .. }));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn does_not_retry_insufficient_quota() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::json(429, json!({
+        "error": { "message": "You exceeded your current quota", "type": "insufficient_quota", "param": null, "code": "insufficient_quota" }
+    })));
+
+    let err = client(&server, 3).chat_completion_async(&request()).await.unwrap_err();
+    assert!(err.is_rate_limited());
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn rejects_invalid_requests_without_sending() {
+    let server = MockServer::start();
+    let mut request = request();
+    request.temperature = Some(3.0);
+
+    let err = client(&server, 1).chat_completion_async(&request).await.unwrap_err();
+    assert!(matches!(err, OGptError::InvalidRequest(_)));
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn streams_chunks() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::sse(&[chunk("Hel", None), chunk("lo!", Some("stop")), String::from("[DONE]")]).chunk_delay(Duration::from_millis(10)));
+
+    let mut stream = client(&server, 1).chat_completion_stream(&request()).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("Hello!"));
+    assert_eq!(accumulator.finish_reason(0), Some("stop"));
+    assert_eq!(server.requests()[0].json().unwrap()["stream"], true);
+    assert!(server.requests()[0].json().unwrap().get("stream_options").is_none());
+}
+
+#[tokio::test]
+async fn asks_for_the_stream_usage_when_enabled() {
+    let server = MockServer::start();
+    let usage = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "choices": [],
+        "usage": { "prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11 }
+    });
+    server.enqueue(MockResponse::sse(&[chunk("Hello!", Some("stop")), usage.to_string(), String::from("[DONE]")]));
+
+    let client = OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .stream_usage(true)
+        .build_async()
+        .unwrap();
+    let mut stream = client.chat_completion_stream(&request()).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.usage().map(|usage| usage.total_tokens), Some(11));
+    assert_eq!(server.requests()[0].json().unwrap()["stream_options"]["include_usage"], true);
+}
+
+#[tokio::test]
+async fn stream_cut_short_ends_without_finish_reason() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::sse(&[chunk("Hel", None)]));
+
+    let mut stream = client(&server, 1).chat_completion_stream(&request()).await.unwrap();
+    let mut accumulator = ChatStreamAccumulator::new();
+    while let Some(chunk) = stream.next().await {
+        accumulator.push(&chunk.unwrap());
+    }
+    assert_eq!(accumulator.content(0), Some("Hel"));
+    assert!(!accumulator.is_finished(0));
+}
+
+#[tokio::test]
+async fn stream_error_event_ends_the_stream() {
+    let server = MockServer::start();
+    let error = json!({ "error": { "message": "The model is overloaded", "type": "server_error", "param": null, "code": null } });
+    server.enqueue(MockResponse::sse(&[chunk("Hel", None), error.to_string()]));
+
+    let mut stream = client(&server, 1).chat_completion_stream(&request()).await.unwrap();
+    assert!(stream.next().await.unwrap().is_ok());
+    let err = stream.next().await.unwrap().unwrap_err();
+    assert!(err.to_string().contains("The model is overloaded"));
+    assert!(stream.next().await.is_none());
+}
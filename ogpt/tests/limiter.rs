@@ -0,0 +1,106 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use futures_util::StreamExt;
+use ogpt::{client::{OGptAsyncClient, RetryPolicy}, error::OGptError, limiter::{RateLimiter, RateLimits}, model::chat_completions::{ChatCompletionsRequest, Message, StreamOptions}, testing::{MockResponse, MockServer}};
+use serde_json::json;
+
+fn limiter(requests_per_minute: Option<u64>, tokens_per_minute: Option<u64>, max_wait: Duration) -> RateLimiter {
+    RateLimiter::new(RateLimits { requests_per_minute, tokens_per_minute, max_wait })
+}
+
+#[test]
+fn zero_limits_are_disabled() {
+    let limiter = limiter(Some(0), Some(0), Duration::ZERO);
+    for _ in 0..100 {
+        limiter.acquire_blocking(1000).unwrap();
+    }
+}
+
+#[test]
+fn rejects_requests_past_the_limit_with_the_time_until_the_next_one() {
+    let limiter = limiter(Some(60), None, Duration::ZERO);
+    for _ in 0..60 {
+        limiter.acquire_blocking(0).unwrap();
+    }
+
+    let err = limiter.acquire_blocking(0).unwrap_err();
+    assert!(matches!(&err, OGptError::BudgetExhausted { limit, .. } if limit == "requests per minute"));
+    let retry_after = err.retry_after().unwrap();
+    assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1), "{:?}", retry_after);
+}
+
+#[test]
+fn rejected_requests_give_back_their_reservation() {
+    let limiter = limiter(Some(60), Some(100), Duration::ZERO);
+    limiter.acquire_blocking(90).unwrap();
+    assert!(limiter.acquire_blocking(50).is_err());
+    limiter.acquire_blocking(10).unwrap();
+}
+
+#[test]
+fn waits_for_the_bucket_to_refill() {
+    let limiter = limiter(Some(600), None, Duration::from_secs(1));
+    for _ in 0..600 {
+        limiter.acquire_blocking(0).unwrap();
+    }
+
+    let start = Instant::now();
+    limiter.acquire_blocking(0).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(90), "{:?}", start.elapsed());
+}
+
+#[test]
+fn caps_requests_larger_than_the_budget() {
+    let limiter = limiter(None, Some(100), Duration::ZERO);
+    let permit = limiter.acquire_blocking(1000).unwrap();
+    assert_eq!(permit.estimated_tokens(), 100);
+    assert!(limiter.acquire_blocking(1).is_err());
+}
+
+#[test]
+fn reconcile_refunds_overestimates() {
+    let limiter = limiter(None, Some(100), Duration::ZERO);
+    let permit = limiter.acquire_blocking(100).unwrap();
+    limiter.reconcile(permit, 10);
+    limiter.acquire_blocking(90).unwrap();
+    assert!(limiter.acquire_blocking(1).is_err());
+}
+
+#[tokio::test]
+async fn streams_reconcile_with_their_usage() {
+    let server = MockServer::start();
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "choices": [{ "index": 0, "finish_reason": "stop", "delta": { "role": null, "content": "Hello!" } }]
+    });
+    let usage = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "choices": [],
+        "usage": { "prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12 }
+    });
+    server.enqueue(MockResponse::sse(&[chunk.to_string(), usage.to_string(), String::from("[DONE]")]));
+
+    let rate_limiter = Arc::new(limiter(None, Some(300), Duration::ZERO));
+    let client = OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .retry_policy(RetryPolicy::default().max_attempts(1))
+        .rate_limiter(rate_limiter.clone())
+        .build_async()
+        .unwrap();
+    let request = ChatCompletionsRequest::new(String::from("gpt-4o-mini"), vec![Message::user(String::from("Say hello"))])
+        .stream_options(StreamOptions { include_usage: true });
+
+    let mut stream = client.chat_completion_stream(&request).await.unwrap();
+    // The request reserves its prompt and the default completion allowance until the usage arrives.
+    assert!(rate_limiter.acquire_blocking(100).is_err());
+    while let Some(chunk) = stream.next().await {
+        chunk.unwrap();
+    }
+    rate_limiter.acquire_blocking(280).unwrap();
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use ogpt::{model::chat_completions::Usage, pricing::{self, ModelPrice, PriceTable}};
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+}
+
+#[test]
+fn prices_known_models_by_prefix() {
+    let mini = ModelPrice::for_model("gpt-4o-mini-2024-07-18").unwrap();
+    assert_eq!(mini.input, 0.15);
+    assert_eq!(ModelPrice::for_model("gpt-4o-2024-08-06").unwrap().input, 2.50);
+    assert_eq!(ModelPrice::for_model("gpt-4-0613").unwrap().input, 30.00);
+    assert!(ModelPrice::for_model("llama3.1:8b").is_none());
+}
+
+#[test]
+fn discounts_cached_prompt_tokens() {
+    let price = ModelPrice { input: 2.0, output: 8.0, cached_input: Some(0.5) };
+    let usage = Usage::new(1_000_000, 500_000).cached_tokens(400_000);
+    assert_close(price.cost(&usage), 0.6 * 2.0 + 0.4 * 0.5 + 0.5 * 8.0);
+
+    let uncached = ModelPrice { cached_input: None, ..price };
+    assert_close(uncached.cost(&usage), 2.0 + 4.0);
+}
+
+#[test]
+fn the_longest_matching_prefix_wins() {
+    let mut overrides = HashMap::new();
+    overrides.insert(String::from("gpt-4o"), ModelPrice { input: 1.0, output: 1.0, cached_input: None });
+    overrides.insert(String::from("gpt-4.1-mini"), ModelPrice { input: 0.2, output: 0.8, cached_input: None });
+    overrides.insert(String::from("gpt-4.1-mini-2025"), ModelPrice { input: 0.3, output: 0.9, cached_input: None });
+    overrides.insert(String::from("llama3"), ModelPrice { input: 0.0, output: 0.0, cached_input: None });
+    let table = PriceTable::with_overrides(overrides);
+
+    assert_eq!(table.price("gpt-4o-2024-08-06").unwrap().input, 1.0);
+    // Overriding the gpt-4o family leaves the more specific known gpt-4o-mini price alone.
+    assert_eq!(table.price("gpt-4o-mini").unwrap().input, 0.15);
+    // An override of the same prefix as a known price replaces it.
+    assert_eq!(table.price("gpt-4.1-mini").unwrap().input, 0.2);
+    assert_eq!(table.price("gpt-4.1-mini-2025-04-14").unwrap().input, 0.3);
+    assert_eq!(table.price("gpt-4.1").unwrap().input, 2.00);
+    assert_eq!(table.cost("llama3.1:8b", &Usage::new(1000, 1000)), Some(0.0));
+    assert_eq!(table.cost("mistral", &Usage::new(1000, 1000)), None);
+}
+
+#[test]
+fn prices_media_by_what_they_produce() {
+    assert_close(pricing::image_cost("dall-e-3", "1024x1024", None, 1).unwrap(), 0.04);
+    assert_close(pricing::image_cost("dall-e-3", "1792x1024", Some("hd"), 2).unwrap(), 0.24);
+    assert_close(pricing::image_cost("dall-e-2", "512x512", None, 1).unwrap(), 0.018);
+    assert_close(pricing::image_cost("gpt-image-1", "1024x1024", Some("low"), 1).unwrap(), 0.011);
+    assert_close(pricing::image_cost("gpt-image-1", "1024x1024", None, 1).unwrap(), 0.167);
+    assert!(pricing::image_cost("dall-e-3", "640x480", None, 1).is_none());
+
+    assert_close(pricing::speech_cost("tts-1", 1000).unwrap(), 0.015);
+    assert_close(pricing::speech_cost("tts-1-hd", 1000).unwrap(), 0.03);
+    assert!(pricing::speech_cost("kokoro", 1000).is_none());
+
+    assert_close(pricing::transcription_cost("whisper-1", 90.0).unwrap(), 0.009);
+    assert_close(pricing::transcription_cost("gpt-4o-mini-transcribe", 60.0).unwrap(), 0.003);
+}
+
+#[test]
+fn prices_embeddings_and_free_moderation() {
+    assert_close(ModelPrice::for_model("text-embedding-3-small").unwrap().cost(&Usage::new(1_000_000, 0)), 0.02);
+    assert_eq!(ModelPrice::for_model("omni-moderation-latest").unwrap().cost(&Usage::new(1000, 0)), 0.0);
+}
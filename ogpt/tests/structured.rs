@@ -0,0 +1,124 @@
+use ogpt::{backend::AnthropicBackend, client::{OGptAsyncClient, RetryPolicy}, error::OGptError, model::chat_completions::{ChatCompletionsRequest, JsonSchema, Message}, structured, testing::{MockResponse, MockServer}};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Capital {
+    city: String,
+}
+
+fn client(server: &MockServer) -> OGptAsyncClient {
+    OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .retry_policy(RetryPolicy::none())
+        .build_async()
+        .unwrap()
+}
+
+fn request() -> ChatCompletionsRequest {
+    ChatCompletionsRequest::new(String::from("gpt-4o-mini"), vec![Message::user(String::from("What is the capital of France?"))])
+}
+
+fn schema() -> JsonSchema {
+    JsonSchema {
+        name: String::from("capital"),
+        description: None,
+        schema: json!({ "type": "object", "properties": { "city": { "type": "string" } }, "required": ["city"] }),
+        strict: Some(true),
+    }
+}
+
+fn completion(content: &str) -> MockResponse {
+    MockResponse::json(200, json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion",
+        "created": 1700000000,
+        "model": "gpt-4o-mini",
+        "usage": { "prompt_tokens": 20, "completion_tokens": 8, "total_tokens": 28 },
+        "choices": [{ "index": 0, "finish_reason": "stop", "message": { "role": "assistant", "content": content } }]
+    }))
+}
+
+#[test]
+fn parses_plain_and_fenced_json() {
+    let expected = Capital { city: String::from("Paris") };
+    assert_eq!(structured::parse_json::<Capital>(r#" {"city": "Paris"} "#).unwrap(), expected);
+    assert_eq!(structured::parse_json::<Capital>("```json\n{\"city\": \"Paris\"}\n```").unwrap(), expected);
+    assert_eq!(structured::parse_json::<Capital>("```\n{\"city\": \"Paris\"}\n```").unwrap(), expected);
+    assert!(structured::parse_json::<Capital>("The capital is Paris.").is_err());
+    assert!(structured::parse_json::<Capital>(r#"{"town": "Paris"}"#).is_err());
+}
+
+#[tokio::test]
+async fn returns_valid_json_without_retrying() {
+    let server = MockServer::start();
+    server.enqueue(completion(r#"{"city": "Paris"}"#));
+
+    let response = structured::chat_completion_json::<Capital>(&client(&server), request(), schema()).await.unwrap();
+    assert_eq!(response.value, Capital { city: String::from("Paris") });
+    assert!(!response.retried);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    let body = requests[0].json().unwrap();
+    assert_eq!(body["response_format"]["type"], "json_schema");
+    assert_eq!(body["response_format"]["json_schema"]["name"], "capital");
+    assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn asks_the_model_to_correct_invalid_json() {
+    let server = MockServer::start();
+    server
+        .enqueue(completion("The capital of France is Paris."))
+        .enqueue(completion(r#"{"city": "Paris"}"#));
+
+    let response = structured::chat_completion_json::<Capital>(&client(&server), request(), schema()).await.unwrap();
+    assert_eq!(response.value, Capital { city: String::from("Paris") });
+    assert!(response.retried);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    let messages = requests[1].json().unwrap()["messages"].as_array().unwrap().clone();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"], "The capital of France is Paris.");
+    assert_eq!(messages[2]["role"], "user");
+    assert!(messages[2]["content"].as_str().unwrap().starts_with("That is not valid JSON for the schema"));
+}
+
+#[tokio::test]
+async fn gives_up_after_the_correction_fails() {
+    let server = MockServer::start();
+    server
+        .enqueue(completion("Paris."))
+        .enqueue(completion(r#"{"town": "Paris"}"#));
+
+    let err = structured::chat_completion_json::<Capital>(&client(&server), request(), schema()).await.unwrap_err();
+    assert!(matches!(&err, OGptError::InvalidResponse(message) if message.starts_with("model returned invalid JSON twice")), "{:?}", err);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn gives_backends_without_schemas_the_schema_in_a_system_message() {
+    let server = MockServer::start();
+    server.enqueue(MockResponse::json(200, json!({
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4-5",
+        "content": [{ "type": "text", "text": "```json\n{\"city\": \"Paris\"}\n```" }],
+        "stop_reason": "end_turn",
+        "usage": { "input_tokens": 30, "output_tokens": 9 }
+    })));
+
+    let backend = AnthropicBackend::new(String::from("test-key"))
+        .base_url(format!("{}/v1", server.url()))
+        .retry_policy(RetryPolicy::none());
+    let request = ChatCompletionsRequest::new(String::from("claude-sonnet-4-5"), request().messages);
+    let response = structured::chat_completion_json::<Capital>(&backend, request, schema()).await.unwrap();
+    assert_eq!(response.value, Capital { city: String::from("Paris") });
+
+    let body = server.requests()[0].json().unwrap();
+    assert!(body["system"].as_str().unwrap().contains("matching this JSON schema"));
+}
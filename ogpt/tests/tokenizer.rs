@@ -0,0 +1,66 @@
+use ogpt::{model::chat_completions::Message, tokenizer::{self, Encoding, Tokenizer}};
+
+// The example conversation from OpenAI's "How to count tokens with tiktoken" cookbook.
+fn cookbook_messages() -> Vec<Message> {
+    vec![
+        Message::system(String::from("You are a helpful, pattern-following assistant that translates corporate jargon into plain English.")),
+        Message::system(String::from("New synergies will help drive top-line growth.")).name(String::from("example_user")),
+        Message::system(String::from("Things working well together will increase revenue.")).name(String::from("example_assistant")),
+        Message::system(String::from("Let's circle back when we have more bandwidth to touch base on opportunities for increased leverage.")).name(String::from("example_user")),
+        Message::system(String::from("Let's talk later when we're less busy about how to do better.")).name(String::from("example_assistant")),
+        Message::user(String::from("This late pivot means we don't have time to boil the ocean for the client deliverable.")),
+    ]
+}
+
+#[test]
+fn picks_encoding_by_model() {
+    assert_eq!(Encoding::for_model("gpt-4o-mini"), Encoding::O200kBase);
+    assert_eq!(Encoding::for_model("o3-mini"), Encoding::O200kBase);
+    assert_eq!(Encoding::for_model("gpt-4-turbo"), Encoding::Cl100kBase);
+    assert_eq!(Encoding::for_model("gpt-3.5-turbo"), Encoding::Cl100kBase);
+}
+
+#[test]
+fn encodes_cl100k_base() {
+    let tokenizer = Tokenizer::get(Encoding::Cl100kBase);
+    assert_eq!(tokenizer.encode("hello world"), vec![15339, 1917]);
+    assert_eq!(tokenizer.encode("tiktoken is great!"), vec![83, 1609, 5963, 374, 2294, 0]);
+    assert_eq!(tokenizer.encode(""), Vec::<u32>::new());
+}
+
+#[test]
+fn encodes_o200k_base() {
+    let tokenizer = Tokenizer::get(Encoding::O200kBase);
+    assert_eq!(tokenizer.encode("hello world"), vec![24912, 2375]);
+}
+
+#[test]
+fn decodes_what_it_encodes() {
+    for encoding in [Encoding::Cl100kBase, Encoding::O200kBase] {
+        let tokenizer = Tokenizer::get(encoding);
+        let text = "Ünïcödé, emoji 🦀 and\n\n  whitespace\tsurvive the round trip.";
+        assert_eq!(tokenizer.decode(&tokenizer.encode(text)), text);
+    }
+}
+
+#[test]
+fn counts_chat_prompts_like_the_api() {
+    assert_eq!(tokenizer::count_tokens("gpt-3.5-turbo", &cookbook_messages()), 129);
+    assert_eq!(tokenizer::count_tokens("gpt-4", &cookbook_messages()), 129);
+    assert_eq!(tokenizer::count_tokens("gpt-4o", &cookbook_messages()), 124);
+}
+
+#[test]
+fn truncates_to_whole_tokens() {
+    let tokenizer = Tokenizer::get(Encoding::Cl100kBase);
+    assert_eq!(tokenizer.truncate("tiktoken is great!", 3), "tiktoken");
+    assert_eq!(tokenizer.truncate("tiktoken is great!", 100), "tiktoken is great!");
+}
+
+#[test]
+fn truncated_messages_fit_the_budget() {
+    let message = Message::user("lorem ipsum dolor sit amet ".repeat(50));
+    let truncated = tokenizer::truncate_message("gpt-4o", &message, 20).unwrap();
+    assert_eq!(tokenizer::count_message_tokens("gpt-4o", &truncated), 20);
+    assert!(tokenizer::truncate_message("gpt-4o", &message, 2).is_none());
+}
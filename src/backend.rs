@@ -0,0 +1,96 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ogpt::{backend::{AnthropicBackend, ChatBackend, OllamaBackend}, client::{Cassette, OGptAsyncClient, RetryPolicy}};
+
+use crate::{ServerError, config::{BackendKind, BackendSettings, ChatSettings, OpenAiConfig}};
+
+// A chat backend together with how the handler uses it.
+#[derive(Debug, Clone)]
+pub struct ChatRoute {
+    pub backend: Arc<dyn ChatBackend>,
+    pub settings: ChatSettings,
+}
+
+// The chat backend answering in each guild.
+#[derive(Debug, Clone)]
+pub struct ChatRoutes {
+    default: ChatRoute,
+    guilds: HashMap<u64, ChatRoute>,
+}
+
+impl ChatRoutes {
+    pub fn new(default: ChatRoute) -> ChatRoutes {
+        ChatRoutes {
+            default,
+            guilds: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(openai_config: &OpenAiConfig, ogpt_async_client: OGptAsyncClient, settings: BackendSettings, cassette: Option<Arc<Cassette>>) -> Result<ChatRoutes, ServerError> {
+        let retry_policy = match openai_config.max_attempts {
+            Some(max_attempts) => RetryPolicy::default().max_attempts(max_attempts),
+            None => RetryPolicy::default(),
+        };
+
+        let mut backends: HashMap<BackendKind, Arc<dyn ChatBackend>> = HashMap::new();
+        backends.insert(BackendKind::OpenAi, Arc::new(ogpt_async_client));
+        if settings.uses(BackendKind::Anthropic) {
+            let api_key = settings.anthropic_api_key
+                .to_owned()
+                .ok_or_else(|| ServerError::ConfigError(String::from("ANTHROPIC_API_KEY is required for the anthropic backend")))?;
+            let mut backend = AnthropicBackend::new(api_key).retry_policy(retry_policy.clone());
+            if let Some(base_url) = &settings.anthropic_base_url {
+                backend = backend.base_url(base_url.to_owned());
+            }
+            if let Some(cassette) = &cassette {
+                backend = backend.cassette(cassette.clone());
+            }
+            backends.insert(BackendKind::Anthropic, Arc::new(backend));
+        }
+        if settings.uses(BackendKind::Ollama) {
+            let mut backend = OllamaBackend::new().retry_policy(retry_policy);
+            if let Some(base_url) = &settings.ollama_base_url {
+                backend = backend.base_url(base_url.to_owned());
+            }
+            if let Some(cassette) = &cassette {
+                backend = backend.cassette(cassette.clone());
+            }
+            backends.insert(BackendKind::Ollama, Arc::new(backend));
+        }
+
+        let default_backend = backends[&settings.default].clone();
+        let mut default_settings = openai_config.chat_settings(settings.default);
+        if openai_config.vision.is_none() {
+            default_settings.supports_vision = default_backend.capabilities(&default_settings.model).vision;
+        }
+        if settings.default != BackendKind::OpenAi && openai_config.summary_model.is_none() {
+            // The default summary model is an OpenAI one.
+            default_settings.summary_model = default_settings.model.to_owned();
+        }
+
+        let mut routes = ChatRoutes::new(ChatRoute { backend: default_backend, settings: default_settings });
+        for (guild_id, guild) in settings.guilds {
+            let backend = backends[&guild.kind].clone();
+            let mut chat_settings = ChatSettings::for_model(guild.model.to_owned());
+            if let Some(max_tokens) = openai_config.max_tokens {
+                chat_settings.max_tokens = max_tokens;
+            }
+            // Summaries are made by the guild's own model, the configured summary model may not be served by its backend.
+            chat_settings.summary_model = guild.model;
+            chat_settings.supports_vision = backend.capabilities(&chat_settings.model).vision;
+            routes = routes.guild(guild_id, ChatRoute { backend, settings: chat_settings });
+        }
+        Ok(routes)
+    }
+
+    pub fn guild(mut self, guild_id: u64, route: ChatRoute) -> ChatRoutes {
+        self.guilds.insert(guild_id, route);
+        self
+    }
+
+    pub fn for_guild(&self, guild_id: Option<u64>) -> &ChatRoute {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id))
+            .unwrap_or(&self.default)
+    }
+}
@@ -1,9 +1,9 @@
 use ogpt::model::chat_completions;
 use serenity::{async_trait, prelude::Context, model::prelude::Message};
 
-use crate::{ServerError, handler::Handler};
+use crate::{ServerError, handler::{Handler, image_urls}, knowledge};
 
-use super::{Command, CommandError};
+use super::{Command, reply_streaming};
 
 pub const PREFIX: &str = "!";
 pub const COMMAND: &str = "gpt";
@@ -33,35 +33,160 @@ impl Command for Gpt {
     }
 
     async fn matches(&self, msg: &Message) -> bool {
-        msg.content.starts_with(FULL_COMMAND)
+        question(&msg.content).is_some()
     }
 
     async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
-        let question = msg.content.strip_prefix(FULL_COMMAND).unwrap().trim();
+        let question = question(&msg.content).unwrap().trim();
 
-        let messages = vec![
-            chat_completions::Message {
-                role: chat_completions::Role::System,
-                content: handler.get_prompt(),
+        answer_question(self, handler, ctx, msg, question).await
+    }
+}
+
+// The question in a `!gpt` message, which must not match longer commands such as `!gpt-prompt`.
+pub fn question(content: &str) -> Option<&str> {
+    let question = content.strip_prefix(FULL_COMMAND)?;
+    if question.is_empty() || question.starts_with(char::is_whitespace) {
+        Some(question)
+    } else {
+        None
+    }
+}
+
+// Answers `question` in a streamed reply to `msg`, with excerpts from the guild's knowledge base and the
+// images attached to `msg`.
+pub async fn answer_question(command: &dyn Command, handler: &Handler, ctx: &Context, msg: &Message, question: &str) -> Result<(), ServerError> {
+    handler.moderate_input(ctx, msg, question).await?;
+
+    let mut prompt = handler.get_prompt();
+    let mut sources = vec![];
+    if let Some(guild_id) = msg.guild_id {
+        // The question is still answered without the knowledge base if searching it fails.
+        match handler.search_knowledge(ctx, msg, guild_id.0, question).await {
+            Ok(chunks) if !chunks.is_empty() => {
+                prompt = format!("{}\n\n{}", prompt, knowledge::context_prompt(&chunks));
+                sources = chunks.into_iter().map(|chunk| chunk.source).collect();
             },
-            chat_completions::Message {
-                role: chat_completions::Role::User,
-                content: question.to_owned(),
-            }
-        ];
-
-        let response = handler.get_gpt_response(messages).await?;
-
-        let message: &str = match ogpt::utils::get_chat_message(&response, 0) {
-            Some(message) => message,
-            None => {
-                return Err(ServerError::CommandError(
-                    CommandError::new(self.get_command().to_owned(), String::from("Failed to get 0th choice from response")))
-                ); 
-            }
-        };
-
-        msg.reply(&ctx.http, message).await?;
-        Ok(())
+            Ok(_) => {},
+            Err(err) => eprintln!("Error searching the knowledge base - {}", err),
+        }
+    }
+
+    let messages = vec![
+        chat_completions::Message::system(prompt),
+        handler.user_message(msg.guild_id, question.to_owned(), &image_urls(msg)),
+    ];
+
+    reply_streaming(command, handler, ctx, msg, messages, &sources).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ogpt::{client::{OGptAsyncClient, RetryPolicy}, error::OGptError, testing::{FakeBackend, MockResponse, MockServer}};
+    use serde_json::json;
+    use serenity::prelude::EventHandler;
+
+    use crate::storage::MemoryStore;
+    use crate::testing::{self, CHANNEL_ID, GUILD_ID, USER_ID};
+    use crate::usage::{Budgets, UsageFilter};
+
+    use super::{Command, Gpt};
+
+    #[tokio::test]
+    async fn matches_only_the_whole_command() {
+        assert!(Gpt.matches(&testing::message(1, "!gpt")).await);
+        assert!(Gpt.matches(&testing::message(1, "!gpt What is the capital of France?")).await);
+        assert!(!Gpt.matches(&testing::message(1, "!gpt-prompt Answer like a pirate")).await);
+        assert!(!Gpt.matches(&testing::message(1, "!gpt-kb list")).await);
+    }
+
+    #[tokio::test]
+    async fn answers_in_an_edited_reply() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.reply("The capital of France is Paris.");
+        let handler = testing::handler(backend.clone(), testing::chat_settings());
+        let discord = MockServer::start();
+        discord
+            .enqueue(testing::bot_message(10, "..."))
+            .enqueue(testing::bot_message(10, "The capital of France is Paris."));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!gpt What is the capital of France?")).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!((requests[0].method.as_str(), requests[0].path.as_str()), ("POST", format!("/api/v10/channels/{}/messages", CHANNEL_ID).as_str()));
+        let placeholder = requests[0].json().unwrap();
+        assert_eq!(placeholder["content"], "...");
+        assert_eq!(placeholder["message_reference"]["message_id"], "1");
+        assert_eq!((requests[1].method.as_str(), requests[1].path.as_str()), ("PATCH", format!("/api/v10/channels/{}/messages/10", CHANNEL_ID).as_str()));
+        // Edits are sent as multipart forms with the JSON in a `payload_json` part.
+        assert!(String::from_utf8_lossy(&requests[1].body).contains(r#""content":"The capital of France is Paris.""#));
+
+        let messages = &backend.chat_requests()[0].messages;
+        assert_eq!(messages[0].text(), handler.get_prompt());
+        assert_eq!(messages[1].text(), "What is the capital of France?");
+    }
+
+    #[tokio::test]
+    async fn reports_backend_errors_in_place_of_the_answer() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.fail(OGptError::InvalidResponse(String::from("overloaded")));
+        let handler = testing::handler(backend, testing::chat_settings());
+        let discord = MockServer::start();
+        discord
+            .enqueue(testing::bot_message(10, "..."))
+            .enqueue(MockResponse::new(204))
+            .enqueue(testing::bot_message(11, "error"));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!gpt What is the capital of France?")).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!((requests[1].method.as_str(), requests[1].path.as_str()), ("DELETE", format!("/api/v10/channels/{}/messages/10", CHANNEL_ID).as_str()));
+        assert!(requests[2].json().unwrap()["content"].as_str().unwrap().contains("overloaded"));
+    }
+
+    #[tokio::test]
+    async fn records_the_usage_streamed_after_the_answer() {
+        let openai = MockServer::start();
+        let chunk = |choices: serde_json::Value, usage: serde_json::Value| json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1700000000,
+            "model": "gpt-4o-mini",
+            "choices": choices,
+            "usage": usage,
+        }).to_string();
+        openai.enqueue(MockResponse::sse(&[
+            chunk(json!([{ "index": 0, "finish_reason": null, "delta": { "role": "assistant", "content": "Paris." } }]), json!(null)),
+            chunk(json!([{ "index": 0, "finish_reason": "stop", "delta": {} }]), json!(null)),
+            // OpenAI sends the usage in a chunk without choices after the one finishing the answer.
+            chunk(json!([]), json!({ "prompt_tokens": 30, "completion_tokens": 2, "total_tokens": 32 })),
+            String::from("[DONE]"),
+        ]));
+        let client = Arc::new(OGptAsyncClient::builder(String::from("test-key"))
+            .base_url(format!("{}/v1", openai.url()))
+            .retry_policy(RetryPolicy::none())
+            .stream_usage(true)
+            .build_async()
+            .unwrap());
+        let budgets = Budgets::new(&crate::config::UsageSettings { price_file: None, budget_file: None }).unwrap();
+        let handler = testing::handler_with(client.clone(), client, testing::chat_settings(), Arc::new(MemoryStore::new()), budgets);
+        let discord = MockServer::start();
+        discord
+            .enqueue(testing::bot_message(10, "..."))
+            .enqueue(testing::bot_message(10, "Paris."));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!gpt What is the capital of France?")).await;
+
+        assert_eq!(openai.requests()[0].json().unwrap()["stream_options"]["include_usage"], true);
+        assert!(String::from_utf8_lossy(&discord.requests()[1].body).contains(r#""content":"Paris.""#));
+        let records = handler.usage_ledger().records(&UsageFilter::default());
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].guild_id, records[0].channel_id, records[0].user_id), (Some(GUILD_ID), CHANNEL_ID, USER_ID));
+        assert_eq!((records[0].prompt_tokens, records[0].completion_tokens), (30, 2));
+        assert!(records[0].cost > 0.0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use ogpt::model::chat_completions;
+use serenity::{async_trait, prelude::Context, model::prelude::Message};
+use songbird::{Call, Event, EventContext, TrackEvent, tracks::PlayMode};
+use tokio::sync::Mutex;
+
+use crate::{ServerError, handler::Handler};
+
+use super::{Command, join_channel, stream_reply::{truncate, MAX_MESSAGE_LENGTH}};
+
+pub const PREFIX: &str = "!";
+pub const COMMAND: &str = "gpt-voice";
+pub const FULL_COMMAND: &str = "!gpt-voice";
+pub const ALIAS: &str = "!say";
+pub const DESCRIPTION: &str = "Ask a question and hear the answer in your voice channel. Music is paused while the bot speaks";
+pub const USAGE_EXAMPLE: &str = "!gpt-voice <question> or !say <question>";
+
+const VOICE_PROMPT: &str = "Your answer will be read aloud in a voice channel, so keep it short and conversational \
+and don't use markdown, lists, links or code.";
+
+#[derive(Debug)]
+pub struct GptVoice;
+
+#[async_trait]
+impl Command for GptVoice {
+    fn get_prefix(&self) -> &'static str {
+        PREFIX
+    }
+
+    fn get_command(&self) -> &'static str {
+        COMMAND
+    }
+
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_usage_example(&self) -> &'static str {
+        USAGE_EXAMPLE
+    }
+
+    async fn matches(&self, msg: &Message) -> bool {
+        question(&msg.content).is_some()
+    }
+
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+        let question = question(&msg.content).unwrap().trim();
+        if question.is_empty() {
+            return self.command_error(format!("Usage: {}", USAGE_EXAMPLE));
+        }
+        let guild_id = match msg.guild_id {
+            Some(id) => id,
+            None => return self.command_error(String::from("This command can only be used in a guild")),
+        };
+
+        // Joins the caller's channel before answering, so a caller outside of voice doesn't pay for an answer.
+        let (_, call) = join_channel(self, ctx, msg).await?;
+
+        let typing = msg.channel_id.start_typing(&ctx.http)?;
+        let answer = answer(handler, ctx, msg, question).await;
+        let _ = typing.stop();
+        let (answer, audio) = match answer? {
+            Some(answer) => answer,
+            None => return self.command_error(String::from("Failed to get 0th choice from response")),
+        };
+
+        msg.reply(&ctx.http, truncate(&answer, MAX_MESSAGE_LENGTH)).await?;
+
+        // ffmpeg reads the audio from a file, which is removed once the answer has been spoken.
+        let finished = SpeechFinished {
+            call: call.clone(),
+            speakers: handler.speakers(),
+            guild_id: guild_id.0,
+            path: std::env::temp_dir().join(format!("gpt-voice-{}.mp3", msg.id.0)),
+        };
+        let mut locked_call = call.lock().await;
+        if let Err(err) = speak(&mut locked_call, &finished, audio).await {
+            drop(locked_call);
+            finished.finish().await;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+// The question after `!gpt-voice` or `!say`, if the message starts with either as a whole word.
+fn question(content: &str) -> Option<&str> {
+    [FULL_COMMAND, ALIAS].into_iter().find_map(|command| {
+        let question = content.strip_prefix(command)?;
+        if question.is_empty() || question.starts_with(char::is_whitespace) {
+            Some(question)
+        } else {
+            None
+        }
+    })
+}
+
+// Plays `audio` in the call, pausing the music for it, and cleans up with `finished` once it ends.
+// songbird ends a track whose input fails too, so the End event also covers playback errors.
+async fn speak(call: &mut Call, finished: &SpeechFinished, audio: Vec<u8>) -> Result<(), ServerError> {
+    // Answers spoken over each other pause the music once, the first one checks whether it is playing.
+    if finished.speakers.start(finished.guild_id) {
+        if let Some(track) = call.queue().current() {
+            if track.get_info().await.is_ok_and(|info| info.playing == PlayMode::Play) {
+                call.queue().pause()?;
+                finished.speakers.paused_queue(finished.guild_id);
+            }
+        }
+    }
+
+    tokio::fs::write(&finished.path, audio).await?;
+    let source = songbird::ffmpeg(&finished.path).await?;
+    let track = call.play_source(source);
+    track.add_event(Event::Track(TrackEvent::End), finished.clone())?;
+    Ok(())
+}
+
+// A short spoken answer to `question` and its audio, or None if the model didn't answer.
+async fn answer(handler: &Handler, ctx: &Context, msg: &Message, question: &str) -> Result<Option<(String, Vec<u8>)>, ServerError> {
+    handler.moderate_input(ctx, msg, question).await?;
+
+    let messages = vec![
+        chat_completions::Message::system(format!("{}\n\n{}", handler.get_prompt(), VOICE_PROMPT)),
+        chat_completions::Message::user(question.to_owned()),
+    ];
+    let response = handler.get_gpt_response(ctx, msg, messages).await?;
+    let answer = ogpt::utils::get_chat_message(&response, 0).unwrap_or_default().trim().to_owned();
+    if answer.is_empty() {
+        return Ok(None);
+    }
+    let answer = handler.moderate_output(ctx, msg, answer).await?;
+
+    let audio = handler.speak(ctx, msg, &answer).await?;
+    Ok(Some((answer, audio)))
+}
+
+// The answers being spoken in each guild, so that music paused for answers spoken over each other resumes
+// only once the last of them has ended.
+#[derive(Default)]
+pub struct Speakers {
+    guilds: std::sync::Mutex<HashMap<u64, Speaking>>,
+}
+
+#[derive(Default)]
+struct Speaking {
+    answers: usize,
+    resume_queue: bool,
+}
+
+impl Speakers {
+    // Counts an answer starting in `guild_id`, returning whether it is the only one.
+    fn start(&self, guild_id: u64) -> bool {
+        let mut guilds = self.guilds.lock().unwrap();
+        let speaking = guilds.entry(guild_id).or_default();
+        speaking.answers += 1;
+        speaking.answers == 1
+    }
+
+    fn paused_queue(&self, guild_id: u64) {
+        if let Some(speaking) = self.guilds.lock().unwrap().get_mut(&guild_id) {
+            speaking.resume_queue = true;
+        }
+    }
+
+    // Counts an answer ending in `guild_id`, returning whether it was the last one and music was paused for it.
+    fn finish(&self, guild_id: u64) -> bool {
+        let mut guilds = self.guilds.lock().unwrap();
+        match guilds.get_mut(&guild_id) {
+            Some(speaking) if speaking.answers > 1 => {
+                speaking.answers -= 1;
+                false
+            },
+            Some(_) => guilds.remove(&guild_id).is_some_and(|speaking| speaking.resume_queue),
+            None => false,
+        }
+    }
+}
+
+// Resumes the music paused for the answers once the last one ends and removes the answer's audio file.
+#[derive(Clone)]
+struct SpeechFinished {
+    call: Arc<Mutex<Call>>,
+    speakers: Arc<Speakers>,
+    guild_id: u64,
+    path: PathBuf,
+}
+
+impl SpeechFinished {
+    async fn finish(&self) {
+        // Locking the call first orders this after an answer that is starting to speak.
+        let call = self.call.lock().await;
+        if self.speakers.finish(self.guild_id) {
+            if let Err(err) = call.queue().resume() {
+                eprintln!("Error resuming the queue - {}", err);
+            }
+        }
+        drop(call);
+        match tokio::fs::remove_file(&self.path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => eprintln!("Error removing speech file - {}", err),
+            _ => {},
+        }
+    }
+}
+
+#[async_trait]
+impl songbird::EventHandler for SpeechFinished {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        self.finish().await;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Speakers;
+
+    #[test]
+    fn resumes_the_music_after_the_last_overlapping_answer() {
+        let speakers = Speakers::default();
+
+        assert!(speakers.start(1));
+        speakers.paused_queue(1);
+        assert!(!speakers.start(1));
+        assert!(speakers.start(2));
+
+        assert!(!speakers.finish(1));
+        assert!(speakers.finish(1));
+        // Nothing was paused for the answer in the other guild.
+        assert!(!speakers.finish(2));
+        assert!(speakers.start(1));
+    }
+}
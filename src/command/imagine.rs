@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+
+use serenity::{async_trait, builder::CreateEmbed, prelude::Context, model::prelude::{AttachmentType, Message}};
+
+use crate::{ServerError, handler::Handler};
+
+use super::Command;
+
+pub const PREFIX: &str = "!";
+pub const COMMAND: &str = "imagine";
+pub const FULL_COMMAND: &str = "!imagine";
+pub const DESCRIPTION: &str = "Generate an image from a description";
+pub const USAGE_EXAMPLE: &str = "!imagine <description>";
+
+#[derive(Debug)]
+pub struct Imagine;
+
+#[async_trait]
+impl Command for Imagine {
+    fn get_prefix(&self) -> &'static str {
+        PREFIX
+    }
+
+    fn get_command(&self) -> &'static str {
+        COMMAND
+    }
+
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_usage_example(&self) -> &'static str {
+        USAGE_EXAMPLE
+    }
+
+    async fn matches(&self, msg: &Message) -> bool {
+        msg.content.starts_with(FULL_COMMAND)
+    }
+
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+        let prompt = msg.content.strip_prefix(FULL_COMMAND).unwrap().trim();
+        if prompt.is_empty() {
+            return self.command_error(format!("Usage: {}", USAGE_EXAMPLE));
+        }
+
+        handler.moderate_input(ctx, msg, prompt).await?;
+
+        // Generating takes a while, show that the bot is working on it.
+        let typing = msg.channel_id.start_typing(&ctx.http)?;
+        let response = handler.generate_images(ctx, msg, prompt.to_owned()).await;
+        let _ = typing.stop();
+        let response = response?;
+
+        let mut files = vec![];
+        let mut embeds = vec![];
+        for (i, image) in response.data.iter().enumerate() {
+            let mut embed = CreateEmbed::default();
+            embed.description(image.revised_prompt.as_deref().unwrap_or(prompt));
+
+            // Base64 images are uploaded as attachments, only URL responses are linked.
+            match (image.bytes()?, &image.url) {
+                (Some(bytes), _) => {
+                    let filename = format!("image-{}.png", i + 1);
+                    embed.image(format!("attachment://{}", filename));
+                    files.push(AttachmentType::Bytes { data: Cow::Owned(bytes), filename });
+                },
+                (None, Some(url)) => {
+                    embed.image(url);
+                },
+                (None, None) => continue,
+            }
+            embeds.push(embed);
+        }
+
+        if embeds.is_empty() {
+            return self.command_error(String::from("No images were generated"));
+        }
+
+        msg.channel_id.send_message(&ctx.http, |m| {
+            m.reference_message(msg)
+                .add_files(files)
+                .set_embeds(embeds)
+        }).await?;
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
-use tokio::time::{Duration, sleep};
-use serenity::{async_trait, prelude::Context, model::prelude::Message};
+use std::sync::Arc;
+
+use tokio::{sync::Mutex, time::{Duration, sleep}};
+use serenity::{async_trait, prelude::Context, model::prelude::{ChannelId, GuildId, Message}};
+use songbird::Call;
 
 use crate::{ServerError, handler::Handler};
 
-use super::Command;
+use super::{Command, CommandError};
 
 pub const PREFIX: &str = "!";
 pub const COMMAND: &str = "join";
@@ -14,42 +17,49 @@ pub const USAGE_EXAMPLE: &str = "!join";
 #[derive(Debug)]
 pub struct Join;
 
-pub async fn join_channel(command: &dyn Command, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
-    match msg.guild(&ctx.cache) {
+// Joins the voice channel the author of `msg` is in, returning its id and the call in it.
+pub async fn join_channel(command: &dyn Command, ctx: &Context, msg: &Message) -> Result<(ChannelId, Arc<Mutex<Call>>), ServerError> {
+    let (guild_id, channel_id) = match msg.guild(&ctx.cache) {
         Some(guild) => {
-            let guild_id = guild.id;
-
             let channel_id = guild
                 .voice_states
                 .get(&msg.author.id)
                 .and_then(|voice_state| voice_state.channel_id);
+            (guild.id, channel_id)
+        },
+        None => return Err(join_error(command, String::from("Failed to get channel details"))),
+    };
+
+    match channel_id {
+        Some(channel_id) => Ok((channel_id, join(ctx, guild_id, channel_id).await?)),
+        None => Err(join_error(command, "You must be in a voice channel to use this command".to_owned())),
+    }
+}
 
-            match channel_id {
-                Some(channel_id) => {
-                    let manager = songbird::get(ctx).await.expect("Songbird not initialized").clone();
-                    
-                    manager.join(guild_id, channel_id).await.1?;
-                    tokio::spawn(async move {
-                        let backoff_seconds = 300;
-                        let handler = manager.get(guild_id).expect("No handler found");
-
-                        loop {
-                            sleep(Duration::from_secs(backoff_seconds)).await;
-                            if handler.lock().await.queue().is_empty() {
-                                manager.leave(guild_id).await.expect("Cannot leave");
-                                break;
-                            }
-                        }
-
-                    });
-                },
-                None => return command.command_error("You must be in a voice channel to use this command".to_owned()),
+// Joins `channel_id` and leaves it again once nothing has been queued for a while. Returns the call.
+pub async fn join(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Result<Arc<Mutex<Call>>, ServerError> {
+    let manager = songbird::get(ctx).await.expect("Songbird not initialized").clone();
+
+    let (call, joined) = manager.join(guild_id, channel_id).await;
+    joined?;
+    let handler = call.clone();
+    tokio::spawn(async move {
+        let backoff_seconds = 300;
+
+        loop {
+            sleep(Duration::from_secs(backoff_seconds)).await;
+            if handler.lock().await.queue().is_empty() {
+                manager.leave(guild_id).await.expect("Cannot leave");
+                break;
             }
+        }
 
-        },
-        None => return command.command_error(String::from("Failed to get channel details")),
-    }
-    Ok(())
+    });
+    Ok(call)
+}
+
+fn join_error(command: &dyn Command, err: String) -> ServerError {
+    ServerError::CommandError(CommandError::new(format!("{}{}", command.get_prefix(), command.get_command()), err))
 }
 
 #[async_trait]
@@ -75,6 +85,7 @@ impl Command for Join {
     }
 
     async fn handle(&self, _: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
-        join_channel(self, ctx, msg).await
+        join_channel(self, ctx, msg).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,246 @@
+use serenity::{async_trait, prelude::Context, model::prelude::{Attachment, GuildId, Message}};
+
+use crate::{ServerError, handler::Handler};
+
+use super::{Command, CommandError, permissions::can_manage_guild};
+
+pub const PREFIX: &str = "!";
+pub const COMMAND: &str = "gpt-kb";
+pub const FULL_COMMAND: &str = "!gpt-kb";
+pub const DESCRIPTION: &str = "Manage this server's knowledge base used to answer !gpt questions (admins only). \
+Add attached .txt/.md files or the replied-to message, import the channel's pins, list, remove a source or clear it";
+pub const USAGE_EXAMPLE: &str = "!gpt-kb add | pins | list | remove <source> | clear";
+
+// Larger uploads are rejected rather than filling the index with a single document.
+const MAX_DOCUMENT_BYTES: u64 = 1_000_000;
+const TEXT_EXTENSIONS: [&str; 3] = [".txt", ".md", ".markdown"];
+// Leaves room for the "...and N more" line within Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1900;
+
+#[derive(Debug)]
+pub struct GptKnowledge;
+
+#[async_trait]
+impl Command for GptKnowledge {
+    fn get_prefix(&self) -> &'static str {
+        PREFIX
+    }
+
+    fn get_command(&self) -> &'static str {
+        COMMAND
+    }
+
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_usage_example(&self) -> &'static str {
+        USAGE_EXAMPLE
+    }
+
+    async fn matches(&self, msg: &Message) -> bool {
+        msg.content.starts_with(FULL_COMMAND)
+    }
+
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+        let guild_id = match msg.guild_id {
+            Some(guild_id) => guild_id.0,
+            None => return self.command_error(String::from("The knowledge base is only available in servers")),
+        };
+
+        if !can_manage_guild(ctx, msg, GuildId(guild_id)).await? {
+            return self.command_error(String::from("Only members who can manage the server can change its knowledge base"));
+        }
+
+        let args = msg.content.strip_prefix(FULL_COMMAND).unwrap().trim();
+        let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let knowledge_base = handler.knowledge_base();
+
+        let response = match subcommand {
+            "add" => {
+                let mut added = vec![];
+                for attachment in &msg.attachments {
+                    let text = Self::download_text(attachment).await?;
+                    let chunks = handler.add_knowledge(ctx, msg, guild_id, &attachment.filename, &text).await?;
+                    added.push(format!("{} ({} chunks)", attachment.filename, chunks));
+                }
+                if let Some(referenced) = &msg.referenced_message {
+                    let source = referenced.link();
+                    let chunks = handler.add_knowledge(ctx, msg, guild_id, &source, &referenced.content).await?;
+                    added.push(format!("{} ({} chunks)", source, chunks));
+                }
+
+                if added.is_empty() {
+                    return self.command_error(String::from("Attach .txt or .md files or reply to the message to add"));
+                }
+                format!("Added {}", added.join(", "))
+            },
+            "pins" => {
+                let pins = msg.channel_id.pins(&ctx.http).await?;
+                let pins: Vec<&Message> = pins.iter().filter(|pin| !pin.content.trim().is_empty()).collect();
+                let mut chunks = 0;
+                for pin in &pins {
+                    chunks += handler.add_knowledge(ctx, msg, guild_id, &pin.link(), &pin.content).await?;
+                }
+                format!("Added {} pinned messages ({} chunks)", pins.len(), chunks)
+            },
+            "list" => {
+                let sources = knowledge_base.sources(guild_id).await?;
+                if sources.is_empty() {
+                    String::from("The knowledge base is empty")
+                } else {
+                    list_lines(sources
+                        .iter()
+                        .map(|(source, chunks)| format!("{} ({} chunks)", source, chunks))
+                        .collect())
+                }
+            },
+            "remove" if !rest.is_empty() => {
+                match knowledge_base.remove_source(guild_id, rest.trim()).await? {
+                    0 => return self.command_error(format!("No source named {}", rest.trim())),
+                    removed => format!("Removed {} ({} chunks)", rest.trim(), removed),
+                }
+            },
+            "clear" => {
+                knowledge_base.clear(guild_id).await?;
+                String::from("Knowledge base cleared")
+            },
+            _ => return self.command_error(format!("Usage: {}", USAGE_EXAMPLE)),
+        };
+
+        msg.channel_id.say(&ctx.http, response).await?;
+        Ok(())
+    }
+}
+
+impl GptKnowledge {
+    async fn download_text(attachment: &Attachment) -> Result<String, ServerError> {
+        let filename = attachment.filename.to_lowercase();
+        let is_text = TEXT_EXTENSIONS.iter().any(|extension| filename.ends_with(extension))
+            || attachment.content_type.as_deref().is_some_and(|content_type| content_type.starts_with("text/"));
+        if !is_text {
+            return Err(knowledge_error(format!("{} is not a .txt or .md file", attachment.filename)));
+        }
+        if attachment.size > MAX_DOCUMENT_BYTES {
+            return Err(knowledge_error(format!("{} is larger than {} bytes", attachment.filename, MAX_DOCUMENT_BYTES)));
+        }
+
+        let bytes = attachment.download().await?;
+        String::from_utf8(bytes).map_err(|_| knowledge_error(format!("{} is not valid UTF-8 text", attachment.filename)))
+    }
+}
+
+fn knowledge_error(err: String) -> ServerError {
+    ServerError::CommandError(CommandError::new(String::from(FULL_COMMAND), err))
+}
+
+// Lists as many lines as fit in one Discord message.
+fn list_lines(lines: Vec<String>) -> String {
+    let mut list = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if list.len() + line.len() + 1 > MAX_LIST_LENGTH {
+            list.push_str(&format!("...and {} more", lines.len() - i));
+            break;
+        }
+        list.push_str(line);
+        list.push('\n');
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ogpt::testing::{FakeBackend, MockResponse, MockServer};
+    use serde_json::{json, Value};
+    use serenity::prelude::EventHandler;
+
+    use crate::testing::{self, GUILD_ID, USER_ID};
+
+    // The server as Discord returns it, without the roles a member's permissions would come from.
+    fn partial_guild(owner_id: u64) -> Value {
+        json!({
+            "id": GUILD_ID.to_string(),
+            "name": "Test server",
+            "icon": null,
+            "splash": null,
+            "discovery_splash": null,
+            "owner_id": owner_id.to_string(),
+            "afk_channel_id": null,
+            "afk_timeout": 300,
+            "verification_level": 0,
+            "default_message_notifications": 0,
+            "explicit_content_filter": 0,
+            "roles": [{
+                "id": GUILD_ID.to_string(),
+                "name": "@everyone",
+                "color": 0,
+                "hoist": false,
+                "position": 0,
+                "permissions": "0",
+                "managed": false,
+                "mentionable": false,
+                "icon": null,
+                "unicode_emoji": null,
+            }],
+            "emojis": [],
+            "features": [],
+            "mfa_level": 0,
+            "system_channel_id": null,
+            "system_channel_flags": 0,
+            "rules_channel_id": null,
+            "vanity_url_code": null,
+            "description": null,
+            "banner": null,
+            "premium_tier": 0,
+            "premium_subscription_count": 0,
+            "preferred_locale": "en-US",
+            "public_updates_channel_id": null,
+            "nsfw_level": 0,
+            "premium_progress_bar_enabled": false,
+            "stickers": [],
+        })
+    }
+
+    #[tokio::test]
+    async fn asks_discord_for_permissions_missing_from_the_cache() {
+        let handler = testing::handler(Arc::new(FakeBackend::new()), testing::chat_settings());
+        let discord = MockServer::start();
+        discord
+            .enqueue(MockResponse::error(404, "not_found", "Unknown Member"))
+            .enqueue(MockResponse::json(200, partial_guild(USER_ID)))
+            .enqueue(testing::bot_message(10, ""));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!gpt-kb list")).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].path, format!("/api/v10/guilds/{}", GUILD_ID));
+        assert_eq!(requests[2].json().unwrap()["content"], "The knowledge base is empty");
+    }
+
+    #[tokio::test]
+    async fn refuses_members_who_cannot_manage_the_server() {
+        let handler = testing::handler(Arc::new(FakeBackend::new()), testing::chat_settings());
+        let discord = MockServer::start();
+        discord
+            .enqueue(MockResponse::error(404, "not_found", "Unknown Member"))
+            .enqueue(MockResponse::json(200, partial_guild(USER_ID + 1)))
+            .enqueue(MockResponse::json(200, json!({
+                "user": { "id": USER_ID.to_string(), "username": "user", "discriminator": "0001", "avatar": null },
+                "roles": [],
+                "joined_at": "2024-01-01T00:00:00.000Z",
+                "deaf": false,
+                "mute": false,
+            })))
+            .enqueue(testing::bot_message(10, ""));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!gpt-kb clear")).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 4);
+        let error = requests[3].json().unwrap();
+        assert!(error["content"].as_str().unwrap().contains("Only members who can manage the server"));
+    }
+}
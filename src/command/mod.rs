@@ -11,6 +11,13 @@ mod skip;
 mod pause;
 mod resume;
 mod stop;
+mod stream_reply;
+mod knowledge;
+mod imagine;
+mod voice_message;
+mod gpt_voice;
+mod usage;
+mod permissions;
 
 pub use command::Command;
 pub use error::CommandError;
@@ -19,25 +26,39 @@ use gpt::Gpt;
 use reply::GptReply;
 use help::Help;
 use prompt::GptPrompt;
+use knowledge::GptKnowledge;
+use imagine::Imagine;
+use voice_message::VoiceMessage;
+use gpt_voice::GptVoice;
+use usage::UsageReport;
 use play::Play;
 use join::Join;
 use skip::Skip;
 use pause::Pause;
 use resume::Resume;
 use stop::Stop;
+pub use gpt::{PREFIX, question as gpt_question};
+pub use gpt_voice::Speakers;
 pub use join::join_channel;
+pub use play::restore_music;
+pub use stream_reply::reply_streaming;
 
 static COMMANDS: &'static [&dyn Command] = &[
     &Ping,
+    &GptKnowledge,
+    &GptVoice,
     &Gpt,
     &Help,
     &GptPrompt,
+    &Imagine,
+    &UsageReport,
     &Join,
     &Play,
     &Pause,
     &Resume,
     &Stop,
     &Skip,
+    &VoiceMessage,
     &GptReply, // This matches all messages not sent by the bot, so it should be last
 ];
 
@@ -46,7 +67,12 @@ static COMMANDS_HELP: &'static [&dyn Command] = &[
     &Ping,
     &GptPrompt,
     &Gpt,
+    &GptKnowledge,
     &GptReply,
+    &VoiceMessage,
+    &Imagine,
+    &GptVoice,
+    &UsageReport,
     &Join,
     &Play,
     &Pause,
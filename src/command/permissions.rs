@@ -0,0 +1,14 @@
+use serenity::{prelude::{Context, SerenityError}, model::{Permissions, prelude::{GuildId, Message}}};
+
+// Whether the author of `msg` can manage the server, asking Discord for the server when it isn't cached.
+pub async fn can_manage_guild(ctx: &Context, msg: &Message, guild_id: GuildId) -> Result<bool, SerenityError> {
+    let cached = match msg.member(ctx).await {
+        Ok(member) => member.permissions(&ctx.cache).ok(),
+        Err(_) => None,
+    };
+    let permissions = match cached {
+        Some(permissions) => permissions,
+        None => guild_id.to_partial_guild(&ctx.http).await?.member_permissions(ctx, msg.author.id).await?,
+    };
+    Ok(permissions.intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD))
+}
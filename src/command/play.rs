@@ -1,9 +1,14 @@
-use serenity::{async_trait, prelude::Context, model::prelude::Message};
+use std::sync::Arc;
+
+use serenity::{async_trait, prelude::Context, model::prelude::{ChannelId, GuildId, Message}};
+use songbird::{Call, Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent};
 use songbird::input::{Restartable, Input};
 
 use crate::{ServerError, handler::Handler};
+use crate::storage::{self, MusicState, Store};
 
 use super::{Command, join_channel};
+use super::join::join;
 
 pub const PREFIX: &str = "!";
 pub const COMMAND: &str = "play";
@@ -36,30 +41,15 @@ impl Command for Play {
         msg.content.starts_with(FULL_COMMAND)
     }
 
-    async fn handle(&self, _: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
-        join_channel(self, ctx, msg).await?;
-
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
         let search_string = msg.content.strip_prefix(FULL_COMMAND).unwrap().trim().to_owned();
         let guild_id = match msg.guild_id {
             Some(id) => id,
             None => return self.command_error(String::from("This command can only be used in a guild")),
         };
 
-        let manager = songbird::get(ctx).await;
-        
-        if manager.is_none() {
-            return self.command_error(String::from("Songbird not initialized"));
-        }
-
-        let manager = manager.unwrap().clone();
-        let handler = manager.get(guild_id);
-        
-        if handler.is_none() {
-            return self.command_error(String::from("No handler found"));
-        }
-
-        let handler = handler.unwrap();
-        let mut handler = handler.lock().await;
+        let (channel_id, call) = join_channel(self, ctx, msg).await?;
+        let mut call = call.lock().await;
 
         let source =  Restartable::ytdl_search(search_string, true).await?;
         let source: Input = source.into();
@@ -67,7 +57,9 @@ impl Command for Play {
         match source.metadata.source_url.clone() {
             Some(url) => {
                 msg.channel_id.say(&ctx.http, format!("Added to the queue {}", url)).await?;
-                handler.enqueue_source(source);
+                let stored_url = url.clone();
+                storage::blocking(handler.store(), move |store| store.push_music_track(guild_id.0, channel_id.0, &stored_url)).await?;
+                enqueue(handler.store(), &mut call, guild_id.0, source, url).await?;
             },
             None => return self.command_error(String::from("No source url found")),
         }
@@ -75,3 +67,77 @@ impl Command for Play {
         Ok(())
     }
 }
+
+// Queues `source` and removes it from the stored queue once it has finished, failed or was skipped.
+// songbird ends tracks whose input fails with the same event as finished ones, but one that failed
+// before its event was added never sends it, so it is removed here.
+async fn enqueue(store: &Arc<dyn Store>, call: &mut Call, guild_id: u64, source: Input, url: String) -> Result<(), ServerError> {
+    let track = call.enqueue_source(source);
+    let ended = TrackEnded { store: store.clone(), guild_id, url };
+    if let Err(err) = track.add_event(Event::Track(TrackEvent::End), ended.clone()) {
+        ended.remove().await;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct TrackEnded {
+    store: Arc<dyn Store>,
+    guild_id: u64,
+    url: String,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackEnded {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        self.remove().await;
+        None
+    }
+}
+
+impl TrackEnded {
+    async fn remove(&self) {
+        let (guild_id, url) = (self.guild_id, self.url.clone());
+        if let Err(err) = storage::blocking(&self.store, move |store| store.remove_music_track(guild_id, &url)).await {
+            eprintln!("Error removing finished track {} from the store - {}", self.url, err);
+        }
+    }
+}
+
+// Rejoins the voice channels the bot was playing in before it restarted and queues their tracks again.
+pub async fn restore_music(handler: &Handler, ctx: &Context) {
+    let states = match storage::blocking(handler.store(), |store| store.music_states()).await {
+        Ok(states) => states,
+        Err(err) => {
+            eprintln!("Error loading the stored music queues - {}", err);
+            return;
+        }
+    };
+
+    for state in states {
+        let guild_id = state.guild_id;
+        if let Err(err) = restore_queue(handler, ctx, state).await {
+            eprintln!("Error restoring the music queue of guild {} - {}", guild_id, err);
+        }
+    }
+}
+
+async fn restore_queue(handler: &Handler, ctx: &Context, state: MusicState) -> Result<(), ServerError> {
+    let call = join(ctx, GuildId(state.guild_id), ChannelId(state.channel_id)).await?;
+    let mut call = call.lock().await;
+
+    for url in state.queue {
+        match Restartable::ytdl(url.clone(), true).await {
+            Ok(source) => enqueue(handler.store(), &mut call, state.guild_id, source.into(), url).await?,
+            // A track that can't be loaded anymore would fail on every restart.
+            Err(err) => {
+                eprintln!("Error loading track {} - {}", url, err);
+                let guild_id = state.guild_id;
+                storage::blocking(handler.store(), move |store| store.remove_music_track(guild_id, &url)).await?;
+            }
+        }
+    }
+    println!("Restored the music queue of guild {}", state.guild_id);
+    Ok(())
+}
@@ -37,7 +37,7 @@ impl Command for GptPrompt {
 
     async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
         let prompt = msg.content.strip_prefix(FULL_COMMAND).unwrap().trim();
-        handler.set_prompt(prompt.to_string());
+        handler.set_prompt(prompt.to_string()).await?;
         msg.channel_id.say(&ctx.http, "Prompt set").await?;
         Ok(())
     }
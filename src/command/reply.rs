@@ -1,9 +1,9 @@
 use ogpt::model::chat_completions;
 use serenity::{async_trait, prelude::Context, model::prelude::Message};
 
-use crate::{ServerError, handler::Handler, handler::MessageLite};
+use crate::{ServerError, handler::Handler, handler::MessageLite, handler::ConversationTurn, handler::summary_message};
 
-use super::{Command, gpt};
+use super::{Command, gpt, reply_streaming};
 
 pub const DESCRIPTION: &str = "After getting a response from ChatGPT, you can reply to continue the conversation";
 pub const USAGE_EXAMPLE: &str = "<reply>";
@@ -34,7 +34,7 @@ impl Command for GptReply {
     }
 
     async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
-        let mut msg_list: Vec<chat_completions::Message> = vec![];
+        let mut turns: Vec<ConversationTurn> = vec![];
         let mut cur_msg_option: Option<MessageLite> = Some(MessageLite::from_msg(msg, ctx));
         let mut is_valid: bool = false;
         let mut expecting_own_msg = false;
@@ -42,59 +42,50 @@ impl Command for GptReply {
         while let Some(cur_msg) = cur_msg_option {
             let is_own = cur_msg.is_own;
             if is_own != expecting_own_msg || (!is_own && msg.author.bot) { break; }
+
+            // Everything from here back has already been summarized.
+            if let Some(summary) = handler.get_cached_summary(cur_msg.id) {
+                turns.push(ConversationTurn { message_id: None, message: summary_message(&summary) });
+                is_valid = true;
+                break;
+            }
+
             let first_question = cur_msg.content.strip_prefix(gpt::FULL_COMMAND);
             match first_question {
                 Some(first_question) => {
-                    msg_list.push(
-                        chat_completions::Message {
-                            role: chat_completions::Role::User,
-                            content: first_question.to_string()
-                        }
-                    );
+                    turns.push(ConversationTurn {
+                        message_id: Some(cur_msg.id),
+                        message: handler.user_message(msg.guild_id, first_question.to_string(), &cur_msg.image_urls),
+                    });
                     is_valid = true;
                     cur_msg_option = None;
                 },
                 None => {
-                    let role = if is_own {
-                        chat_completions::Role::Assistant
+                    let message = if is_own {
+                        chat_completions::Message::assistant(cur_msg.content.to_string())
                     } else {
-                        chat_completions::Role::User
+                        handler.user_message(msg.guild_id, cur_msg.content.to_string(), &cur_msg.image_urls)
                     };
 
-                    msg_list.push(
-                        chat_completions::Message {
-                            role,
-                            content: cur_msg.content.to_string(),
-                        }
-                    );
+                    turns.push(ConversationTurn { message_id: Some(cur_msg.id), message });
 
-                    cur_msg_option = handler.get_referenced_from_cache(&cur_msg);
+                    cur_msg_option = handler.get_referenced_from_cache(&cur_msg).await;
                 },
             }
             expecting_own_msg = !expecting_own_msg;
         }
 
-        msg_list.push(
-            chat_completions::Message {
-                role: chat_completions::Role::System,
-                content: handler.get_prompt(),
-            }
+        turns.push(
+            ConversationTurn::new(None, chat_completions::Role::System, handler.get_prompt())
         );
 
         if is_valid {
-            msg_list.reverse();
-            
-            let response = handler.get_gpt_response(msg_list).await?;
-
-            let message: &str = match ogpt::utils::get_chat_message(&response, 0) {
-                Some(message) => message,
-                None => {
-                    return self.command_error(String::from("Failed to get 0th choice from response"));
-                }
-            };
+            handler.moderate_input(ctx, msg, &msg.content).await?;
+            turns.reverse();
 
-            msg.reply(&ctx.http, message).await?;
+            let msg_list = handler.compact_conversation(ctx, msg, turns).await?;
+            reply_streaming(self, handler, ctx, msg, msg_list, &[]).await?;
         }
         Ok(())
     }
-}
+}
\ No newline at end of file
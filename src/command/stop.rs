@@ -1,6 +1,6 @@
 use serenity::{async_trait, prelude::Context, model::prelude::Message};
 
-use crate::{ServerError, handler::Handler};
+use crate::{ServerError, handler::Handler, storage};
 
 use super::Command;
 
@@ -35,8 +35,9 @@ impl Command for Stop {
         msg.content == FULL_COMMAND
     }
 
-    async fn handle(&self, _: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
         let guild_id = msg.guild_id.unwrap();
+        storage::blocking(handler.store(), move |store| store.clear_music(guild_id.0)).await?;
 
         let manager = songbird::get(ctx).await;
         if manager.is_none() {
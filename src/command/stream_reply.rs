@@ -0,0 +1,187 @@
+use futures_util::StreamExt;
+use ogpt::{model::chat_completions, utils::ChatStreamAccumulator};
+use serenity::{prelude::Context, model::prelude::Message};
+use tokio::time::{Duration, Instant};
+
+use crate::{ServerError, handler::Handler, knowledge};
+
+use super::Command;
+
+pub const STREAM_PLACEHOLDER: &str = "...";
+const STREAM_INTERRUPTED: &str = "*(The answer was interrupted by an error.)*";
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+// Discord allows roughly five edits per five seconds per channel, so stay comfortably below that.
+const EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+// Posts a placeholder reply to `msg` and keeps editing it as the completion streams in. Answers
+// longer than a single Discord message continue in follow-up replies. `sources` are the sources of the
+// knowledge base excerpts in the prompt, the ones the answer cites are listed at its end.
+pub async fn reply_streaming(command: &dyn Command, handler: &Handler, ctx: &Context, msg: &Message, messages: Vec<chat_completions::Message>, sources: &[String]) -> Result<(), ServerError> {
+    if handler.moderates_output(msg) {
+        return reply_moderated(command, handler, ctx, msg, messages, sources).await;
+    }
+
+    let mut reply = msg.reply(&ctx.http, STREAM_PLACEHOLDER).await?;
+
+    let mut stream = match handler.get_gpt_response_stream(ctx, msg, messages).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            if let Err(err) = reply.delete(&ctx.http).await {
+                eprintln!("Error deleting placeholder message - {}", err);
+            }
+            return Err(err);
+        }
+    };
+
+    let mut accumulator = ChatStreamAccumulator::new();
+    let mut committed = 0;
+    let mut rendered = String::new();
+    let mut last_edit = Instant::now();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                abandon_reply(&mut reply, ctx, &rendered).await;
+                return Err(err.into());
+            }
+        };
+        accumulator.push(&chunk);
+        // The usage comes in a chunk of its own after the one that finishes the answer, so the stream
+        // is read to its end. The finished answer is posted once, below.
+        if accumulator.is_finished(0) || last_edit.elapsed() < EDIT_INTERVAL {
+            continue;
+        }
+
+        let content = accumulator.content(0).unwrap_or_default();
+        if flush_overflow(handler, ctx, msg, &mut reply, content, &mut committed).await? {
+            rendered.clear();
+        }
+
+        let pending = content[committed..].trim();
+        if !pending.is_empty() && pending != rendered {
+            edit_reply(&mut reply, ctx, pending).await?;
+            rendered = pending.to_owned();
+        }
+        last_edit = Instant::now();
+    }
+
+    let mut content = match accumulator.content(0) {
+        Some(content) if !content.trim().is_empty() => content.to_owned(),
+        _ => {
+            if let Err(err) = reply.delete(&ctx.http).await {
+                eprintln!("Error deleting placeholder message - {}", err);
+            }
+            return command.command_error(String::from("Failed to get 0th choice from response"));
+        }
+    };
+
+    if let Some(footer) = knowledge::citation_footer(&content, sources) {
+        content.push_str("\n\n");
+        content.push_str(&footer);
+    }
+
+    flush_overflow(handler, ctx, msg, &mut reply, &content, &mut committed).await?;
+
+    let remainder = content[committed..].trim();
+    if remainder.is_empty() {
+        reply.delete(&ctx.http).await?;
+    } else {
+        edit_reply(&mut reply, ctx, remainder).await?;
+        handler.update_cached_message(&reply, ctx).await;
+    }
+    Ok(())
+}
+
+// Answers that are moderated can't be shown before they are complete, so they are posted in one go
+// once they have passed the guild's moderation policy.
+async fn reply_moderated(command: &dyn Command, handler: &Handler, ctx: &Context, msg: &Message, messages: Vec<chat_completions::Message>, sources: &[String]) -> Result<(), ServerError> {
+    let typing = msg.channel_id.start_typing(&ctx.http)?;
+    let response = handler.get_gpt_response(ctx, msg, messages).await;
+    let _ = typing.stop();
+    let response = response?;
+
+    let content = ogpt::utils::get_chat_message(&response, 0).unwrap_or_default().trim().to_owned();
+    if content.is_empty() {
+        return command.command_error(String::from("Failed to get 0th choice from response"));
+    }
+
+    let mut content = handler.moderate_output(ctx, msg, content).await?;
+    if let Some(footer) = knowledge::citation_footer(&content, sources) {
+        content.push_str("\n\n");
+        content.push_str(&footer);
+    }
+
+    let mut remainder = content.as_str();
+    while !remainder.is_empty() {
+        let end = if remainder.len() > MAX_MESSAGE_LENGTH { split_point(remainder) } else { remainder.len() };
+        let part = remainder[..end].trim();
+        if !part.is_empty() {
+            msg.reply(&ctx.http, part).await?;
+        }
+        remainder = &remainder[end..];
+    }
+    Ok(())
+}
+
+// Finalizes full-length parts of `content` past `committed`, moving on to a new placeholder reply for
+// the remainder. Returns whether a new reply was started.
+async fn flush_overflow(handler: &Handler, ctx: &Context, msg: &Message, reply: &mut Message, content: &str, committed: &mut usize) -> Result<bool, ServerError> {
+    let mut started_new = false;
+    while content.len() - *committed > MAX_MESSAGE_LENGTH {
+        let end = *committed + split_point(&content[*committed..]);
+        edit_reply(reply, ctx, &content[*committed..end]).await?;
+        handler.update_cached_message(reply, ctx).await;
+        *reply = msg.reply(&ctx.http, STREAM_PLACEHOLDER).await?;
+        *committed = end;
+        started_new = true;
+    }
+    Ok(started_new)
+}
+
+// Leaves an answer that failed part way marked as incomplete, or removes its reply if nothing of it
+// was shown yet.
+async fn abandon_reply(reply: &mut Message, ctx: &Context, rendered: &str) {
+    let result = if rendered.is_empty() {
+        reply.delete(&ctx.http).await.map_err(ServerError::from)
+    } else {
+        let mut content = truncate(rendered, MAX_MESSAGE_LENGTH - STREAM_INTERRUPTED.len() - 2).to_owned();
+        content.push_str("\n\n");
+        content.push_str(STREAM_INTERRUPTED);
+        edit_reply(reply, ctx, &content).await
+    };
+    if let Err(err) = result {
+        eprintln!("Error cleaning up interrupted reply - {}", err);
+    }
+}
+
+async fn edit_reply(reply: &mut Message, ctx: &Context, content: &str) -> Result<(), ServerError> {
+    reply.edit(&ctx.http, |m| m.content(content)).await?;
+    Ok(())
+}
+
+// Finds where to cut `content` so the first part fits in one message, preferring a line or word break.
+fn split_point(content: &str) -> usize {
+    split_point_at(content, MAX_MESSAGE_LENGTH)
+}
+
+// The beginning of `text` that fits in `max_len` bytes, cut at a line or word break if there is one.
+pub fn truncate(text: &str, max_len: usize) -> &str {
+    &text[..split_point_at(text, max_len)]
+}
+
+fn split_point_at(content: &str, mut limit: usize) -> usize {
+    if content.len() <= limit {
+        return content.len();
+    }
+    while !content.is_char_boundary(limit) {
+        limit -= 1;
+    }
+
+    let head = &content[..limit];
+    match head.rfind('\n').or_else(|| head.rfind(' ')) {
+        Some(index) if index > 0 => index + 1,
+        _ => limit,
+    }
+}
@@ -0,0 +1,226 @@
+use std::borrow::Cow;
+
+use serenity::{async_trait, builder::CreateEmbed, prelude::Context, model::prelude::{AttachmentType, GuildId, Message}, utils::{parse_channel, parse_username}};
+
+use crate::{ServerError, handler::Handler};
+use crate::usage::{self, Period, UsageFilter, UsageTotals};
+
+use super::{Command, permissions::can_manage_guild};
+
+pub const PREFIX: &str = "!";
+pub const COMMAND: &str = "usage";
+pub const FULL_COMMAND: &str = "!usage";
+pub const DESCRIPTION: &str = "Show the chat requests, tokens and estimated cost of this server, one of its members or \
+channels, this month unless another period is given. Admins also get the server's records as a CSV file";
+pub const USAGE_EXAMPLE: &str = "!usage [day|week|month] [@user] [#channel]";
+
+const TOP_USERS: usize = 5;
+// Keeps the CSV well below Discord's attachment size limit, at roughly 100 bytes a row.
+const MAX_CSV_RECORDS: usize = 50_000;
+
+#[derive(Debug)]
+pub struct UsageReport;
+
+#[async_trait]
+impl Command for UsageReport {
+    fn get_prefix(&self) -> &'static str {
+        PREFIX
+    }
+
+    fn get_command(&self) -> &'static str {
+        COMMAND
+    }
+
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_usage_example(&self) -> &'static str {
+        USAGE_EXAMPLE
+    }
+
+    async fn matches(&self, msg: &Message) -> bool {
+        msg.content == FULL_COMMAND || msg.content.starts_with(&format!("{} ", FULL_COMMAND))
+    }
+
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+        let guild_id = match msg.guild_id {
+            Some(guild_id) => guild_id.0,
+            None => return self.command_error(String::from("Usage reports are only available in servers")),
+        };
+
+        let mut period = Period::Month;
+        let mut user_id = None;
+        let mut channel_id = None;
+        for arg in msg.content.strip_prefix(FULL_COMMAND).unwrap().split_whitespace() {
+            if let Some(parsed) = Period::parse(arg) {
+                period = parsed;
+            } else if let Some(parsed) = parse_username(arg) {
+                user_id = Some(parsed);
+            } else if let Some(parsed) = parse_channel(arg) {
+                channel_id = Some(parsed);
+            } else {
+                return self.command_error(format!("Usage: {}", USAGE_EXAMPLE));
+            }
+        }
+
+        let since = period.start(usage::unix_time());
+        let mut filter = UsageFilter::default().guild(guild_id).since(since);
+        if let Some(user_id) = user_id {
+            filter = filter.user(user_id);
+        }
+        if let Some(channel_id) = channel_id {
+            filter = filter.channel(channel_id);
+        }
+
+        let ledger = handler.usage_ledger();
+        let totals = ledger.totals(&filter);
+        let mut embed = CreateEmbed::default();
+        embed
+            .title(format!("Usage {}", period.label()))
+            .color(0x90_EE_90)
+            .field("Requests", totals.requests, true)
+            .field("Images, speech and transcriptions", totals.media_requests, true)
+            .field("Prompt tokens", format_prompt_tokens(&totals), true)
+            .field("Completion tokens", totals.completion_tokens, true)
+            .field("Estimated cost", format_cost(totals.cost), true)
+            .footer(|footer| footer.text("Costs are estimated from list prices in US dollars"));
+
+        let scope: Vec<String> = user_id
+            .map(|user_id| format!("<@{}>", user_id))
+            .into_iter()
+            .chain(channel_id.map(|channel_id| format!("<#{}>", channel_id)))
+            .collect();
+        if !scope.is_empty() {
+            embed.description(scope.join(" in "));
+        }
+
+        let any_requests = totals.requests + totals.media_requests > 0;
+        if user_id.is_none() && any_requests {
+            let top_users: Vec<String> = ledger.totals_by_user(&filter)
+                .iter()
+                .take(TOP_USERS)
+                .enumerate()
+                .map(|(i, (user_id, totals))| format!("{}. <@{}> - {} ({} requests, {} tokens)",
+                    i + 1, user_id, format_cost(totals.cost), totals.requests, totals.total_tokens()))
+                .collect();
+            embed.field("Top users", top_users.join("\n"), false);
+        }
+
+        // The CSV lists every request with its channel and user, so only admins get it, and only for the whole server.
+        let mut files = vec![];
+        if user_id.is_none() && channel_id.is_none() && any_requests && is_admin(ctx, msg, GuildId(guild_id)).await {
+            let records = ledger.records(&filter);
+            let skipped = records.len().saturating_sub(MAX_CSV_RECORDS);
+            if skipped > 0 {
+                embed.footer(|footer| footer.text(format!(
+                    "Costs are estimated from list prices in US dollars. The CSV has the latest {} of {} requests",
+                    MAX_CSV_RECORDS, records.len())));
+            }
+            let csv = usage::to_csv(&records[skipped..]);
+            let filename = format!("usage-{}-{}.csv", guild_id, since);
+            files.push(AttachmentType::Bytes { data: Cow::Owned(csv.into_bytes()), filename });
+        }
+
+        msg.channel_id.send_message(&ctx.http, |m| {
+            m.reference_message(msg)
+                .add_files(files)
+                .set_embed(embed)
+        }).await?;
+        Ok(())
+    }
+}
+
+// Treats the author as no admin, without the CSV, when their permissions can't be looked up.
+async fn is_admin(ctx: &Context, msg: &Message, guild_id: GuildId) -> bool {
+    match can_manage_guild(ctx, msg, guild_id).await {
+        Ok(is_admin) => is_admin,
+        Err(err) => {
+            eprintln!("Error checking the permissions of {} for the usage CSV - {}", msg.author.id, err);
+            false
+        }
+    }
+}
+
+fn format_prompt_tokens(totals: &UsageTotals) -> String {
+    if totals.cached_tokens > 0 {
+        format!("{} ({} cached)", totals.prompt_tokens, totals.cached_tokens)
+    } else {
+        totals.prompt_tokens.to_string()
+    }
+}
+
+fn format_cost(cost: f64) -> String {
+    if cost > 0.0 && cost < 0.01 {
+        format!("${:.4}", cost)
+    } else {
+        format!("${:.2}", cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ogpt::testing::{FakeBackend, MockResponse, MockServer};
+    use serenity::prelude::EventHandler;
+
+    use crate::handler::Handler;
+    use crate::testing::{self, CHANNEL_ID, GUILD_ID, USER_ID};
+    use crate::usage::{self, UsageKind, UsageRecord};
+
+    fn handler_with_usage() -> Handler {
+        let handler = testing::handler(Arc::new(FakeBackend::new()), testing::chat_settings());
+        handler.usage_ledger().insert(UsageRecord {
+            timestamp: usage::unix_time(),
+            guild_id: Some(GUILD_ID),
+            channel_id: CHANNEL_ID,
+            user_id: USER_ID,
+            kind: UsageKind::Chat,
+            model: String::from("gpt-4o-mini"),
+            units: 0,
+            prompt_tokens: 1000,
+            cached_tokens: 0,
+            completion_tokens: 200,
+            cost: 0.00027,
+        });
+        handler
+    }
+
+    #[tokio::test]
+    async fn reports_a_members_usage_without_looking_up_permissions() {
+        let handler = handler_with_usage();
+        let discord = MockServer::start();
+        discord.enqueue(testing::bot_message(10, ""));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, &format!("!usage day <@{}>", USER_ID))).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 1);
+        let embed = &requests[0].json().unwrap()["embeds"][0];
+        assert_eq!(embed["title"], "Usage today");
+        assert_eq!(embed["description"], format!("<@{}>", USER_ID));
+        assert_eq!(embed["fields"][0]["value"], "1");
+    }
+
+    #[tokio::test]
+    async fn asks_discord_for_permissions_missing_from_the_cache() {
+        let handler = handler_with_usage();
+        let discord = MockServer::start();
+        discord
+            .enqueue(MockResponse::error(404, "not_found", "Unknown Member"))
+            .enqueue(MockResponse::error(404, "not_found", "Unknown Guild"))
+            .enqueue(testing::bot_message(10, ""));
+
+        handler.message(testing::discord_context(&discord), testing::message(1, "!usage")).await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path, format!("/api/v10/guilds/{}/members/{}", GUILD_ID, USER_ID));
+        assert_eq!(requests[1].path, format!("/api/v10/guilds/{}", GUILD_ID));
+        // The report still goes out, without the CSV only admins get.
+        let report = requests[2].json().unwrap();
+        assert_eq!(report["embeds"][0]["title"], "Usage this month");
+        assert!(!String::from_utf8_lossy(&requests[2].body).contains("usage-"));
+    }
+}
@@ -0,0 +1,92 @@
+use serenity::{async_trait, prelude::Context, model::prelude::{Attachment, Message}};
+
+use crate::{ServerError, handler::{Handler, MessageLite}};
+
+use super::{Command, gpt, stream_reply::{truncate, MAX_MESSAGE_LENGTH}};
+
+pub const DESCRIPTION: &str = "Voice messages are transcribed, and answered like a !gpt question if enabled";
+pub const USAGE_EXAMPLE: &str = "<voice message>";
+
+// The transcription API rejects larger uploads.
+const MAX_AUDIO_BYTES: u64 = 25 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct VoiceMessage;
+
+#[async_trait]
+impl Command for VoiceMessage {
+    fn get_prefix(&self) -> &'static str {
+        ""
+    }
+
+    fn get_command(&self) -> &'static str {
+        ""
+    }
+
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_usage_example(&self) -> &'static str {
+        USAGE_EXAMPLE
+    }
+
+    async fn matches(&self, msg: &Message) -> bool {
+        !msg.author.bot && voice_attachment(msg).is_some()
+    }
+
+    async fn handle(&self, handler: &Handler, ctx: &Context, msg: &Message) -> Result<(), ServerError> {
+        let settings = handler.voice_message_settings();
+        if !settings.is_enabled_in(msg.channel_id.0) {
+            return Ok(());
+        }
+
+        let attachment = voice_attachment(msg).unwrap();
+        if attachment.size > MAX_AUDIO_BYTES {
+            return self.command_error(format!("Voice messages larger than {} MB can't be transcribed", MAX_AUDIO_BYTES / 1024 / 1024));
+        }
+
+        let typing = msg.channel_id.start_typing(&ctx.http)?;
+        let transcript = transcribe(handler, ctx, msg, attachment).await;
+        let _ = typing.stop();
+        let transcript = transcript?;
+        let transcript = transcript.trim();
+        if transcript.is_empty() {
+            return self.command_error(String::from("No speech was recognized in the voice message"));
+        }
+
+        msg.reply(&ctx.http, quote(transcript)).await?;
+
+        if settings.answer {
+            // Cached as the question it was answered as, so replies to the answer continue the conversation.
+            let mut message = MessageLite::from_msg(msg, ctx);
+            message.content = format!("{} {}", gpt::FULL_COMMAND, transcript);
+            handler.put_cached_message(message).await;
+
+            gpt::answer_question(self, handler, ctx, msg, transcript).await?;
+        }
+        Ok(())
+    }
+}
+
+// Discord voice messages are ogg/opus attachments.
+fn voice_attachment(msg: &Message) -> Option<&Attachment> {
+    msg.attachments
+        .iter()
+        .find(|attachment| attachment.content_type.as_deref().is_some_and(|content_type| content_type.starts_with("audio/ogg")))
+}
+
+async fn transcribe(handler: &Handler, ctx: &Context, msg: &Message, attachment: &Attachment) -> Result<String, ServerError> {
+    let audio = attachment.download().await?;
+    handler.transcribe(ctx, msg, attachment.filename.to_owned(), audio).await
+}
+
+// The transcript as a block quote, shortened to fit in one message.
+fn quote(transcript: &str) -> String {
+    let quoted = format!("> {}", transcript.replace('\n', "\n> "));
+    if quoted.len() > MAX_MESSAGE_LENGTH {
+        format!("{}...", truncate(&quoted, MAX_MESSAGE_LENGTH - 3))
+    } else {
+        quoted
+    }
+}
@@ -0,0 +1,491 @@
+use std::{collections::HashMap, env, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
+use ogpt::{client::{Cassette, CassetteMode, OGptAsyncClient, OGptClientBuilder, RetryPolicy}, limiter::{RateLimiter, RateLimits}, model_info::ModelInfo};
+
+use crate::ServerError;
+
+const OPENAI_TOKEN: &str = "OPENAI_TOKEN";
+const OPENAI_BASE_URL: &str = "OPENAI_BASE_URL";
+const OPENAI_ORGANIZATION: &str = "OPENAI_ORGANIZATION";
+const OPENAI_PROJECT: &str = "OPENAI_PROJECT";
+const OPENAI_MODEL: &str = "OPENAI_MODEL";
+const AZURE_OPENAI_DEPLOYMENT: &str = "AZURE_OPENAI_DEPLOYMENT";
+const AZURE_OPENAI_API_VERSION: &str = "AZURE_OPENAI_API_VERSION";
+const OPENAI_MAX_ATTEMPTS: &str = "OPENAI_MAX_ATTEMPTS";
+const OPENAI_REQUESTS_PER_MINUTE: &str = "OPENAI_REQUESTS_PER_MINUTE";
+const OPENAI_TOKENS_PER_MINUTE: &str = "OPENAI_TOKENS_PER_MINUTE";
+const OPENAI_RATE_LIMIT_MAX_WAIT: &str = "OPENAI_RATE_LIMIT_MAX_WAIT";
+const OPENAI_MAX_TOKENS: &str = "OPENAI_MAX_TOKENS";
+const OPENAI_STREAM_USAGE: &str = "OPENAI_STREAM_USAGE";
+const OPENAI_CONTEXT_WINDOW: &str = "OPENAI_CONTEXT_WINDOW";
+const OPENAI_SUMMARY_MODEL: &str = "OPENAI_SUMMARY_MODEL";
+const OPENAI_VISION: &str = "OPENAI_VISION";
+const OPENAI_EMBEDDING_MODEL: &str = "OPENAI_EMBEDDING_MODEL";
+const OPENAI_IMAGE_MODEL: &str = "OPENAI_IMAGE_MODEL";
+const OPENAI_IMAGE_SIZE: &str = "OPENAI_IMAGE_SIZE";
+const OPENAI_IMAGE_QUALITY: &str = "OPENAI_IMAGE_QUALITY";
+const OPENAI_TTS_MODEL: &str = "OPENAI_TTS_MODEL";
+const OPENAI_TTS_VOICE: &str = "OPENAI_TTS_VOICE";
+const OPENAI_TRANSCRIPTION_MODEL: &str = "OPENAI_TRANSCRIPTION_MODEL";
+const GPT_CHANNELS: &str = "GPT_CHANNELS";
+const VOICE_MESSAGE_ANSWER: &str = "VOICE_MESSAGE_ANSWER";
+const KNOWLEDGE_BASE_DIR: &str = "KNOWLEDGE_BASE_DIR";
+const KNOWLEDGE_TOP_K: &str = "KNOWLEDGE_TOP_K";
+const MODERATION_POLICY: &str = "MODERATION_POLICY";
+const OPENAI_MODERATION_MODEL: &str = "OPENAI_MODERATION_MODEL";
+const CHAT_BACKEND: &str = "CHAT_BACKEND";
+const GUILD_CHAT_BACKENDS: &str = "GUILD_CHAT_BACKENDS";
+const ANTHROPIC_API_KEY: &str = "ANTHROPIC_API_KEY";
+const ANTHROPIC_BASE_URL: &str = "ANTHROPIC_BASE_URL";
+const OLLAMA_BASE_URL: &str = "OLLAMA_BASE_URL";
+const OPENAI_CASSETTE: &str = "OPENAI_CASSETTE";
+const OPENAI_CASSETTE_MODE: &str = "OPENAI_CASSETTE_MODE";
+const MODEL_PRICES: &str = "MODEL_PRICES";
+const BUDGET_POLICY: &str = "BUDGET_POLICY";
+const DATABASE_FILE: &str = "DATABASE_FILE";
+const USAGE_LOG: &str = "USAGE_LOG";
+
+pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+pub const DEFAULT_ANTHROPIC_MODEL: &str = "claude-sonnet-4-5";
+pub const DEFAULT_OLLAMA_MODEL: &str = "llama3.1";
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-02-01";
+// The first Azure api-version accepting `stream_options`.
+pub const AZURE_STREAM_USAGE_API_VERSION: &str = "2024-09-01-preview";
+pub const DEFAULT_MAX_TOKENS: u64 = 1024;
+pub const DEFAULT_SUMMARY_MODEL: &str = "gpt-4o-mini";
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+pub const DEFAULT_IMAGE_MODEL: &str = "dall-e-3";
+pub const DEFAULT_IMAGE_SIZE: &str = "1024x1024";
+pub const DEFAULT_TTS_MODEL: &str = "tts-1";
+pub const DEFAULT_TTS_VOICE: &str = "alloy";
+pub const DEFAULT_TRANSCRIPTION_MODEL: &str = "whisper-1";
+pub const DEFAULT_KNOWLEDGE_BASE_DIR: &str = "knowledge";
+pub const DEFAULT_KNOWLEDGE_TOP_K: usize = 4;
+pub const DEFAULT_MODERATION_MODEL: &str = "omni-moderation-latest";
+pub const DEFAULT_DATABASE_FILE: &str = "bot.sqlite3";
+// Where usage was recorded before it moved into the database.
+pub const DEFAULT_USAGE_LOG: &str = "usage.jsonl";
+
+// How the handler talks to the chat model: which model, and how its context window is split between
+// the conversation and the reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatSettings {
+    pub model: String,
+    pub max_tokens: u64,
+    pub context_window: usize,
+    // Cheaper model used to summarize conversations that outgrow the context window.
+    pub summary_model: String,
+    // Whether image attachments are sent along with the messages they belong to.
+    pub supports_vision: bool,
+}
+
+impl ChatSettings {
+    pub fn for_model(model: String) -> ChatSettings {
+        let info = ModelInfo::for_model(&model);
+        ChatSettings {
+            model,
+            max_tokens: DEFAULT_MAX_TOKENS.min(info.max_output_tokens as u64),
+            context_window: info.context_window,
+            summary_model: String::from(DEFAULT_SUMMARY_MODEL),
+            supports_vision: info.supports_vision,
+        }
+    }
+
+    pub fn max_prompt_tokens(&self) -> usize {
+        self.context_window.saturating_sub(self.max_tokens as usize)
+    }
+}
+
+// How `!imagine` generates images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSettings {
+    pub model: String,
+    pub size: String,
+    pub quality: Option<String>,
+}
+
+// How answers are spoken in voice channels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeechSettings {
+    pub model: String,
+    pub voice: String,
+}
+
+// Where the per-guild knowledge bases live and how they are searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnowledgeSettings {
+    pub dir: PathBuf,
+    pub embedding_model: String,
+    // Number of chunks added to the prompt of a question.
+    pub top_k: usize,
+}
+
+impl KnowledgeSettings {
+    pub fn from_env() -> KnowledgeSettings {
+        KnowledgeSettings {
+            dir: PathBuf::from(env::var(KNOWLEDGE_BASE_DIR).unwrap_or_else(|_| String::from(DEFAULT_KNOWLEDGE_BASE_DIR))),
+            embedding_model: env::var(OPENAI_EMBEDDING_MODEL).unwrap_or_else(|_| String::from(DEFAULT_EMBEDDING_MODEL)),
+            top_k: parse_env(KNOWLEDGE_TOP_K).unwrap_or(DEFAULT_KNOWLEDGE_TOP_K),
+        }
+    }
+}
+
+// How voice messages are handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoiceMessageSettings {
+    pub transcription_model: String,
+    // Channels where voice messages are transcribed, all channels if empty.
+    pub channels: Vec<u64>,
+    // Whether transcripts are also answered like a !gpt question.
+    pub answer: bool,
+}
+
+impl VoiceMessageSettings {
+    pub fn from_env() -> VoiceMessageSettings {
+        let channels = env::var(GPT_CHANNELS)
+            .map(|channels| channels.split(',').filter_map(|channel| channel.trim().parse().ok()).collect())
+            .unwrap_or_default();
+
+        VoiceMessageSettings {
+            transcription_model: env::var(OPENAI_TRANSCRIPTION_MODEL).unwrap_or_else(|_| String::from(DEFAULT_TRANSCRIPTION_MODEL)),
+            channels,
+            answer: parse_env(VOICE_MESSAGE_ANSWER).unwrap_or(false),
+        }
+    }
+
+    pub fn is_enabled_in(&self, channel_id: u64) -> bool {
+        self.channels.is_empty() || self.channels.contains(&channel_id)
+    }
+}
+
+// Where the moderation policies are read from. Nothing is moderated without a policy file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationSettings {
+    pub policy_file: Option<PathBuf>,
+    pub model: String,
+}
+
+impl ModerationSettings {
+    pub fn from_env() -> ModerationSettings {
+        ModerationSettings {
+            policy_file: env::var(MODERATION_POLICY).ok().map(PathBuf::from),
+            model: env::var(OPENAI_MODERATION_MODEL).unwrap_or_else(|_| String::from(DEFAULT_MODERATION_MODEL)),
+        }
+    }
+}
+
+// The prices overriding the built-in table and the budgets limiting what requests may cost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageSettings {
+    pub price_file: Option<PathBuf>,
+    pub budget_file: Option<PathBuf>,
+}
+
+impl UsageSettings {
+    pub fn from_env() -> UsageSettings {
+        UsageSettings {
+            price_file: env::var(MODEL_PRICES).ok().map(PathBuf::from),
+            budget_file: env::var(BUDGET_POLICY).ok().map(PathBuf::from),
+        }
+    }
+}
+
+// The SQLite database holding the state that has to survive restarts. An empty DATABASE_FILE keeps the
+// state in memory instead, e.g. for offline runs against a cassette.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageSettings {
+    pub database_file: Option<PathBuf>,
+    // A JSON lines usage log of earlier versions, imported into the store on startup if it exists.
+    pub usage_log: PathBuf,
+}
+
+impl StorageSettings {
+    pub fn from_env() -> StorageSettings {
+        let database_file = match env::var(DATABASE_FILE) {
+            Ok(file) if file.trim().is_empty() => None,
+            Ok(file) => Some(PathBuf::from(file)),
+            Err(_) => Some(PathBuf::from(DEFAULT_DATABASE_FILE)),
+        };
+        StorageSettings {
+            database_file,
+            usage_log: PathBuf::from(env::var(USAGE_LOG).unwrap_or_else(|_| String::from(DEFAULT_USAGE_LOG))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    OpenAi,
+    Anthropic,
+    Ollama,
+}
+
+impl FromStr for BackendKind {
+    type Err = ServerError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "openai" => Ok(BackendKind::OpenAi),
+            "anthropic" => Ok(BackendKind::Anthropic),
+            "ollama" => Ok(BackendKind::Ollama),
+            other => Err(ServerError::ConfigError(format!("Unknown chat backend {}, expected openai, anthropic or ollama", other))),
+        }
+    }
+}
+
+impl BackendKind {
+    // Model answering when OPENAI_MODEL is unset.
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            BackendKind::OpenAi => DEFAULT_MODEL,
+            BackendKind::Anthropic => DEFAULT_ANTHROPIC_MODEL,
+            BackendKind::Ollama => DEFAULT_OLLAMA_MODEL,
+        }
+    }
+}
+
+// The backend and model answering in a guild.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuildBackend {
+    pub kind: BackendKind,
+    pub model: String,
+}
+
+// Which chat backends are used where. The default backend answers with OPENAI_MODEL, guilds listed in
+// GUILD_CHAT_BACKENDS as `guild_id=backend:model` use their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendSettings {
+    pub default: BackendKind,
+    pub guilds: HashMap<u64, GuildBackend>,
+    pub anthropic_api_key: Option<String>,
+    pub anthropic_base_url: Option<String>,
+    pub ollama_base_url: Option<String>,
+}
+
+impl BackendSettings {
+    pub fn from_env() -> Result<BackendSettings, ServerError> {
+        let default = match env::var(CHAT_BACKEND) {
+            Ok(backend) => backend.parse()?,
+            Err(_) => BackendKind::OpenAi,
+        };
+
+        let mut guilds = HashMap::new();
+        for entry in env::var(GUILD_CHAT_BACKENDS).unwrap_or_default().split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (guild_id, kind, model) = parse_guild_backend(entry)
+                .ok_or_else(|| ServerError::ConfigError(format!("Invalid {} entry {}, expected guild_id=backend:model", GUILD_CHAT_BACKENDS, entry)))?;
+            guilds.insert(guild_id, GuildBackend { kind: kind.parse()?, model: model.to_owned() });
+        }
+
+        Ok(BackendSettings {
+            default,
+            guilds,
+            anthropic_api_key: env::var(ANTHROPIC_API_KEY).ok(),
+            anthropic_base_url: env::var(ANTHROPIC_BASE_URL).ok(),
+            ollama_base_url: env::var(OLLAMA_BASE_URL).ok(),
+        })
+    }
+
+    pub fn uses(&self, kind: BackendKind) -> bool {
+        self.default == kind || self.guilds.values().any(|guild| guild.kind == kind)
+    }
+}
+
+// Splits a `guild_id=backend:model` entry. Models may contain colons themselves, e.g. `llama3.1:8b`.
+fn parse_guild_backend(entry: &str) -> Option<(u64, &str, &str)> {
+    let (guild_id, backend) = entry.trim().split_once('=')?;
+    let (kind, model) = backend.split_once(':')?;
+    if model.trim().is_empty() {
+        return None;
+    }
+    Some((guild_id.trim().parse().ok()?, kind, model.trim()))
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenAiConfig {
+    pub api_key: String,
+    pub base_url: Option<String>,
+    pub organization: Option<String>,
+    pub project: Option<String>,
+    // Unset picks the default model of the default chat backend.
+    pub model: Option<String>,
+    pub azure_deployment: Option<String>,
+    pub azure_api_version: Option<String>,
+    pub max_attempts: Option<u32>,
+    pub requests_per_minute: Option<u64>,
+    pub tokens_per_minute: Option<u64>,
+    pub rate_limit_max_wait: Option<Duration>,
+    pub max_tokens: Option<u64>,
+    // Whether streams end with their usage, unset asks for it where the API is known to support it.
+    pub stream_usage: Option<bool>,
+    pub context_window: Option<usize>,
+    pub summary_model: Option<String>,
+    pub vision: Option<bool>,
+    pub image_model: Option<String>,
+    pub image_size: Option<String>,
+    pub image_quality: Option<String>,
+    pub tts_model: Option<String>,
+    pub tts_voice: Option<String>,
+    // Cassette file the chat backends record to or replay from, for running the bot offline.
+    pub cassette: Option<PathBuf>,
+    pub cassette_mode: Option<String>,
+}
+
+impl OpenAiConfig {
+    pub fn from_env() -> Result<OpenAiConfig, ServerError> {
+        Ok(OpenAiConfig {
+            api_key: env::var(OPENAI_TOKEN)?,
+            base_url: env::var(OPENAI_BASE_URL).ok(),
+            organization: env::var(OPENAI_ORGANIZATION).ok(),
+            project: env::var(OPENAI_PROJECT).ok(),
+            model: env::var(OPENAI_MODEL).ok(),
+            azure_deployment: env::var(AZURE_OPENAI_DEPLOYMENT).ok(),
+            azure_api_version: env::var(AZURE_OPENAI_API_VERSION).ok(),
+            max_attempts: parse_env(OPENAI_MAX_ATTEMPTS),
+            requests_per_minute: parse_env(OPENAI_REQUESTS_PER_MINUTE),
+            tokens_per_minute: parse_env(OPENAI_TOKENS_PER_MINUTE),
+            rate_limit_max_wait: parse_env(OPENAI_RATE_LIMIT_MAX_WAIT).map(Duration::from_secs),
+            // Requests can't ask for 0 tokens, so 0 leaves the model's default.
+            max_tokens: parse_env(OPENAI_MAX_TOKENS).filter(|&max_tokens| max_tokens > 0),
+            stream_usage: parse_env(OPENAI_STREAM_USAGE),
+            context_window: parse_env(OPENAI_CONTEXT_WINDOW),
+            summary_model: env::var(OPENAI_SUMMARY_MODEL).ok(),
+            vision: parse_env(OPENAI_VISION),
+            image_model: env::var(OPENAI_IMAGE_MODEL).ok(),
+            image_size: env::var(OPENAI_IMAGE_SIZE).ok(),
+            image_quality: env::var(OPENAI_IMAGE_QUALITY).ok(),
+            tts_model: env::var(OPENAI_TTS_MODEL).ok(),
+            tts_voice: env::var(OPENAI_TTS_VOICE).ok(),
+            cassette: env::var(OPENAI_CASSETTE).ok().map(PathBuf::from),
+            cassette_mode: env::var(OPENAI_CASSETTE_MODE).ok(),
+        })
+    }
+
+    pub fn chat_settings(&self, kind: BackendKind) -> ChatSettings {
+        let model = self.model.to_owned().unwrap_or_else(|| String::from(kind.default_model()));
+        let mut settings = ChatSettings::for_model(model);
+        if let Some(max_tokens) = self.max_tokens {
+            settings.max_tokens = max_tokens;
+        }
+        if let Some(context_window) = self.context_window {
+            settings.context_window = context_window;
+        }
+        if let Some(summary_model) = &self.summary_model {
+            settings.summary_model = summary_model.to_owned();
+        }
+        if let Some(vision) = self.vision {
+            settings.supports_vision = vision;
+        }
+        settings
+    }
+
+    pub fn image_settings(&self) -> ImageSettings {
+        ImageSettings {
+            model: self.image_model.to_owned().unwrap_or_else(|| String::from(DEFAULT_IMAGE_MODEL)),
+            size: self.image_size.to_owned().unwrap_or_else(|| String::from(DEFAULT_IMAGE_SIZE)),
+            quality: self.image_quality.to_owned(),
+        }
+    }
+
+    pub fn speech_settings(&self) -> SpeechSettings {
+        SpeechSettings {
+            model: self.tts_model.to_owned().unwrap_or_else(|| String::from(DEFAULT_TTS_MODEL)),
+            voice: self.tts_voice.to_owned().unwrap_or_else(|| String::from(DEFAULT_TTS_VOICE)),
+        }
+    }
+
+    pub fn client_builder(&self) -> OGptClientBuilder {
+        let mut builder = OGptAsyncClient::builder(self.api_key.to_owned());
+
+        if let Some(base_url) = &self.base_url {
+            builder = builder.base_url(base_url.to_owned());
+        }
+        if let Some(organization) = &self.organization {
+            builder = builder.organization(organization.to_owned());
+        }
+        if let Some(project) = &self.project {
+            builder = builder.project(project.to_owned());
+        }
+        if let Some(deployment) = &self.azure_deployment {
+            let api_version = self.azure_api_version.to_owned().unwrap_or_else(|| String::from(DEFAULT_AZURE_API_VERSION));
+            builder = builder.azure_deployment(deployment.to_owned(), api_version);
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            builder = builder.retry_policy(RetryPolicy::default().max_attempts(max_attempts));
+        }
+        builder = builder.stream_usage(self.stream_usage.unwrap_or_else(|| self.supports_stream_usage()));
+        if self.requests_per_minute.is_some() || self.tokens_per_minute.is_some() {
+            let mut limits = RateLimits {
+                requests_per_minute: self.requests_per_minute,
+                tokens_per_minute: self.tokens_per_minute,
+                ..Default::default()
+            };
+            if let Some(max_wait) = self.rate_limit_max_wait {
+                limits.max_wait = max_wait;
+            }
+            builder = builder.rate_limiter(Arc::new(RateLimiter::new(limits)));
+        }
+        builder
+    }
+
+    // OpenAI and recent Azure api-versions report the usage of streams, other servers may reject the option.
+    // Dated api-versions compare in order as strings.
+    fn supports_stream_usage(&self) -> bool {
+        match &self.azure_deployment {
+            Some(_) => self.azure_api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION) >= AZURE_STREAM_USAGE_API_VERSION,
+            None => self.base_url.is_none(),
+        }
+    }
+
+    pub fn open_cassette(&self) -> Result<Option<Arc<Cassette>>, ServerError> {
+        let path = match &self.cassette {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mode = match self.cassette_mode.as_deref().map(|mode| mode.trim().to_lowercase()) {
+            None => CassetteMode::Replay,
+            Some(mode) if mode == "replay" => CassetteMode::Replay,
+            Some(mode) if mode == "record" => CassetteMode::Record,
+            Some(mode) => return Err(ServerError::ConfigError(format!("Unknown cassette mode {}, expected record or replay", mode))),
+        };
+        println!("Using cassette {} in {:?} mode", path.display(), mode);
+        Ok(Some(Arc::new(Cassette::open(path, mode)?)))
+    }
+
+    pub fn build_client(&self, cassette: Option<Arc<Cassette>>) -> Result<OGptAsyncClient, ServerError> {
+        let mut builder = self.client_builder();
+        if let Some(cassette) = cassette {
+            builder = builder.cassette(cassette);
+        }
+        Ok(builder.build_async()?)
+    }
+}
+
+// Everything the bot is configured with besides its Discord token.
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    pub openai: OpenAiConfig,
+    pub knowledge: KnowledgeSettings,
+    pub voice_message: VoiceMessageSettings,
+    pub moderation: ModerationSettings,
+    pub backend: BackendSettings,
+    pub usage: UsageSettings,
+    pub storage: StorageSettings,
+}
+
+impl ServerSettings {
+    pub fn from_env() -> Result<ServerSettings, ServerError> {
+        Ok(ServerSettings {
+            openai: OpenAiConfig::from_env()?,
+            knowledge: KnowledgeSettings::from_env(),
+            voice_message: VoiceMessageSettings::from_env(),
+            moderation: ModerationSettings::from_env(),
+            backend: BackendSettings::from_env()?,
+            usage: UsageSettings::from_env(),
+            storage: StorageSettings::from_env(),
+        })
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|value| value.trim().parse().ok())
+}
\ No newline at end of file
@@ -1,8 +1,10 @@
-use std::{env::VarError, fmt, error};
+use std::{env::VarError, fmt, error, io};
 use ogpt::error::OGptError;
 use songbird::tracks::TrackError;
 
 use crate::command::CommandError;
+use crate::moderation::Stage;
+use crate::usage::Budget;
 
 #[derive(Debug)]
 pub enum ServerError {
@@ -13,6 +15,14 @@ pub enum ServerError {
     SongbirdInputError(songbird::input::error::Error),
     VoiceChannelJoinError(songbird::error::JoinError),
     TrackError(songbird::tracks::TrackError),
+    IoError(io::Error),
+    SerdeJsonError(serde_json::Error),
+    SqliteError(rusqlite::Error),
+    KnowledgeBaseError(String),
+    ConfigError(String),
+    ModerationBlocked(Stage),
+    BudgetExceeded(Budget),
+    PromptTooLong,
 }
 
 impl fmt::Display for ServerError {
@@ -25,6 +35,16 @@ impl fmt::Display for ServerError {
             ServerError::SongbirdInputError(err) => write!(f, "Songbird input error: {}", err),
             ServerError::VoiceChannelJoinError(err) => write!(f, "Voice channel join error: {}", err),
             ServerError::TrackError(err) => write!(f, "Track error: {}", err),
+            ServerError::IoError(err) => write!(f, "IO error: {}", err),
+            ServerError::SerdeJsonError(err) => write!(f, "Serde json error: {}", err),
+            ServerError::SqliteError(err) => write!(f, "SQLite error: {}", err),
+            ServerError::KnowledgeBaseError(err) => write!(f, "Knowledge base error: {}", err),
+            ServerError::ConfigError(err) => write!(f, "Config error: {}", err),
+            ServerError::ModerationBlocked(Stage::Input) => write!(f, "This question was blocked by the server's moderation policy"),
+            ServerError::ModerationBlocked(Stage::Output) => write!(f, "The answer was blocked by the server's moderation policy"),
+            ServerError::BudgetExceeded(Budget::GuildMonthly) => write!(f, "This server has used its monthly budget for questions, it resets at the start of next month (UTC)"),
+            ServerError::BudgetExceeded(Budget::UserDaily) => write!(f, "You have used your daily quota for questions, it resets at midnight UTC"),
+            ServerError::PromptTooLong => write!(f, "This message is too long for the model's context window"),
         }
     }
 }
@@ -71,6 +91,24 @@ impl From<TrackError> for ServerError {
     }
 }
 
+impl From<io::Error> for ServerError {
+    fn from(err: io::Error) -> Self {
+        ServerError::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for ServerError {
+    fn from(err: serde_json::Error) -> Self {
+        ServerError::SerdeJsonError(err)
+    }
+}
+
+impl From<rusqlite::Error> for ServerError {
+    fn from(err: rusqlite::Error) -> Self {
+        ServerError::SqliteError(err)
+    }
+}
+
 impl error::Error for  ServerError {
     fn cause(&self) -> Option<&dyn error::Error> {
         match self {
@@ -81,6 +119,14 @@ impl error::Error for  ServerError {
             ServerError::SongbirdInputError(err) => Some(err),
             ServerError::VoiceChannelJoinError(err) => Some(err),
             ServerError::TrackError(err) => Some(err),
+            ServerError::IoError(err) => Some(err),
+            ServerError::SerdeJsonError(err) => Some(err),
+            ServerError::SqliteError(err) => Some(err),
+            ServerError::KnowledgeBaseError(_) => None,
+            ServerError::ConfigError(_) => None,
+            ServerError::ModerationBlocked(_) => None,
+            ServerError::BudgetExceeded(_) => None,
+            ServerError::PromptTooLong => None,
         }
     }
 
@@ -93,6 +139,14 @@ impl error::Error for  ServerError {
             ServerError::SongbirdInputError(err) => err.source(),
             ServerError::VoiceChannelJoinError(err) => err.source(),
             ServerError::TrackError(err) => err.source(),
+            ServerError::IoError(err) => err.source(),
+            ServerError::SerdeJsonError(err) => err.source(),
+            ServerError::SqliteError(err) => err.source(),
+            ServerError::KnowledgeBaseError(_) => None,
+            ServerError::ConfigError(_) => None,
+            ServerError::ModerationBlocked(_) => None,
+            ServerError::BudgetExceeded(_) => None,
+            ServerError::PromptTooLong => None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,119 @@
+use ogpt::{model::chat_completions::{ContentPart, ImageDetail, Message, Role}, tokenizer};
+
+use crate::ServerError;
+
+// Images beyond this many on a single message are not sent to the model.
+const MAX_IMAGES_PER_MESSAGE: usize = 4;
+
+// Older turns are only truncated to fit if at least this much room is left, otherwise they are dropped.
+const MIN_TRUNCATED_TURN_TOKENS: usize = 64;
+
+// A message of a reply chain together with the id of the Discord message it came from, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationTurn {
+    pub message_id: Option<u64>,
+    pub message: Message,
+}
+
+impl ConversationTurn {
+    pub fn new(message_id: Option<u64>, role: Role, content: String) -> ConversationTurn {
+        ConversationTurn {
+            message_id,
+            message: Message::new(role, content),
+        }
+    }
+}
+
+// A user message with its image attachments as image parts, or plain text if there are none.
+pub fn user_message(text: String, image_urls: &[String]) -> Message {
+    if image_urls.is_empty() {
+        return Message::user(text);
+    }
+
+    let mut parts = vec![];
+    if !text.is_empty() {
+        parts.push(ContentPart::text(text));
+    }
+    parts.extend(
+        image_urls
+            .iter()
+            .take(MAX_IMAGES_PER_MESSAGE)
+            .map(|url| ContentPart::image_url(url.to_owned(), Some(ImageDetail::Auto)))
+    );
+    Message::with_parts(Role::User, parts)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrimmedConversation {
+    pub messages: Vec<Message>,
+    // Oldest turns that didn't fit, in chronological order.
+    pub dropped: Vec<Message>,
+}
+
+// Fits a conversation (system prompt first, latest user message last) into `max_prompt_tokens`. The system
+// prompt and latest message are kept, truncated if they alone exceed the budget, and older turns are kept
+// newest first until the budget runs out. A system prompt that can't be truncated enough is left out, a
+// latest message that can't is an error.
+pub fn trim_to_budget(model: &str, mut messages: Vec<Message>, max_prompt_tokens: usize) -> Result<TrimmedConversation, ServerError> {
+    let mut remaining = max_prompt_tokens.saturating_sub(tokenizer::reply_priming_tokens());
+
+    let latest = match messages.pop() {
+        Some(latest) => latest,
+        None => return Ok(TrimmedConversation { messages, dropped: vec![] }),
+    };
+    let system = match messages.first() {
+        Some(message) if message.role == Role::System => Some(messages.remove(0)),
+        _ => None,
+    };
+
+    // When the system prompt and latest message don't both fit, the latest message gets at least half.
+    let latest_cost = tokenizer::count_message_tokens(model, &latest);
+    let system = system.and_then(|system| {
+        let limit = remaining.saturating_sub(latest_cost).max(remaining / 2);
+        if tokenizer::count_message_tokens(model, &system) <= limit {
+            Some(system)
+        } else {
+            tokenizer::truncate_message(model, &system, limit)
+        }
+    });
+    if let Some(system) = &system {
+        remaining = remaining.saturating_sub(tokenizer::count_message_tokens(model, system));
+    }
+
+    let latest = if latest_cost <= remaining {
+        latest
+    } else {
+        tokenizer::truncate_message(model, &latest, remaining).ok_or(ServerError::PromptTooLong)?
+    };
+    remaining = remaining.saturating_sub(tokenizer::count_message_tokens(model, &latest));
+
+    let mut kept = vec![];
+    while let Some(message) = messages.pop() {
+        let cost = tokenizer::count_message_tokens(model, &message);
+        if cost <= remaining {
+            remaining -= cost;
+            kept.push(message);
+            continue;
+        }
+
+        if remaining >= MIN_TRUNCATED_TURN_TOKENS {
+            if let Some(truncated) = tokenizer::truncate_message(model, &message, remaining) {
+                kept.push(truncated);
+            }
+        } else {
+            messages.push(message);
+        }
+        break;
+    }
+    kept.reverse();
+
+    let mut trimmed = vec![];
+    trimmed.extend(system);
+    trimmed.extend(kept);
+    trimmed.push(latest);
+
+    Ok(TrimmedConversation {
+        messages: trimmed,
+        dropped: messages,
+    })
+}
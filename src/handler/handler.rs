@@ -1,54 +1,215 @@
-use ogpt::model::chat_completions;
+use ogpt::model::{audio, chat_completions, images, speech};
 use serenity::async_trait;
+use serenity::http::Http;
 use serenity::model::channel::Message;
 use serenity::model::gateway::Ready;
+use serenity::model::id::{ChannelId, GuildId};
+use futures_util::StreamExt;
 use serenity::prelude::EventHandler;
 use serenity::prelude::Context;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use ogpt::client::OGptAsyncClient;
+use ogpt::backend::MediaBackend;
+use ogpt::client::ChatCompletionsStream;
+use ogpt::pricing;
 
 use lru::LruCache;
 
 use crate::ServerError;
 use crate::command;
+use crate::backend::{ChatRoute, ChatRoutes};
+use crate::config::{ImageSettings, SpeechSettings, VoiceMessageSettings};
+use crate::knowledge::{KnowledgeBase, RetrievedChunk};
+use crate::moderation::{self, ModerationAction, Moderator, Stage, Verdict};
+use crate::storage::{self, Store};
+use crate::usage::{Attribution, Budgets, UsageKind, UsageLedger, UsageRecord};
+
+use super::conversation::{self, ConversationTurn};
+use super::summary;
+
+// Attachment types the vision models accept.
+const IMAGE_CONTENT_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/webp", "image/gif"];
+
+// Start of every ogg page, and the rate at which opus granule positions count samples.
+const OGG_CAPTURE_PATTERN: &[u8] = b"OggS";
+const OPUS_SAMPLE_RATE: f64 = 48_000.0;
+
+// Longest input the speech endpoint accepts.
+const MAX_SPEECH_INPUT_CHARS: usize = 4096;
 
 pub const GPT_DEFAULT_SYSTEM_PROMPT: &str = "You are a bot that answers questions accurately.";
 
+// The backends the handler answers with and the stores and ledgers it keeps its state in.
+pub struct HandlerDeps {
+    pub media_backend: Arc<dyn MediaBackend>,
+    pub chat_routes: ChatRoutes,
+    pub knowledge_base: KnowledgeBase,
+    pub moderator: Moderator,
+    pub usage_ledger: UsageLedger,
+    pub budgets: Budgets,
+    pub store: Arc<dyn Store>,
+}
+
+pub struct HandlerSettings {
+    pub image: ImageSettings,
+    pub speech: SpeechSettings,
+    pub voice_message: VoiceMessageSettings,
+    // Number of messages and summaries kept in memory for reply chains.
+    pub lru_cache_size: usize,
+    // Used until a prompt is set with !gpt-prompt, the built-in one if unset.
+    pub default_prompt: Option<String>,
+}
+
 pub struct Handler {
-    ogpt_async_client: OGptAsyncClient,
+    // Generates images and speech and transcribes voice messages, whichever backend answers questions.
+    media_backend: Arc<dyn MediaBackend>,
+    chat_routes: ChatRoutes,
+    image_settings: ImageSettings,
+    speech_settings: SpeechSettings,
+    knowledge_base: KnowledgeBase,
+    moderator: Moderator,
+    voice_message_settings: VoiceMessageSettings,
+    usage_ledger: Arc<UsageLedger>,
+    budgets: Arc<Budgets>,
+    store: Arc<dyn Store>,
+    // Music is restored on the first ready event only, later ones are reconnects.
+    music_restored: AtomicBool,
+    speakers: Arc<command::Speakers>,
     message_cache: Arc<Mutex<LruCache<u64, MessageLite>>>,
+    // Summaries of reply chains, keyed by the id of the newest message each one covers.
+    summary_cache: Arc<Mutex<LruCache<u64, String>>>,
     prompt: Arc<Mutex<String>>,
 }
 
 impl Handler {
-    pub fn new(open_api_key: String, lru_cache_size: usize, default_prompt: Option<String>) -> Handler {
-        let prompt = match default_prompt {
+    pub fn new(deps: HandlerDeps, settings: HandlerSettings) -> Handler {
+        let HandlerDeps { media_backend, chat_routes, knowledge_base, moderator, usage_ledger, budgets, store } = deps;
+        // A prompt set with !gpt-prompt before the restart wins over the configured one.
+        let stored_prompt = store.prompt().unwrap_or_else(|err| {
+            eprintln!("Error loading the stored prompt - {}", err);
+            None
+        });
+        let prompt = match stored_prompt.or(settings.default_prompt) {
             Some(prompt) => prompt,
             None => String::from(GPT_DEFAULT_SYSTEM_PROMPT),
         };
 
         Handler {
-            ogpt_async_client: OGptAsyncClient::new(open_api_key),
-            message_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(lru_cache_size).unwrap()))),
+            media_backend,
+            chat_routes,
+            image_settings: settings.image,
+            speech_settings: settings.speech,
+            knowledge_base,
+            moderator,
+            voice_message_settings: settings.voice_message,
+            usage_ledger: Arc::new(usage_ledger),
+            budgets: Arc::new(budgets),
+            store,
+            music_restored: AtomicBool::new(false),
+            speakers: Arc::new(command::Speakers::default()),
+            message_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(settings.lru_cache_size).unwrap()))),
+            summary_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(settings.lru_cache_size).unwrap()))),
             prompt: Arc::new(Mutex::new(prompt)),
         }
     }
 
-    pub fn cache_message(&self, msg: &Message, ctx: &Context) {
-        let mut r = self.message_cache.lock().unwrap();
-        r.put(msg.id.0, MessageLite::from_msg(msg, ctx));
+    // Every message is cached for replies to it, but only conversations with the bot are stored.
+    pub async fn cache_message(&self, msg: &Message, ctx: &Context) {
+        let message = MessageLite::from_msg(msg, ctx);
+        // Streamed replies are cached with their final content once editing is done, so a late
+        // create event for the placeholder must not overwrite it, in the cache or in the store.
+        let added = {
+            let mut r = self.message_cache.lock().unwrap();
+            !r.contains(&msg.id.0) && r.put(msg.id.0, message.clone()).is_none()
+        };
+        if added && is_conversation(msg, ctx) {
+            self.store_message(message, false).await;
+        }
     }
 
-    pub fn get_referenced_from_cache(&self, msg: &MessageLite) -> Option<MessageLite> {
-        match &msg.ref_msg_id {
-            Some(ref_id) => {
-                let mut r = self.message_cache.lock().unwrap();
-                r.get(ref_id).map(|m| m.clone())
+    pub async fn update_cached_message(&self, msg: &Message, ctx: &Context) {
+        let message = MessageLite::from_msg(msg, ctx);
+        self.message_cache.lock().unwrap().put(msg.id.0, message.clone());
+        self.store_message(message, true).await;
+    }
+
+    // Caches a message under its id with content other than what Discord has, e.g. a voice message's transcript.
+    pub async fn put_cached_message(&self, message: MessageLite) {
+        self.message_cache.lock().unwrap().put(message.id, message.clone());
+        self.store_message(message, true).await;
+    }
+
+    // Messages evicted from the cache, or seen before a restart, are read back from the store.
+    pub async fn get_referenced_from_cache(&self, msg: &MessageLite) -> Option<MessageLite> {
+        let ref_id = msg.ref_msg_id?;
+        if let Some(message) = self.message_cache.lock().unwrap().get(&ref_id) {
+            return Some(message.clone());
+        }
+
+        match storage::blocking(&self.store, move |store| store.message(ref_id)).await {
+            Ok(Some(message)) => {
+                self.message_cache.lock().unwrap().put(ref_id, message.clone());
+                Some(message)
             },
-            None => None
+            Ok(None) => None,
+            Err(err) => {
+                eprintln!("Error loading message {} from the store - {}", ref_id, err);
+                None
+            }
+        }
+    }
+
+    // A conversation can go on without its history being stored, so failures are only logged. Unless
+    // `replace` is set, a message already stored under the same id is kept.
+    async fn store_message(&self, message: MessageLite, replace: bool) {
+        let id = message.id;
+        let stored = storage::blocking(&self.store, move |store| {
+            if replace { store.put_message(&message) } else { store.add_message(&message) }
+        }).await;
+        if let Err(err) = stored {
+            eprintln!("Error storing message {} - {}", id, err);
+        }
+    }
+
+    pub fn get_cached_summary(&self, msg_id: u64) -> Option<String> {
+        let mut r = self.summary_cache.lock().unwrap();
+        r.get(&msg_id).map(|s| s.to_owned())
+    }
+
+    pub fn knowledge_base(&self) -> &KnowledgeBase {
+        &self.knowledge_base
+    }
+
+    pub fn voice_message_settings(&self) -> &VoiceMessageSettings {
+        &self.voice_message_settings
+    }
+
+    pub fn store(&self) -> &Arc<dyn Store> {
+        &self.store
+    }
+
+    pub fn speakers(&self) -> Arc<command::Speakers> {
+        self.speakers.clone()
+    }
+
+    pub fn usage_ledger(&self) -> &UsageLedger {
+        &self.usage_ledger
+    }
+
+    // The chat backend and model answering in `guild_id`.
+    fn chat_route(&self, guild_id: Option<GuildId>) -> &ChatRoute {
+        self.chat_routes.for_guild(guild_id.map(|id| id.0))
+    }
+
+    // A user message for the model, carrying the image attachments if the model can see them.
+    pub fn user_message(&self, guild_id: Option<GuildId>, text: String, image_urls: &[String]) -> chat_completions::Message {
+        if self.chat_route(guild_id).settings.supports_vision {
+            conversation::user_message(text, image_urls)
+        } else {
+            chat_completions::Message::user(text)
         }
     }
 
@@ -56,41 +217,330 @@ impl Handler {
         self.prompt.lock().unwrap().to_owned()
     }
 
-    pub fn set_prompt(&self, prompt: String) {
+    pub async fn set_prompt(&self, prompt: String) -> Result<(), ServerError> {
+        let stored = prompt.clone();
+        storage::blocking(&self.store, move |store| store.set_prompt(&stored)).await?;
         let mut r = self.prompt.lock().unwrap();
         *r = prompt;
+        Ok(())
     }
 
-    pub async fn get_gpt_response(&self, messages: Vec<chat_completions::Message>) -> Result<chat_completions::ChatCompletionsResponse, ServerError> {
-        let response = self
-            .ogpt_async_client
-            .chat_completion_async(
-                &chat_completions::ChatCompletionsRequest::default(
-                    String::from("gpt-3.5-turbo"), messages))
+    // Builds the request for `messages`, dropping or truncating the oldest turns so the prompt leaves
+    // room for `max_tokens` of output within the model's context window.
+    fn build_request(route: &ChatRoute, messages: Vec<chat_completions::Message>) -> Result<chat_completions::ChatCompletionsRequest, ServerError> {
+        let settings = &route.settings;
+        let trimmed = conversation::trim_to_budget(&settings.model, messages, settings.max_prompt_tokens())?;
+        if !trimmed.dropped.is_empty() {
+            println!("Dropped {} messages to fit the context window of {}", trimmed.dropped.len(), settings.model);
+        }
+
+        Ok(chat_completions::ChatCompletionsRequest::default(settings.model.to_owned(), trimmed.messages)
+            .max_tokens(settings.max_tokens)?)
+    }
+
+    // Fits a reply chain into the context budget. Turns that don't fit are condensed into a summary by
+    // the summary model instead of being dropped, and the summary is cached under the newest message it
+    // covers so later replies in the chain can start from it.
+    pub async fn compact_conversation(&self, ctx: &Context, msg: &Message, turns: Vec<ConversationTurn>) -> Result<Vec<chat_completions::Message>, ServerError> {
+        let route = self.chat_route(msg.guild_id);
+        let settings = &route.settings;
+        let messages: Vec<chat_completions::Message> = turns.iter().map(|turn| turn.message.clone()).collect();
+        let budget = settings.max_prompt_tokens().saturating_sub(summary::SUMMARY_MAX_TOKENS as usize * 2);
+        let trimmed = conversation::trim_to_budget(&settings.model, messages.clone(), budget)?;
+        if trimmed.dropped.is_empty() {
+            return Ok(messages);
+        }
+
+        // Like `trim_to_budget`, treat a leading system message as the system prompt that is always kept.
+        let offset = match messages.first() {
+            Some(message) if message.role == chat_completions::Role::System => 1,
+            _ => 0,
+        };
+        let summary_key = turns[offset..offset + trimmed.dropped.len()]
+            .iter()
+            .rev()
+            .find_map(|turn| turn.message_id);
+
+        let summary = match summary_key.and_then(|key| self.get_cached_summary(key)) {
+            Some(summary) => summary,
+            // Without a summary the reply can still be answered from the turns that fit.
+            None => match self.summarize(ctx, msg, route, &trimmed.dropped).await {
+                Ok(summary) if !summary.is_empty() => {
+                    if let Some(key) = summary_key {
+                        self.summary_cache.lock().unwrap().put(key, summary.to_owned());
+                    }
+                    summary
+                },
+                Ok(_) => {
+                    eprintln!("Empty summary of {} dropped messages", trimmed.dropped.len());
+                    return Ok(trimmed.messages);
+                },
+                Err(err) => {
+                    eprintln!("Error summarizing {} dropped messages - {}", trimmed.dropped.len(), err);
+                    return Ok(trimmed.messages);
+                },
+            },
+        };
+
+        // The system prompt may have been left out if it didn't fit.
+        let mut messages = trimmed.messages;
+        let summary_index = match messages.first() {
+            Some(message) if message.role == chat_completions::Role::System => 1,
+            _ => 0,
+        };
+        messages.insert(summary_index, summary::summary_message(&summary));
+        Ok(messages)
+    }
+
+    async fn summarize(&self, ctx: &Context, msg: &Message, route: &ChatRoute, dropped: &[chat_completions::Message]) -> Result<String, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let settings = &route.settings;
+        let request = chat_completions::ChatCompletionsRequest::default(
+                settings.summary_model.to_owned(),
+                summary::summary_request_messages(&settings.summary_model, dropped))
+            .max_tokens(summary::SUMMARY_MAX_TOKENS)?;
+        let response = route.backend.chat(&request).await?;
+        Handler::record_usage(&self.usage_ledger, &self.budgets, &ctx.http, Attribution::from_msg(msg), UsageKind::Chat, &response.model, &response.usage);
+        Ok(ogpt::utils::get_chat_message(&response, 0).unwrap_or_default().trim().to_owned())
+    }
+
+    // Checks a question from `msg` against the guild's moderation policy before it is sent to the model.
+    // Questions can't be partially answered, so redacted ones are blocked too.
+    // Over budget, the question would be refused anyway, so it isn't checked either.
+    pub async fn moderate_input(&self, ctx: &Context, msg: &Message, text: &str) -> Result<(), ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        match self.moderate(ctx, msg, Stage::Input, text).await {
+            Some(ModerationAction::Redact | ModerationAction::Block) => Err(ServerError::ModerationBlocked(Stage::Input)),
+            _ => Ok(()),
+        }
+    }
+
+    // Whether answers in the guild are checked, in which case they have to be complete before they are shown.
+    pub fn moderates_output(&self, msg: &Message) -> bool {
+        self.moderator.policy(msg.guild_id.map(|id| id.0), Stage::Output).is_some()
+    }
+
+    // Checks the answer to `msg` against the guild's moderation policy, returning what may be posted.
+    pub async fn moderate_output(&self, ctx: &Context, msg: &Message, text: String) -> Result<String, ServerError> {
+        match self.moderate(ctx, msg, Stage::Output, &text).await {
+            Some(ModerationAction::Block) => Err(ServerError::ModerationBlocked(Stage::Output)),
+            Some(ModerationAction::Redact) => Ok(String::from(moderation::REDACTED_RESPONSE)),
+            _ => Ok(text),
+        }
+    }
+
+    // Runs `text` through the moderation policy and reports any decision to the mod log.
+    async fn moderate(&self, ctx: &Context, msg: &Message, stage: Stage, text: &str) -> Option<ModerationAction> {
+        let policy = self.moderator.policy(msg.guild_id.map(|id| id.0), stage)?;
+        // Moderation is free, so checks aren't recorded as requests in the usage ledger.
+        let verdict = match self.moderator.check(policy, text).await {
+            Ok(verdict) => verdict?,
+            Err(err) => {
+                eprintln!("Error moderating the {} of message {} - {}", stage, msg.id.0, err);
+                if !policy.block_on_error {
+                    return None;
+                }
+                Verdict { action: ModerationAction::Block, categories: vec![] }
+            }
+        };
+
+        if let Some(channel_id) = policy.mod_log_channel {
+            // The entry quotes the moderated text, whose mentions must not ping anyone.
+            let entry = moderation::log_entry(stage, &verdict, msg, text);
+            let sent = ChannelId(channel_id)
+                .send_message(&ctx.http, |m| m.content(entry).allowed_mentions(|mentions| mentions.empty_parse()))
+                .await;
+            if let Err(err) = sent {
+                eprintln!("Error sending mod log entry - {}", err);
+            }
+        }
+        Some(verdict.action)
+    }
+
+    pub async fn get_gpt_response(&self, ctx: &Context, msg: &Message, messages: Vec<chat_completions::Message>) -> Result<chat_completions::ChatCompletionsResponse, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let route = self.chat_route(msg.guild_id);
+        let response = route.backend
+            .chat(&Handler::build_request(route, messages)?)
             .await?;
+        Handler::record_usage(&self.usage_ledger, &self.budgets, &ctx.http, Attribution::from_msg(msg), UsageKind::Chat, &response.model, &response.usage);
+
+        if response.attempts > 1 {
+            println!("Chat completion {} succeeded after {} attempts", response.id, response.attempts);
+        }
+        Ok(response)
+    }
+
+    pub async fn generate_images(&self, ctx: &Context, msg: &Message, prompt: String) -> Result<images::ImagesResponse, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let settings = &self.image_settings;
+        let mut request = images::ImagesRequest::new(prompt)
+            .model(settings.model.to_owned())
+            .size(settings.size.to_owned());
+        if let Some(quality) = &settings.quality {
+            request = request.quality(quality.to_owned());
+        }
+        // dall-e models return short-lived URLs by default, ask for the data so it can be uploaded directly.
+        if settings.model.starts_with("dall-e") {
+            request = request.response_format(images::ImageResponseFormat::B64Json);
+        }
+
+        let response = self.media_backend.images(&request).await?;
+        let images = response.data.len() as u64;
+        let cost = pricing::image_cost(&settings.model, &settings.size, settings.quality.as_deref(), images);
+        self.record_media(ctx, msg, UsageKind::Image, &settings.model, images, cost);
         Ok(response)
     }
+
+    // Speaks `text` as mp3 audio.
+    pub async fn speak(&self, ctx: &Context, msg: &Message, text: &str) -> Result<Vec<u8>, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let settings = &self.speech_settings;
+        let input: String = text.chars().take(MAX_SPEECH_INPUT_CHARS).collect();
+        let characters = input.chars().count();
+        let cost = pricing::speech_cost(&settings.model, characters);
+        let request = speech::SpeechRequest::new(settings.model.to_owned(), input, settings.voice.to_owned())
+            .response_format(speech::SpeechFormat::Mp3);
+        let audio = self.media_backend.speech(&request).await?;
+        self.record_media(ctx, msg, UsageKind::Speech, &settings.model, characters as u64, cost);
+        Ok(audio)
+    }
+
+    // Transcribes a voice message. Only verbose responses say how long the audio was, so the length of
+    // anything else is read from its ogg pages to price it.
+    pub async fn transcribe(&self, ctx: &Context, msg: &Message, filename: String, audio: Vec<u8>) -> Result<String, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let model = &self.voice_message_settings.transcription_model;
+        let duration = ogg_duration(&audio);
+        let request = audio::AudioRequest::new(model.to_owned(), filename, audio);
+        let response = self.media_backend.transcription(&request).await?;
+        let seconds = response.duration.or(duration);
+        let cost = seconds.and_then(|seconds| pricing::transcription_cost(model, seconds));
+        self.record_media(ctx, msg, UsageKind::Transcription, model, seconds.map_or(0, |seconds| seconds.ceil() as u64), cost);
+        Ok(response.text)
+    }
+
+    // The chunks of the guild's knowledge base most relevant to the question in `msg`.
+    pub async fn search_knowledge(&self, ctx: &Context, msg: &Message, guild_id: u64, question: &str) -> Result<Vec<RetrievedChunk>, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        self.knowledge_base
+            .search(guild_id, question, &|model, usage| self.record_usage_of(ctx, msg, UsageKind::Embedding, model, usage))
+            .await
+    }
+
+    // Adds a document to the guild's knowledge base for `msg`, returning the number of chunks stored.
+    pub async fn add_knowledge(&self, ctx: &Context, msg: &Message, guild_id: u64, source: &str, text: &str) -> Result<usize, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        self.knowledge_base
+            .add_document(guild_id, source, text, &|model, usage| self.record_usage_of(ctx, msg, UsageKind::Embedding, model, usage))
+            .await
+    }
+
+    // Usage arrives with the last chunk, so a stream dropped early goes unrecorded.
+    pub async fn get_gpt_response_stream(&self, ctx: &Context, msg: &Message, messages: Vec<chat_completions::Message>) -> Result<ChatCompletionsStream, ServerError> {
+        self.budgets.check(&self.usage_ledger, msg)?;
+        let route = self.chat_route(msg.guild_id);
+        let stream = route.backend
+            .stream(&Handler::build_request(route, messages)?)
+            .await?;
+
+        let (usage_ledger, budgets, http) = (self.usage_ledger.clone(), self.budgets.clone(), ctx.http.clone());
+        let attribution = Attribution::from_msg(msg);
+        let stream = stream.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                if let Some(usage) = &chunk.usage {
+                    Handler::record_usage(&usage_ledger, &budgets, &http, attribution, UsageKind::Chat, &chunk.model, usage);
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+
+    // Records what a request cost and tells the guild's admin channel when that takes it past a warning
+    // threshold of its monthly cap. Takes the parts of the handler it needs so streams can call it once
+    // their usage arrives.
+    fn record_usage(usage_ledger: &UsageLedger, budgets: &Budgets, http: &Arc<Http>, attribution: Attribution, kind: UsageKind, model: &str, usage: &chat_completions::Usage) {
+        let record = usage_ledger.record(attribution, kind, model, usage);
+        Handler::warn_about_budget(usage_ledger, budgets, http, &record);
+    }
+
+    fn record_usage_of(&self, ctx: &Context, msg: &Message, kind: UsageKind, model: &str, usage: &chat_completions::Usage) {
+        Handler::record_usage(&self.usage_ledger, &self.budgets, &ctx.http, Attribution::from_msg(msg), kind, model, usage);
+    }
+
+    // Like `record_usage` for images, speech and transcriptions, which are priced by their `units`.
+    fn record_media(&self, ctx: &Context, msg: &Message, kind: UsageKind, model: &str, units: u64, cost: Option<f64>) {
+        let record = self.usage_ledger.record_media(Attribution::from_msg(msg), kind, model, units, cost);
+        Handler::warn_about_budget(&self.usage_ledger, &self.budgets, &ctx.http, &record);
+    }
+
+    fn warn_about_budget(usage_ledger: &UsageLedger, budgets: &Budgets, http: &Arc<Http>, record: &UsageRecord) {
+        if let Some((channel_id, warning)) = budgets.warning(usage_ledger, record) {
+            let http = http.clone();
+            tokio::spawn(async move {
+                if let Err(err) = ChannelId(channel_id).say(&http, warning).await {
+                    eprintln!("Error sending budget warning - {}", err);
+                }
+            });
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct MessageLite {
+    pub id: u64,
     pub ref_msg_id: Option<u64>,
     pub content: String,
     pub author_name: String,
     pub is_own: bool,
+    pub image_urls: Vec<String>,
 }
 
 impl MessageLite {
     pub fn from_msg(msg: &Message, ctx: &Context) -> MessageLite {
         MessageLite {
+            id: msg.id.0,
             ref_msg_id: msg.referenced_message.as_ref().map(|x| x.id.0),
             content: msg.content.to_owned(),
             author_name: msg.author.name.to_owned(),
             is_own: msg.is_own(&ctx.cache),
+            image_urls: image_urls(msg),
         }
     }
 }
 
+pub fn image_urls(msg: &Message) -> Vec<String> {
+    msg.attachments
+        .iter()
+        .filter(|attachment| match &attachment.content_type {
+            Some(content_type) => IMAGE_CONTENT_TYPES.contains(&content_type.as_str()),
+            None => false,
+        })
+        .map(|attachment| attachment.url.to_owned())
+        .collect()
+}
+
+// Seconds of audio in an ogg/opus file, read from the granule position of its last page, which counts
+// samples at 48 kHz whatever the input rate was.
+fn ogg_duration(audio: &[u8]) -> Option<f64> {
+    let page = audio.windows(OGG_CAPTURE_PATTERN.len()).rposition(|window| window == OGG_CAPTURE_PATTERN)?;
+    let granule_position = audio.get(page + 6..page + 14)?;
+    let samples = u64::from_le_bytes(granule_position.try_into().ok()?);
+    Some(samples as f64 / OPUS_SAMPLE_RATE)
+}
+
+// Whether `msg` can be part of a conversation with the bot: a question to it, a reply to it or one of its
+// own messages that doesn't answer another command.
+fn is_conversation(msg: &Message, ctx: &Context) -> bool {
+    if msg.is_own(&ctx.cache) {
+        return msg.referenced_message.as_ref().is_none_or(|referenced| {
+            !referenced.content.starts_with(command::PREFIX) || command::gpt_question(&referenced.content).is_some()
+        });
+    }
+    command::gpt_question(&msg.content).is_some()
+        || msg.referenced_message.as_ref().is_some_and(|referenced| referenced.is_own(&ctx.cache))
+}
+
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
@@ -105,10 +555,200 @@ impl EventHandler for Handler {
                 break;
             }
         }
-        self.cache_message(&msg, &ctx);        
+        self.cache_message(&msg, &ctx).await;
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+        if !self.music_restored.swap(true, Ordering::SeqCst) {
+            command::restore_music(self, &ctx).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ogpt::{error::OGptError, testing::{FakeBackend, FakeMediaRequest}};
+
+    use crate::config::{ChatSettings, UsageSettings};
+    use crate::storage::MemoryStore;
+    use crate::testing::{self, USER_ID};
+    use crate::usage::{Budget, UsageFilter};
+
+    use super::*;
+
+    fn turns(count: u64) -> Vec<ConversationTurn> {
+        let mut turns = vec![ConversationTurn::new(None, chat_completions::Role::System, String::from(GPT_DEFAULT_SYSTEM_PROMPT))];
+        turns.extend((1..=count).map(|id| {
+            let role = if id % 2 == 1 { chat_completions::Role::User } else { chat_completions::Role::Assistant };
+            ConversationTurn::new(Some(id), role, format!("Message {} {}", id, "lorem ipsum dolor sit amet ".repeat(20)))
+        }));
+        turns
+    }
+
+    // A context window that only fits the system prompt and a few of the turns above.
+    fn small_window() -> ChatSettings {
+        ChatSettings { context_window: 1200, max_tokens: 100, ..testing::chat_settings() }
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_guild_backend_and_records_usage() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.reply("Paris.");
+        let handler = testing::handler(backend.clone(), testing::chat_settings());
+        let msg = testing::message(1, "!gpt What is the capital of France?");
+
+        let response = handler.get_gpt_response(&testing::context(), &msg, vec![chat_completions::Message::user(String::from("What is the capital of France?"))]).await.unwrap();
+        assert_eq!(ogpt::utils::get_chat_message(&response, 0), Some("Paris."));
+
+        let request = &backend.chat_requests()[0];
+        assert_eq!(request.model, "gpt-4o-mini");
+        assert_eq!(request.max_tokens, Some(testing::chat_settings().max_tokens));
+
+        let totals = handler.usage_ledger().totals(&UsageFilter::default().user(USER_ID));
+        assert_eq!(totals.requests, 1);
+        assert_eq!((totals.prompt_tokens, totals.completion_tokens), (response.usage.prompt_tokens, response.usage.completion_tokens));
+        assert!(totals.cost > 0.0);
+    }
+
+    #[tokio::test]
+    async fn backend_errors_reach_the_caller_unrecorded() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.fail(OGptError::InvalidResponse(String::from("overloaded")));
+        let handler = testing::handler(backend, testing::chat_settings());
+
+        let msg = testing::message(1, "!gpt Hi");
+        assert!(handler.get_gpt_response(&testing::context(), &msg, vec![chat_completions::Message::user(String::from("Hi"))]).await.is_err());
+        assert_eq!(handler.usage_ledger().totals(&UsageFilter::default()).requests, 0);
+    }
+
+    #[tokio::test]
+    async fn summarizes_the_turns_that_dont_fit() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.reply("They talked about lorem ipsum.");
+        let handler = testing::handler(backend.clone(), small_window());
+        let msg = testing::message(20, "!gpt And then?");
+
+        let messages = handler.compact_conversation(&testing::context(), &msg, turns(12)).await.unwrap();
+        assert_eq!(messages[0].role, chat_completions::Role::System);
+        assert_eq!(messages[1], summary::summary_message("They talked about lorem ipsum."));
+        assert!(messages.len() < 12);
+
+        let requests = backend.chat_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].model, small_window().summary_model);
+        assert_eq!(handler.usage_ledger().totals(&UsageFilter::default()).requests, 1);
+
+        // The next reply in the chain starts from the cached summary.
+        handler.compact_conversation(&testing::context(), &msg, turns(12)).await.unwrap();
+        assert_eq!(backend.chat_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_turns_that_fit_when_summarizing_fails() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.fail(OGptError::InvalidResponse(String::from("overloaded")));
+        let handler = testing::handler(backend, small_window());
+        let msg = testing::message(20, "!gpt And then?");
+
+        let messages = handler.compact_conversation(&testing::context(), &msg, turns(12)).await.unwrap();
+        assert!(messages.len() < 12);
+        assert!(messages.iter().all(|message| *message != summary::summary_message("")));
+        assert_eq!(messages.last().unwrap().text(), turns(12).last().unwrap().message.text());
+    }
+
+    #[tokio::test]
+    async fn media_goes_through_the_media_backend_and_is_recorded() {
+        let backend = Arc::new(FakeBackend::new());
+        backend.transcript("Hello there");
+        let handler = testing::handler(backend.clone(), testing::chat_settings());
+        let (ctx, msg) = (testing::context(), testing::message(1, "!imagine A crab"));
+        // The last ogg page of 90 seconds of opus audio.
+        let mut voice_message = b"OggS\0\x04".to_vec();
+        voice_message.extend((90 * 48_000u64).to_le_bytes());
+        voice_message.extend([0; 12]);
+
+        let images = handler.generate_images(&ctx, &msg, String::from("A crab")).await.unwrap();
+        assert!(images.data[0].b64_json.is_some());
+        assert_eq!(handler.speak(&ctx, &msg, "Hi").await.unwrap(), b"Hi".to_vec());
+        assert_eq!(handler.transcribe(&ctx, &msg, String::from("voice-message.ogg"), voice_message).await.unwrap(), "Hello there");
+
+        let requests = backend.media_requests();
+        assert!(matches!(&requests[0], FakeMediaRequest::Images(request) if request.response_format == Some(images::ImageResponseFormat::B64Json)));
+        assert!(matches!(&requests[1], FakeMediaRequest::Speech(request) if request.model == "tts-1"));
+        assert!(matches!(&requests[2], FakeMediaRequest::Transcription(request) if request.model == "whisper-1"));
+
+        let records = handler.usage_ledger().records(&UsageFilter::default().user(USER_ID));
+        let costs: Vec<(&str, f64)> = records.iter().map(|record| (record.model.as_str(), record.cost)).collect();
+        assert_eq!(costs.len(), 3);
+        assert_eq!(costs[0], ("dall-e-3", 0.04));
+        assert_eq!(costs[1].0, "tts-1");
+        assert!((costs[1].1 - 0.00003).abs() < 1e-12);
+        assert_eq!(costs[2].0, "whisper-1");
+        assert!((costs[2].1 - 0.009).abs() < 1e-12);
+        let units: Vec<(UsageKind, u64)> = records.iter().map(|record| (record.kind, record.units)).collect();
+        assert_eq!(units, vec![(UsageKind::Image, 1), (UsageKind::Speech, 2), (UsageKind::Transcription, 90)]);
+        let totals = handler.usage_ledger().totals(&UsageFilter::default().user(USER_ID));
+        assert_eq!((totals.requests, totals.media_requests, totals.total_tokens()), (0, 3, 0));
+    }
+
+    #[tokio::test]
+    async fn media_is_refused_over_budget() {
+        let backend = Arc::new(FakeBackend::new());
+        let policies = serde_json::json!({ "default": { "user_daily_tokens": 10 } });
+        let budget_file = std::env::temp_dir().join(format!("gpt-discord-bot-test-budget-{}.json", std::process::id()));
+        std::fs::write(&budget_file, policies.to_string()).unwrap();
+        let budgets = Budgets::new(&UsageSettings { price_file: None, budget_file: Some(budget_file.clone()) }).unwrap();
+        std::fs::remove_file(budget_file).unwrap();
+        let handler = testing::handler_with(backend.clone(), backend.clone(), testing::chat_settings(), Arc::new(MemoryStore::new()), budgets);
+        let (ctx, msg) = (testing::context(), testing::message(1, "!imagine A crab"));
+        handler.usage_ledger().record(Attribution::from_msg(&msg), UsageKind::Chat, "gpt-4o-mini", &chat_completions::Usage::new(10, 0));
+
+        assert!(matches!(handler.generate_images(&ctx, &msg, String::from("A crab")).await, Err(ServerError::BudgetExceeded(_))));
+        assert!(matches!(handler.speak(&ctx, &msg, "Hi").await, Err(ServerError::BudgetExceeded(_))));
+        assert!(matches!(handler.search_knowledge(&ctx, &msg, testing::GUILD_ID, "Hi").await, Err(ServerError::BudgetExceeded(_))));
+        assert!(backend.media_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn media_is_refused_over_the_monthly_cap_under_the_daily_tokens() {
+        let backend = Arc::new(FakeBackend::new());
+        let policies = serde_json::json!({ "default": { "monthly_cap": 0.05, "user_daily_tokens": 1000 } });
+        let budget_file = std::env::temp_dir().join(format!("gpt-discord-bot-test-cap-{}.json", std::process::id()));
+        std::fs::write(&budget_file, policies.to_string()).unwrap();
+        let budgets = Budgets::new(&UsageSettings { price_file: None, budget_file: Some(budget_file.clone()) }).unwrap();
+        std::fs::remove_file(budget_file).unwrap();
+        let handler = testing::handler_with(backend.clone(), backend.clone(), testing::chat_settings(), Arc::new(MemoryStore::new()), budgets);
+        let (ctx, msg) = (testing::context(), testing::message(1, "!imagine A crab"));
+
+        // Two images take the guild past its cap without using any of the user's tokens.
+        handler.generate_images(&ctx, &msg, String::from("A crab")).await.unwrap();
+        handler.generate_images(&ctx, &msg, String::from("A crab")).await.unwrap();
+        let today = handler.usage_ledger().user_day_totals(msg.guild_id.map(|id| id.0), USER_ID, crate::usage::unix_time());
+        assert_eq!(today.total_tokens(), 0);
+
+        assert!(matches!(handler.generate_images(&ctx, &msg, String::from("A crab")).await, Err(ServerError::BudgetExceeded(Budget::GuildMonthly))));
+        assert!(matches!(handler.speak(&ctx, &msg, "Hi").await, Err(ServerError::BudgetExceeded(Budget::GuildMonthly))));
+        assert_eq!(backend.media_requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stores_only_conversations_with_the_bot() {
+        let backend = Arc::new(FakeBackend::new());
+        let store = Arc::new(MemoryStore::new());
+        let budgets = Budgets::new(&UsageSettings { price_file: None, budget_file: None }).unwrap();
+        let handler = testing::handler_with(backend.clone(), backend, testing::chat_settings(), store.clone(), budgets);
+        let ctx = testing::context();
+
+        handler.cache_message(&testing::message(1, "Anyone around tonight?"), &ctx).await;
+        handler.cache_message(&testing::message(2, "!gpt What is the capital of France?"), &ctx).await;
+        handler.cache_message(&testing::message(4, "!gpt-prompt Answer like a pirate"), &ctx).await;
+
+        assert!(store.message(1).unwrap().is_none());
+        assert_eq!(store.message(2).unwrap().unwrap().content, "!gpt What is the capital of France?");
+        assert!(store.message(4).unwrap().is_none());
+        // Both are still cached for replies while the bot runs.
+        let reply = MessageLite { ref_msg_id: Some(1), ..MessageLite::from_msg(&testing::message(3, "Me"), &ctx) };
+        assert_eq!(handler.get_referenced_from_cache(&reply).await.unwrap().id, 1);
     }
 }
@@ -1,4 +1,9 @@
+mod conversation;
 mod handler;
+mod summary;
 
-pub use handler::Handler;
-pub use handler::MessageLite;
\ No newline at end of file
+pub use conversation::ConversationTurn;
+pub use handler::{Handler, HandlerDeps, HandlerSettings};
+pub use handler::MessageLite;
+pub use handler::image_urls;
+pub use summary::summary_message;
\ No newline at end of file
@@ -0,0 +1,38 @@
+use ogpt::{model::chat_completions::{Message, Role}, tokenizer};
+
+pub const SUMMARY_MAX_TOKENS: u64 = 300;
+
+const SUMMARY_SYSTEM_PROMPT: &str = "You summarize Discord conversations between users and an AI assistant. \
+Write a concise summary of the conversation below that keeps every fact, decision, name and open question \
+needed to continue it. Reply with the summary only.";
+const SUMMARY_MESSAGE_PREFIX: &str = "Summary of the earlier conversation:\n";
+
+// Rendered transcripts are cut to this many tokens so the summarization call itself always fits.
+const MAX_TRANSCRIPT_TOKENS: usize = 12_000;
+
+pub fn summary_message(summary: &str) -> Message {
+    Message::system(format!("{}{}", SUMMARY_MESSAGE_PREFIX, summary))
+}
+
+// Request messages asking the summary model to condense `messages`, which may start with an earlier summary.
+pub fn summary_request_messages(model: &str, messages: &[Message]) -> Vec<Message> {
+    let transcript = messages
+        .iter()
+        .filter_map(|message| match message.role {
+            Role::System => Some(message.text().into_owned()),
+            Role::User => match message.images().len() {
+                0 => Some(format!("User: {}", message.text())),
+                images => Some(format!("User: {} [{} image(s) attached]", message.text(), images)),
+            },
+            Role::Assistant if message.text().is_empty() => None,
+            Role::Assistant => Some(format!("Assistant: {}", message.text())),
+            Role::Tool | Role::Function => Some(format!("Tool result: {}", message.text())),
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    vec![
+        Message::system(String::from(SUMMARY_SYSTEM_PROMPT)),
+        Message::user(tokenizer::Tokenizer::for_model(model).truncate(&transcript, MAX_TRANSCRIPT_TOKENS)),
+    ]
+}
@@ -0,0 +1,39 @@
+use ogpt::tokenizer::Tokenizer;
+
+// Small enough that a handful of retrieved chunks fit in the prompt next to the conversation.
+pub const CHUNK_TOKENS: usize = 400;
+
+// Splits a document into chunks of at most CHUNK_TOKENS, keeping paragraphs together where possible.
+// Paragraphs that are too long on their own are cut at token boundaries.
+pub fn chunk_text(model: &str, text: &str) -> Vec<String> {
+    let tokenizer = Tokenizer::for_model(model);
+    let text = text.replace("\r\n", "\n");
+
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for paragraph in text.split("\n\n").map(str::trim).filter(|paragraph| !paragraph.is_empty()) {
+        let tokens = tokenizer.count(paragraph);
+        if current_tokens + tokens > CHUNK_TOKENS && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+
+        if tokens > CHUNK_TOKENS {
+            let encoded = tokenizer.encode(paragraph);
+            chunks.extend(encoded.chunks(CHUNK_TOKENS).map(|window| tokenizer.decode(window)));
+            continue;
+        }
+
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+        current_tokens += tokens;
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
@@ -0,0 +1,207 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use ogpt::{client::OGptAsyncClient, model::{chat_completions::Usage, embeddings}, utils::cosine_similarity};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+
+use crate::{ServerError, config::KnowledgeSettings};
+
+use super::chunk::chunk_text;
+
+// Inputs per embeddings request when indexing a document.
+const EMBEDDING_BATCH_SIZE: usize = 64;
+// Keeps a single guild from growing its index, and every search over it, without bound.
+const MAX_CHUNKS_PER_GUILD: usize = 2_000;
+// Chunks less similar to the question than this are not worth the prompt space.
+const MIN_SIMILARITY: f32 = 0.25;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredChunk {
+    source: String,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+struct GuildIndex {
+    // Embeddings of different models can't be compared, so the index remembers which one it used.
+    embedding_model: String,
+    chunks: Vec<StoredChunk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetrievedChunk {
+    pub source: String,
+    pub text: String,
+    pub score: f32,
+}
+
+// Told the model and usage of every embeddings request, so what it cost can be recorded.
+pub type RecordUsage<'a> = &'a (dyn Fn(&str, &Usage) + Sync);
+
+// Per-guild vector indexes, stored as one JSON file per guild and loaded on first use.
+#[derive(Debug)]
+pub struct KnowledgeBase {
+    ogpt_async_client: OGptAsyncClient,
+    settings: KnowledgeSettings,
+    indexes: Mutex<HashMap<u64, GuildIndex>>,
+}
+
+impl KnowledgeBase {
+    pub fn new(ogpt_async_client: OGptAsyncClient, settings: KnowledgeSettings) -> KnowledgeBase {
+        KnowledgeBase {
+            ogpt_async_client,
+            settings,
+            indexes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Chunks and embeds a document, replacing any earlier version of the same source. Returns the
+    // number of chunks stored.
+    pub async fn add_document(&self, guild_id: u64, source: &str, text: &str, record_usage: RecordUsage<'_>) -> Result<usize, ServerError> {
+        let model = &self.settings.embedding_model;
+        let chunks = chunk_text(model, text);
+        if chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let mut embedded = vec![];
+        for batch in chunks.chunks(EMBEDDING_BATCH_SIZE) {
+            let vectors = self.embed(batch.to_vec(), record_usage).await?;
+            embedded.extend(batch.iter().zip(vectors).map(|(text, embedding)| StoredChunk {
+                source: source.to_owned(),
+                text: text.to_owned(),
+                embedding,
+            }));
+        }
+
+        let mut indexes = self.indexes.lock().await;
+        let index = self.load(&mut indexes, guild_id).await?;
+        if index.embedding_model != *model {
+            // Older embeddings can't be searched together with the new ones.
+            index.embedding_model = model.to_owned();
+            index.chunks.clear();
+        }
+        index.chunks.retain(|chunk| chunk.source != source);
+        if index.chunks.len() + embedded.len() > MAX_CHUNKS_PER_GUILD {
+            return Err(ServerError::KnowledgeBaseError(format!(
+                "the knowledge base is limited to {} chunks and {} would add {}",
+                MAX_CHUNKS_PER_GUILD, source, embedded.len()
+            )));
+        }
+
+        let count = embedded.len();
+        index.chunks.extend(embedded);
+        self.save(guild_id, index).await?;
+        Ok(count)
+    }
+
+    // Removes every chunk of `source`, returning how many there were.
+    pub async fn remove_source(&self, guild_id: u64, source: &str) -> Result<usize, ServerError> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.load(&mut indexes, guild_id).await?;
+        let before = index.chunks.len();
+        index.chunks.retain(|chunk| chunk.source != source);
+        let removed = before - index.chunks.len();
+        if removed > 0 {
+            self.save(guild_id, index).await?;
+        }
+        Ok(removed)
+    }
+
+    pub async fn clear(&self, guild_id: u64) -> Result<(), ServerError> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.load(&mut indexes, guild_id).await?;
+        index.chunks.clear();
+        self.save(guild_id, index).await
+    }
+
+    // Sources in the guild's knowledge base with their number of chunks, in the order they were added.
+    pub async fn sources(&self, guild_id: u64) -> Result<Vec<(String, usize)>, ServerError> {
+        let mut indexes = self.indexes.lock().await;
+        let index = self.load(&mut indexes, guild_id).await?;
+
+        let mut sources: Vec<(String, usize)> = vec![];
+        for chunk in &index.chunks {
+            match sources.iter_mut().find(|(source, _)| *source == chunk.source) {
+                Some((_, count)) => *count += 1,
+                None => sources.push((chunk.source.to_owned(), 1)),
+            }
+        }
+        Ok(sources)
+    }
+
+    // The chunks most relevant to `query`, best first.
+    pub async fn search(&self, guild_id: u64, query: &str, record_usage: RecordUsage<'_>) -> Result<Vec<RetrievedChunk>, ServerError> {
+        let query = query.trim();
+        {
+            let mut indexes = self.indexes.lock().await;
+            let index = self.load(&mut indexes, guild_id).await?;
+            if query.is_empty() || index.chunks.is_empty() {
+                return Ok(vec![]);
+            }
+            if index.embedding_model != self.settings.embedding_model {
+                return Err(ServerError::KnowledgeBaseError(format!(
+                    "the knowledge base of guild {} was built with {}, re-add its documents to search it", guild_id, index.embedding_model)));
+            }
+        }
+
+        // Not holding the lock while the query is embedded.
+        let query_embedding = match self.embed(vec![query.to_owned()], record_usage).await?.pop() {
+            Some(embedding) => embedding,
+            None => return Ok(vec![]),
+        };
+
+        let mut indexes = self.indexes.lock().await;
+        let index = self.load(&mut indexes, guild_id).await?;
+        let mut results: Vec<RetrievedChunk> = index
+            .chunks
+            .iter()
+            .map(|chunk| RetrievedChunk {
+                source: chunk.source.to_owned(),
+                text: chunk.text.to_owned(),
+                score: cosine_similarity(&query_embedding, &chunk.embedding),
+            })
+            .filter(|chunk| chunk.score >= MIN_SIMILARITY)
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(self.settings.top_k);
+        Ok(results)
+    }
+
+    async fn embed(&self, input: Vec<String>, record_usage: RecordUsage<'_>) -> Result<Vec<Vec<f32>>, ServerError> {
+        let request = embeddings::EmbeddingsRequest::new(self.settings.embedding_model.to_owned(), input.into());
+        let response = self.ogpt_async_client.embeddings_async(&request).await?;
+        record_usage(&response.model, &Usage::new(response.usage.prompt_tokens, 0));
+        Ok(response.vectors()?)
+    }
+
+    fn path(&self, guild_id: u64) -> PathBuf {
+        self.settings.dir.join(format!("{}.json", guild_id))
+    }
+
+    async fn load<'a>(&self, indexes: &'a mut HashMap<u64, GuildIndex>, guild_id: u64) -> Result<&'a mut GuildIndex, ServerError> {
+        if !indexes.contains_key(&guild_id) {
+            let index = match tokio::fs::read(self.path(guild_id)).await {
+                Ok(bytes) => serde_json::from_slice(&bytes)?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => GuildIndex {
+                    embedding_model: self.settings.embedding_model.to_owned(),
+                    chunks: vec![],
+                },
+                Err(err) => return Err(err.into()),
+            };
+            indexes.insert(guild_id, index);
+        }
+        Ok(indexes.get_mut(&guild_id).unwrap())
+    }
+
+    // Written to a temporary file first so a crash mid-write can't corrupt the index.
+    async fn save(&self, guild_id: u64, index: &GuildIndex) -> Result<(), ServerError> {
+        tokio::fs::create_dir_all(&self.settings.dir).await?;
+        let path = self.path(guild_id);
+        let tmp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(index)?).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
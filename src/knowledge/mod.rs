@@ -0,0 +1,37 @@
+mod chunk;
+mod index;
+
+pub use index::{KnowledgeBase, RetrievedChunk};
+
+const CONTEXT_INSTRUCTIONS: &str = "The following numbered excerpts come from this server's knowledge base. \
+Use them when they are relevant to the question and cite the ones you use by their number, e.g. [1]. \
+If they don't answer the question, say so instead of guessing.";
+
+// System prompt addition presenting the retrieved chunks, numbered from 1 for citations.
+pub fn context_prompt(chunks: &[RetrievedChunk]) -> String {
+    let excerpts = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| format!("[{}] (source: {})\n{}", i + 1, chunk.source, chunk.text))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    format!("{}\n\n{}", CONTEXT_INSTRUCTIONS, excerpts)
+}
+
+// Lists the sources of the excerpts `reply` cites, or None if it cites none. `sources[i]` is the source
+// of excerpt `i + 1`.
+pub fn citation_footer(reply: &str, sources: &[String]) -> Option<String> {
+    let cited: Vec<String> = sources
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| reply.contains(&format!("[{}]", i + 1)))
+        .map(|(i, source)| format!("[{}] {}", i + 1, source))
+        .collect();
+
+    if cited.is_empty() {
+        None
+    } else {
+        Some(format!("Sources: {}", cited.join(", ")))
+    }
+}
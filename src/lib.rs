@@ -1,20 +1,69 @@
+mod backend;
+mod config;
 mod error;
 mod handler;
 mod command;
+mod knowledge;
+mod moderation;
+mod storage;
+mod usage;
+#[cfg(test)]
+mod testing;
 
+pub use config::{BackendKind, BackendSettings, ChatSettings, ImageSettings, KnowledgeSettings, ModerationSettings, OpenAiConfig, ServerSettings, SpeechSettings, StorageSettings, UsageSettings, VoiceMessageSettings};
 pub use error::ServerError;
+use std::sync::Arc;
+use std::time::Duration;
+
 use serenity::prelude::GatewayIntents;
 use serenity::prelude::Client as SerenityClient;
 use songbird::SerenityInit;
 
-pub async fn start_server(discord_token: String, openai_token: String) -> Result<(), error::ServerError> {
+// Reply chains reaching further back than this lose their oldest messages after a restart.
+const MESSAGE_RETENTION: Duration = Duration::from_secs(30 * 86_400);
+const MESSAGE_PRUNE_INTERVAL: Duration = Duration::from_secs(86_400);
+
+pub async fn start_server(discord_token: String, settings: ServerSettings) -> Result<(), error::ServerError> {
+    let ServerSettings { openai: openai_config, knowledge: knowledge_settings, voice_message: voice_message_settings, moderation: moderation_settings, backend: backend_settings, usage: usage_settings, storage: storage_settings } = settings;
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT
         | GatewayIntents::GUILD_VOICE_STATES;
 
-    let handler = handler::Handler::new(openai_token, 350, None);
+    let cassette = openai_config.open_cassette()?;
+    let ogpt_async_client = openai_config.build_client(cassette.clone())?;
+    let knowledge_base = knowledge::KnowledgeBase::new(ogpt_async_client.clone(), knowledge_settings);
+    let moderator = moderation::Moderator::new(ogpt_async_client.clone(), moderation_settings)?;
+    let store: Arc<dyn storage::Store> = match &storage_settings.database_file {
+        Some(path) => Arc::new(storage::SqliteStore::open(path)?),
+        None => Arc::new(storage::MemoryStore::new()),
+    };
+    tokio::spawn(storage::prune_messages(store.clone(), MESSAGE_RETENTION, MESSAGE_PRUNE_INTERVAL));
+    let imported = usage::import_usage_log(&storage_settings.usage_log, store.as_ref())?;
+    if imported > 0 {
+        println!("Imported {} usage records from {}", imported, storage_settings.usage_log.display());
+    }
+
+    let budgets = usage::Budgets::new(&usage_settings)?;
+    let usage_ledger = usage::UsageLedger::load(usage_settings, store.clone())?;
+    let chat_routes = backend::ChatRoutes::from_config(&openai_config, ogpt_async_client.clone(), backend_settings, cassette)?;
+    let deps = handler::HandlerDeps {
+        media_backend: Arc::new(ogpt_async_client),
+        chat_routes,
+        knowledge_base,
+        moderator,
+        usage_ledger,
+        budgets,
+        store,
+    };
+    let handler = handler::Handler::new(deps, handler::HandlerSettings {
+        image: openai_config.image_settings(),
+        speech: openai_config.speech_settings(),
+        voice_message: voice_message_settings,
+        lru_cache_size: 350,
+        default_prompt: None,
+    });
 
     let mut client =
         SerenityClient::builder(discord_token, intents)
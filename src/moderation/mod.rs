@@ -0,0 +1,47 @@
+mod moderator;
+mod policy;
+
+use serenity::model::prelude::Message;
+
+pub use moderator::Moderator;
+pub use policy::{ModerationAction, Stage, Verdict};
+
+pub const REDACTED_RESPONSE: &str = "[This answer was redacted by the server's moderation policy]";
+
+// Longest excerpt of the moderated text quoted in the mod log, leaving room for the rest of the entry
+// within one Discord message.
+const MAX_LOG_EXCERPT: usize = 1000;
+
+// Mod-log entry for a moderation decision about `text`, which is either `msg` itself or the answer to it.
+pub fn log_entry(stage: Stage, verdict: &Verdict, msg: &Message, text: &str) -> String {
+    let subject = match stage {
+        Stage::Input => "Question",
+        Stage::Output => "Answer",
+    };
+    let categories = if verdict.categories.is_empty() {
+        String::from("none, the moderation request failed")
+    } else {
+        verdict.categories
+            .iter()
+            .map(|(category, score)| format!("{} ({:.2})", category, score))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let mut excerpt: String = text.chars().take(MAX_LOG_EXCERPT).collect();
+    if excerpt.len() < text.len() {
+        excerpt.push_str("...");
+    }
+
+    format!(
+        "**{} {}** for {} ({}) in <#{}>: {}\nCategories: {}\n> {}",
+        subject,
+        verdict.action,
+        msg.author.tag(),
+        msg.author.id.0,
+        msg.channel_id.0,
+        msg.link(),
+        categories,
+        excerpt.replace('\n', "\n> "),
+    )
+}
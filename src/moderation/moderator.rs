@@ -0,0 +1,41 @@
+use ogpt::{client::OGptAsyncClient, model::moderations};
+
+use crate::{ServerError, config::ModerationSettings};
+
+use super::policy::{ModerationPolicies, ModerationPolicy, Stage, Verdict};
+
+// Checks text against the moderation policy of the guild it was sent in.
+#[derive(Debug)]
+pub struct Moderator {
+    ogpt_async_client: OGptAsyncClient,
+    model: String,
+    policies: ModerationPolicies,
+}
+
+impl Moderator {
+    pub fn new(ogpt_async_client: OGptAsyncClient, settings: ModerationSettings) -> Result<Moderator, ServerError> {
+        let policies = match &settings.policy_file {
+            Some(path) => ModerationPolicies::load(path)?,
+            None => ModerationPolicies::default(),
+        };
+
+        Ok(Moderator {
+            ogpt_async_client,
+            model: settings.model,
+            policies,
+        })
+    }
+
+    // The policy that applies to `stage` in the guild, or None if that stage isn't moderated there.
+    pub fn policy(&self, guild_id: Option<u64>, stage: Stage) -> Option<&ModerationPolicy> {
+        self.policies.for_guild(guild_id).filter(|policy| policy.checks(stage))
+    }
+
+    pub async fn check(&self, policy: &ModerationPolicy, text: &str) -> Result<Option<Verdict>, ServerError> {
+        let request = moderations::ModerationsRequest::new(text.to_owned().into())
+            .model(self.model.to_owned());
+        let response = self.ogpt_async_client.moderations_async(&request).await?;
+
+        Ok(response.results.first().and_then(|result| policy.evaluate(result)))
+    }
+}
@@ -0,0 +1,211 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use ogpt::model::moderations::ModerationResult;
+use serde::Deserialize;
+
+use crate::ServerError;
+
+// Ordered by severity, the most severe action of all matching rules is taken.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationAction {
+    // Let the text through but report it to the mod-log channel.
+    Flag,
+    // Replace a response with a notice. Questions can't be answered in part, so they are blocked.
+    Redact,
+    Block,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationRule {
+    // Moderation API category, e.g. "harassment" or "self-harm/intent".
+    pub category: String,
+    // Score from 0 to 1 at which the rule applies.
+    pub threshold: f64,
+    pub action: ModerationAction,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationPolicy {
+    #[serde(default = "enabled")]
+    pub check_input: bool,
+    #[serde(default = "enabled")]
+    pub check_output: bool,
+    #[serde(default)]
+    pub rules: Vec<ModerationRule>,
+    // Action for results the API flags that no rule matches.
+    #[serde(default)]
+    pub flagged_action: Option<ModerationAction>,
+    // Channel every moderation decision is reported to.
+    #[serde(default)]
+    pub mod_log_channel: Option<u64>,
+    // Whether to block text that couldn't be checked because the moderation request failed.
+    #[serde(default)]
+    pub block_on_error: bool,
+}
+
+// The policy file. A guild's own policy replaces the default one entirely.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ModerationPolicies {
+    #[serde(default)]
+    pub default: Option<ModerationPolicy>,
+    #[serde(default)]
+    pub guilds: HashMap<u64, ModerationPolicy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub action: ModerationAction,
+    // The categories that caused the action and their scores.
+    pub categories: Vec<(String, f64)>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl ModerationPolicy {
+    pub fn checks(&self, stage: Stage) -> bool {
+        match stage {
+            Stage::Input => self.check_input,
+            Stage::Output => self.check_output,
+        }
+    }
+
+    // The action to take for `result`, or None if the text is fine.
+    pub fn evaluate(&self, result: &ModerationResult) -> Option<Verdict> {
+        let mut action = None;
+        let mut categories = vec![];
+        for rule in &self.rules {
+            let score = result.score(&rule.category);
+            if score >= rule.threshold {
+                action = action.max(Some(rule.action));
+                categories.push((rule.category.to_owned(), score));
+            }
+        }
+
+        if let (None, Some(flagged_action), true) = (action, self.flagged_action, result.flagged) {
+            action = Some(flagged_action);
+            categories = result
+                .flagged_categories()
+                .into_iter()
+                .map(|category| (category.to_owned(), result.score(category)))
+                .collect();
+        }
+
+        action.map(|action| Verdict { action, categories })
+    }
+}
+
+impl ModerationPolicies {
+    pub fn load(path: &Path) -> Result<ModerationPolicies, ServerError> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn for_guild(&self, guild_id: Option<u64>) -> Option<&ModerationPolicy> {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id))
+            .or(self.default.as_ref())
+    }
+}
+
+impl fmt::Display for ModerationAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModerationAction::Flag => write!(f, "flagged"),
+            ModerationAction::Redact => write!(f, "redacted"),
+            ModerationAction::Block => write!(f, "blocked"),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Input => write!(f, "input"),
+            Stage::Output => write!(f, "output"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn result(flagged: bool, scores: &[(&str, f64)]) -> ModerationResult {
+        ModerationResult {
+            flagged,
+            categories: scores.iter().map(|(category, score)| (category.to_string(), *score >= 0.5)).collect(),
+            category_scores: scores.iter().map(|(category, score)| (category.to_string(), *score)).collect(),
+        }
+    }
+
+    fn policy(value: serde_json::Value) -> ModerationPolicy {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn defaults_check_both_stages_without_rules() {
+        let policy = policy(json!({}));
+        assert!(policy.checks(Stage::Input));
+        assert!(policy.checks(Stage::Output));
+        assert!(!policy.block_on_error);
+        assert_eq!(policy.evaluate(&result(true, &[("harassment", 0.9)])), None);
+    }
+
+    #[test]
+    fn rules_apply_from_their_threshold() {
+        let policy = policy(json!({ "rules": [{ "category": "harassment", "threshold": 0.5, "action": "flag" }] }));
+        assert_eq!(policy.evaluate(&result(false, &[("harassment", 0.49)])), None);
+        assert_eq!(policy.evaluate(&result(false, &[("harassment", 0.5)])), Some(Verdict {
+            action: ModerationAction::Flag,
+            categories: vec![(String::from("harassment"), 0.5)],
+        }));
+    }
+
+    #[test]
+    fn the_most_severe_matching_rule_wins() {
+        let policy = policy(json!({ "rules": [
+            { "category": "violence", "threshold": 0.2, "action": "block" },
+            { "category": "harassment", "threshold": 0.2, "action": "redact" },
+            { "category": "sexual", "threshold": 0.2, "action": "flag" },
+        ] }));
+        let verdict = policy.evaluate(&result(true, &[("harassment", 0.3), ("violence", 0.4), ("sexual", 0.1)])).unwrap();
+        assert_eq!(verdict.action, ModerationAction::Block);
+        assert_eq!(verdict.categories, vec![(String::from("violence"), 0.4), (String::from("harassment"), 0.3)]);
+    }
+
+    #[test]
+    fn flagged_results_without_a_matching_rule_take_the_flagged_action() {
+        let policy = policy(json!({
+            "rules": [{ "category": "violence", "threshold": 0.5, "action": "block" }],
+            "flagged_action": "redact",
+        }));
+        let verdict = policy.evaluate(&result(true, &[("self-harm", 0.8), ("hate", 0.6), ("violence", 0.1)])).unwrap();
+        assert_eq!(verdict.action, ModerationAction::Redact);
+        assert_eq!(verdict.categories, vec![(String::from("hate"), 0.6), (String::from("self-harm"), 0.8)]);
+        assert_eq!(policy.evaluate(&result(false, &[("hate", 0.4)])), None);
+    }
+
+    #[test]
+    fn guild_policies_replace_the_default() {
+        let policies: ModerationPolicies = serde_json::from_value(json!({
+            "default": { "check_output": false },
+            "guilds": { "42": { "check_input": false } },
+        })).unwrap();
+        assert!(!policies.for_guild(Some(42)).unwrap().checks(Stage::Input));
+        assert!(policies.for_guild(Some(42)).unwrap().checks(Stage::Output));
+        assert!(!policies.for_guild(Some(7)).unwrap().checks(Stage::Output));
+        assert!(!policies.for_guild(None).unwrap().checks(Stage::Output));
+        assert_eq!(ModerationPolicies::default().for_guild(Some(42)), None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::ServerError;
+use crate::handler::MessageLite;
+use crate::usage::{unix_time, UsageRecord};
+
+use super::{MusicState, Store};
+
+#[derive(Debug, Default)]
+struct State {
+    #[allow(dead_code)]
+    guild_settings: HashMap<(u64, String), String>,
+    prompt: Option<String>,
+    // Each message with the unix time it was stored at.
+    messages: HashMap<u64, (MessageLite, u64)>,
+    usage_records: Vec<UsageRecord>,
+    music: Vec<MusicState>,
+}
+
+// A store that forgets everything when the process exits, for tests and running without a database.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    state: Mutex<State>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn guild_setting(&self, guild_id: u64, key: &str) -> Result<Option<String>, ServerError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.guild_settings.get(&(guild_id, key.to_owned())).cloned())
+    }
+
+    fn set_guild_setting(&self, guild_id: u64, key: &str, value: Option<&str>) -> Result<(), ServerError> {
+        let mut state = self.state.lock().unwrap();
+        match value {
+            Some(value) => state.guild_settings.insert((guild_id, key.to_owned()), value.to_owned()),
+            None => state.guild_settings.remove(&(guild_id, key.to_owned())),
+        };
+        Ok(())
+    }
+
+    fn prompt(&self) -> Result<Option<String>, ServerError> {
+        Ok(self.state.lock().unwrap().prompt.clone())
+    }
+
+    fn set_prompt(&self, prompt: &str) -> Result<(), ServerError> {
+        self.state.lock().unwrap().prompt = Some(prompt.to_owned());
+        Ok(())
+    }
+
+    fn message(&self, id: u64) -> Result<Option<MessageLite>, ServerError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.messages.get(&id).map(|(message, _)| message.clone()))
+    }
+
+    fn put_message(&self, message: &MessageLite) -> Result<(), ServerError> {
+        let mut state = self.state.lock().unwrap();
+        state.messages.insert(message.id, (message.clone(), unix_time()));
+        Ok(())
+    }
+
+    fn add_message(&self, message: &MessageLite) -> Result<(), ServerError> {
+        let mut state = self.state.lock().unwrap();
+        state.messages.entry(message.id).or_insert_with(|| (message.clone(), unix_time()));
+        Ok(())
+    }
+
+    fn prune_messages(&self, before: u64) -> Result<usize, ServerError> {
+        let mut state = self.state.lock().unwrap();
+        let count = state.messages.len();
+        state.messages.retain(|_, (_, stored_at)| *stored_at >= before);
+        Ok(count - state.messages.len())
+    }
+
+    fn usage_records(&self, since: u64) -> Result<Vec<UsageRecord>, ServerError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.usage_records.iter().filter(|record| record.timestamp >= since).cloned().collect())
+    }
+
+    fn append_usage(&self, record: &UsageRecord) -> Result<(), ServerError> {
+        self.state.lock().unwrap().usage_records.push(record.clone());
+        Ok(())
+    }
+
+    fn import_usage(&self, records: &[UsageRecord]) -> Result<(), ServerError> {
+        self.state.lock().unwrap().usage_records.extend_from_slice(records);
+        Ok(())
+    }
+
+    fn music_states(&self) -> Result<Vec<MusicState>, ServerError> {
+        Ok(self.state.lock().unwrap().music.clone())
+    }
+
+    fn push_music_track(&self, guild_id: u64, channel_id: u64, url: &str) -> Result<(), ServerError> {
+        let mut state = self.state.lock().unwrap();
+        match state.music.iter_mut().find(|music| music.guild_id == guild_id) {
+            Some(music) => {
+                music.channel_id = channel_id;
+                music.queue.push(url.to_owned());
+            },
+            None => state.music.push(MusicState { guild_id, channel_id, queue: vec![url.to_owned()] }),
+        }
+        Ok(())
+    }
+
+    fn remove_music_track(&self, guild_id: u64, url: &str) -> Result<(), ServerError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(music) = state.music.iter_mut().find(|music| music.guild_id == guild_id) {
+            if let Some(i) = music.queue.iter().position(|queued| queued == url) {
+                music.queue.remove(i);
+            }
+        }
+        state.music.retain(|music| !music.queue.is_empty());
+        Ok(())
+    }
+
+    fn clear_music(&self, guild_id: u64) -> Result<(), ServerError> {
+        self.state.lock().unwrap().music.retain(|music| music.guild_id != guild_id);
+        Ok(())
+    }
+}
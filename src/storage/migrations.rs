@@ -0,0 +1,114 @@
+use rusqlite::Connection;
+
+use crate::ServerError;
+
+// Schema changes in the order they were made. A database records how many it has applied in its
+// `user_version`, so only append to this list and never edit a migration that has been released.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE guild_settings (
+        guild_id INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (guild_id, key)
+    );
+
+    CREATE TABLE prompts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        prompt TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE messages (
+        id INTEGER PRIMARY KEY,
+        ref_msg_id INTEGER,
+        content TEXT NOT NULL,
+        author_name TEXT NOT NULL,
+        is_own INTEGER NOT NULL,
+        image_urls TEXT NOT NULL,
+        stored_at INTEGER NOT NULL
+    );
+    CREATE INDEX messages_stored_at ON messages (stored_at);
+
+    CREATE TABLE usage_records (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        guild_id INTEGER,
+        channel_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        model TEXT NOT NULL,
+        units INTEGER NOT NULL,
+        prompt_tokens INTEGER NOT NULL,
+        cached_tokens INTEGER NOT NULL,
+        completion_tokens INTEGER NOT NULL,
+        cost REAL NOT NULL
+    );
+
+    CREATE TABLE music_tracks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        guild_id INTEGER NOT NULL,
+        channel_id INTEGER NOT NULL,
+        url TEXT NOT NULL
+    );
+    CREATE INDEX music_tracks_guild_id ON music_tracks (guild_id);",
+    "CREATE INDEX usage_records_timestamp ON usage_records (timestamp);",
+];
+
+// Brings the schema up to date, applying each missing migration in its own transaction.
+pub fn migrate(conn: &mut Connection) -> Result<(), ServerError> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(ServerError::ConfigError(format!(
+            "Database schema version {} is newer than this build supports ({})", version, MIGRATIONS.len())));
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+        println!("Applied database migration {}", i + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrates_new_databases_to_the_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        // Running again finds nothing left to do.
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn applies_only_the_missing_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute_batch("PRAGMA user_version = 1").unwrap();
+        conn.execute("INSERT INTO prompts (prompt, created_at) VALUES ('Be brief.', 0)", []).unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        let prompt: String = conn.query_row("SELECT prompt FROM prompts", [], |row| row.get(0)).unwrap();
+        assert_eq!(prompt, "Be brief.");
+        let index: i64 = conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'usage_records_timestamp'", [], |row| row.get(0)).unwrap();
+        assert_eq!(index, 1);
+    }
+
+    #[test]
+    fn refuses_databases_from_newer_versions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1)).unwrap();
+        assert!(matches!(migrate(&mut conn), Err(ServerError::ConfigError(_))));
+    }
+}
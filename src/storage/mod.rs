@@ -0,0 +1,205 @@
+mod memory;
+mod migrations;
+mod sqlite;
+
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use crate::ServerError;
+use crate::handler::MessageLite;
+use crate::usage::{unix_time, UsageRecord};
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+// The voice channel the bot was playing in and the source urls of the current and queued tracks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MusicState {
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub queue: Vec<String>,
+}
+
+// Bot state that has to survive restarts. SqliteStore keeps it on disk, MemoryStore only for as long as
+// the process runs.
+// Calls can wait for the disk, so async code goes through `blocking`.
+pub trait Store: Send + Sync + Debug {
+    // Settings are still configured for all guilds at once, these are for the commands that will change
+    // them per guild.
+    #[allow(dead_code)]
+    fn guild_setting(&self, guild_id: u64, key: &str) -> Result<Option<String>, ServerError>;
+    // Removes the setting if `value` is None.
+    #[allow(dead_code)]
+    fn set_guild_setting(&self, guild_id: u64, key: &str, value: Option<&str>) -> Result<(), ServerError>;
+
+    // The system prompt last set with !gpt-prompt.
+    fn prompt(&self) -> Result<Option<String>, ServerError>;
+    fn set_prompt(&self, prompt: &str) -> Result<(), ServerError>;
+
+    fn message(&self, id: u64) -> Result<Option<MessageLite>, ServerError>;
+    // Replaces any message stored under the same id.
+    fn put_message(&self, message: &MessageLite) -> Result<(), ServerError>;
+    // Keeps the message already stored under the same id, if there is one.
+    fn add_message(&self, message: &MessageLite) -> Result<(), ServerError>;
+    // Removes messages stored before the unix time `before`, returning how many were removed.
+    fn prune_messages(&self, before: u64) -> Result<usize, ServerError>;
+
+    // Records made at or after the unix time `since`, oldest first.
+    fn usage_records(&self, since: u64) -> Result<Vec<UsageRecord>, ServerError>;
+    fn append_usage(&self, record: &UsageRecord) -> Result<(), ServerError>;
+    // Appends all of `records` or, if one fails, none of them.
+    fn import_usage(&self, records: &[UsageRecord]) -> Result<(), ServerError>;
+
+    // Guilds with tracks left to play.
+    fn music_states(&self) -> Result<Vec<MusicState>, ServerError>;
+    // Adds a track to the end of the guild's queue, played in `channel_id`.
+    fn push_music_track(&self, guild_id: u64, channel_id: u64, url: &str) -> Result<(), ServerError>;
+    // Removes the first track in the guild's queue with this url, once it has finished or was skipped.
+    fn remove_music_track(&self, guild_id: u64, url: &str) -> Result<(), ServerError>;
+    fn clear_music(&self, guild_id: u64) -> Result<(), ServerError>;
+}
+
+// Runs `call` on the blocking thread pool, so waiting for the disk doesn't hold up the async runtime.
+pub async fn blocking<T, F>(store: &Arc<dyn Store>, call: F) -> Result<T, ServerError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Store) -> Result<T, ServerError> + Send + 'static,
+{
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || call(store.as_ref()))
+        .await
+        .map_err(std::io::Error::from)?
+}
+
+// Drops stored messages older than `retention` now and then every `interval`, for as long as the bot runs.
+pub async fn prune_messages(store: Arc<dyn Store>, retention: Duration, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let before = unix_time().saturating_sub(retention.as_secs());
+        match blocking(&store, move |store| store.prune_messages(before)).await {
+            Ok(pruned) => println!("Pruned {} stored messages older than {} days", pruned, retention.as_secs() / 86_400),
+            Err(err) => eprintln!("Error pruning stored messages - {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::usage::UsageKind;
+
+    use super::*;
+
+    fn message(id: u64, content: &str) -> MessageLite {
+        MessageLite {
+            id,
+            ref_msg_id: Some(id - 1),
+            content: content.to_owned(),
+            author_name: String::from("user"),
+            is_own: false,
+            image_urls: vec![String::from("https://cdn.discordapp.com/a.png")],
+        }
+    }
+
+    fn usage_record(timestamp: u64, guild_id: Option<u64>) -> UsageRecord {
+        UsageRecord {
+            timestamp,
+            guild_id,
+            channel_id: 2,
+            user_id: u64::MAX,
+            kind: UsageKind::Transcription,
+            model: String::from("gpt-4o-mini"),
+            units: 90,
+            prompt_tokens: 10,
+            cached_tokens: 4,
+            completion_tokens: 5,
+            cost: 0.5,
+        }
+    }
+
+    // Both stores have to behave the same, MemoryStore stands in for SqliteStore in the other tests.
+    fn stores() -> Vec<Arc<dyn Store>> {
+        vec![
+            Arc::new(MemoryStore::new()),
+            Arc::new(SqliteStore::new(Connection::open_in_memory().unwrap()).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn keeps_settings_per_guild() {
+        for store in stores() {
+            store.set_guild_setting(1, "backend", Some("anthropic")).unwrap();
+            store.set_guild_setting(1, "backend", Some("ollama")).unwrap();
+            store.set_guild_setting(u64::MAX, "backend", Some("openai")).unwrap();
+            assert_eq!(store.guild_setting(1, "backend").unwrap().as_deref(), Some("ollama"), "{:?}", store);
+            assert_eq!(store.guild_setting(u64::MAX, "backend").unwrap().as_deref(), Some("openai"), "{:?}", store);
+            assert_eq!(store.guild_setting(1, "model").unwrap(), None, "{:?}", store);
+
+            store.set_guild_setting(1, "backend", None).unwrap();
+            assert_eq!(store.guild_setting(1, "backend").unwrap(), None, "{:?}", store);
+            assert!(store.guild_setting(u64::MAX, "backend").unwrap().is_some(), "{:?}", store);
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_prompt() {
+        for store in stores() {
+            assert_eq!(store.prompt().unwrap(), None);
+            store.set_prompt("Be brief.").unwrap();
+            store.set_prompt("Be thorough.").unwrap();
+            assert_eq!(store.prompt().unwrap().as_deref(), Some("Be thorough."), "{:?}", store);
+        }
+    }
+
+    #[test]
+    fn adding_a_message_keeps_the_stored_one() {
+        for store in stores() {
+            store.add_message(&message(2, "...")).unwrap();
+            store.put_message(&message(2, "The answer")).unwrap();
+            store.add_message(&message(2, "...")).unwrap();
+            let stored = store.message(2).unwrap().unwrap();
+            assert_eq!((stored.content.as_str(), stored.ref_msg_id), ("The answer", Some(1)), "{:?}", store);
+            assert_eq!(stored.image_urls, message(2, "").image_urls);
+            assert!(store.message(3).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn prunes_messages_stored_before_a_time() {
+        for store in stores() {
+            store.put_message(&message(2, "Hi")).unwrap();
+            assert_eq!(store.prune_messages(unix_time().saturating_sub(60)).unwrap(), 0);
+            assert_eq!(store.prune_messages(unix_time() + 60).unwrap(), 1, "{:?}", store);
+            assert!(store.message(2).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn returns_usage_records_since_a_time_in_order() {
+        for store in stores() {
+            store.append_usage(&usage_record(100, Some(1))).unwrap();
+            store.import_usage(&[usage_record(50, None), usage_record(200, Some(1))]).unwrap();
+            assert_eq!(store.usage_records(100).unwrap(), vec![usage_record(100, Some(1)), usage_record(200, Some(1))], "{:?}", store);
+            assert_eq!(store.usage_records(0).unwrap().len(), 3);
+        }
+    }
+
+    #[test]
+    fn keeps_music_queues_per_guild() {
+        for store in stores() {
+            store.push_music_track(1, 10, "https://a").unwrap();
+            store.push_music_track(1, 11, "https://b").unwrap();
+            store.push_music_track(1, 11, "https://a").unwrap();
+            store.push_music_track(2, 20, "https://c").unwrap();
+            store.remove_music_track(1, "https://a").unwrap();
+            store.clear_music(2).unwrap();
+
+            let states = store.music_states().unwrap();
+            assert_eq!(states, vec![MusicState { guild_id: 1, channel_id: 11, queue: vec![String::from("https://b"), String::from("https://a")] }], "{:?}", store);
+            store.remove_music_track(1, "https://b").unwrap();
+            store.remove_music_track(1, "https://a").unwrap();
+            assert!(store.music_states().unwrap().is_empty());
+        }
+    }
+}
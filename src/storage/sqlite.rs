@@ -0,0 +1,249 @@
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+
+use crate::ServerError;
+use crate::handler::MessageLite;
+use crate::usage::{unix_time, UsageKind, UsageRecord};
+
+use super::{migrations, MusicState, Store};
+
+// Discord ids fit in 63 bits, but are stored as SQLite's signed integers bit for bit so none can overflow.
+fn to_sql_id(id: u64) -> i64 {
+    id as i64
+}
+
+fn from_sql_id(id: i64) -> u64 {
+    id as u64
+}
+
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<SqliteStore, ServerError> {
+        SqliteStore::new(Connection::open(path)?)
+    }
+
+    pub(super) fn new(mut conn: Connection) -> Result<SqliteStore, ServerError> {
+        // The write-ahead log lets the bot read while a write is in progress.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrations::migrate(&mut conn)?;
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<(MessageLite, String)> {
+    Ok((MessageLite {
+        id: from_sql_id(row.get(0)?),
+        ref_msg_id: row.get::<_, Option<i64>>(1)?.map(from_sql_id),
+        content: row.get(2)?,
+        author_name: row.get(3)?,
+        is_own: row.get(4)?,
+        image_urls: vec![],
+    }, row.get(5)?))
+}
+
+fn usage_record_from_row(row: &Row) -> rusqlite::Result<UsageRecord> {
+    let kind: String = row.get(4)?;
+    let kind = UsageKind::parse(&kind).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(4, Type::Text, format!("Unknown usage kind {}", kind).into())
+    })?;
+    Ok(UsageRecord {
+        timestamp: row.get::<_, i64>(0)? as u64,
+        guild_id: row.get::<_, Option<i64>>(1)?.map(from_sql_id),
+        channel_id: from_sql_id(row.get(2)?),
+        user_id: from_sql_id(row.get(3)?),
+        kind,
+        model: row.get(5)?,
+        units: row.get::<_, i64>(6)? as u64,
+        prompt_tokens: row.get::<_, i64>(7)? as u64,
+        cached_tokens: row.get::<_, i64>(8)? as u64,
+        completion_tokens: row.get::<_, i64>(9)? as u64,
+        cost: row.get(10)?,
+    })
+}
+
+// `insert` is the INSERT statement with its conflict clause, which decides what happens to a message
+// already stored under the same id.
+fn insert_message(conn: &Connection, insert: &str, message: &MessageLite) -> Result<(), ServerError> {
+    let image_urls = serde_json::to_string(&message.image_urls)?;
+    conn.execute(
+        &format!("{} INTO messages (id, ref_msg_id, content, author_name, is_own, image_urls, stored_at)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", insert),
+        params![
+            to_sql_id(message.id),
+            message.ref_msg_id.map(to_sql_id),
+            message.content,
+            message.author_name,
+            message.is_own,
+            image_urls,
+            unix_time() as i64,
+        ])?;
+    Ok(())
+}
+
+fn insert_usage_record(conn: &Connection, record: &UsageRecord) -> Result<(), ServerError> {
+    conn.execute(
+        "INSERT INTO usage_records (timestamp, guild_id, channel_id, user_id, kind, model, units, prompt_tokens, cached_tokens, completion_tokens, cost)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            record.timestamp as i64,
+            record.guild_id.map(to_sql_id),
+            to_sql_id(record.channel_id),
+            to_sql_id(record.user_id),
+            record.kind.as_str(),
+            record.model,
+            record.units as i64,
+            record.prompt_tokens as i64,
+            record.cached_tokens as i64,
+            record.completion_tokens as i64,
+            record.cost,
+        ])?;
+    Ok(())
+}
+
+impl Store for SqliteStore {
+    fn guild_setting(&self, guild_id: u64, key: &str) -> Result<Option<String>, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT value FROM guild_settings WHERE guild_id = ?1 AND key = ?2",
+                params![to_sql_id(guild_id), key],
+                |row| row.get(0))
+            .optional()?)
+    }
+
+    fn set_guild_setting(&self, guild_id: u64, key: &str, value: Option<&str>) -> Result<(), ServerError> {
+        let conn = self.conn.lock().unwrap();
+        match value {
+            Some(value) => conn.execute(
+                "INSERT INTO guild_settings (guild_id, key, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id, key) DO UPDATE SET value = excluded.value",
+                params![to_sql_id(guild_id), key, value])?,
+            None => conn.execute(
+                "DELETE FROM guild_settings WHERE guild_id = ?1 AND key = ?2",
+                params![to_sql_id(guild_id), key])?,
+        };
+        Ok(())
+    }
+
+    fn prompt(&self) -> Result<Option<String>, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT prompt FROM prompts ORDER BY id DESC LIMIT 1", [], |row| row.get(0))
+            .optional()?)
+    }
+
+    // Earlier prompts are kept as a history of what the bot was told.
+    fn set_prompt(&self, prompt: &str) -> Result<(), ServerError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO prompts (prompt, created_at) VALUES (?1, ?2)",
+            params![prompt, unix_time() as i64])?;
+        Ok(())
+    }
+
+    fn message(&self, id: u64) -> Result<Option<MessageLite>, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(
+                "SELECT id, ref_msg_id, content, author_name, is_own, image_urls FROM messages WHERE id = ?1",
+                params![to_sql_id(id)],
+                message_from_row)
+            .optional()?;
+
+        match row {
+            Some((mut message, image_urls)) => {
+                message.image_urls = serde_json::from_str(&image_urls)?;
+                Ok(Some(message))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn put_message(&self, message: &MessageLite) -> Result<(), ServerError> {
+        insert_message(&self.conn.lock().unwrap(), "INSERT OR REPLACE", message)
+    }
+
+    fn add_message(&self, message: &MessageLite) -> Result<(), ServerError> {
+        insert_message(&self.conn.lock().unwrap(), "INSERT OR IGNORE", message)
+    }
+
+    fn prune_messages(&self, before: u64) -> Result<usize, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM messages WHERE stored_at < ?1", params![before as i64])?)
+    }
+
+    fn usage_records(&self, since: u64) -> Result<Vec<UsageRecord>, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT timestamp, guild_id, channel_id, user_id, kind, model, units, prompt_tokens, cached_tokens, completion_tokens, cost
+             FROM usage_records WHERE timestamp >= ?1 ORDER BY id")?;
+        let records = statement
+            .query_map(params![since as i64], usage_record_from_row)?
+            .collect::<rusqlite::Result<Vec<UsageRecord>>>()?;
+        Ok(records)
+    }
+
+    fn append_usage(&self, record: &UsageRecord) -> Result<(), ServerError> {
+        insert_usage_record(&self.conn.lock().unwrap(), record)
+    }
+
+    fn import_usage(&self, records: &[UsageRecord]) -> Result<(), ServerError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for record in records {
+            insert_usage_record(&tx, record)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn music_states(&self) -> Result<Vec<MusicState>, ServerError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT guild_id, channel_id, url FROM music_tracks ORDER BY guild_id, id")?;
+        let tracks = statement
+            .query_map([], |row| Ok((from_sql_id(row.get(0)?), from_sql_id(row.get(1)?), row.get::<_, String>(2)?)))?
+            .collect::<rusqlite::Result<Vec<(u64, u64, String)>>>()?;
+
+        let mut states: Vec<MusicState> = vec![];
+        for (guild_id, channel_id, url) in tracks {
+            match states.last_mut() {
+                Some(state) if state.guild_id == guild_id => {
+                    // The bot plays in the channel of the latest !play.
+                    state.channel_id = channel_id;
+                    state.queue.push(url);
+                },
+                _ => states.push(MusicState { guild_id, channel_id, queue: vec![url] }),
+            }
+        }
+        Ok(states)
+    }
+
+    fn push_music_track(&self, guild_id: u64, channel_id: u64, url: &str) -> Result<(), ServerError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO music_tracks (guild_id, channel_id, url) VALUES (?1, ?2, ?3)",
+            params![to_sql_id(guild_id), to_sql_id(channel_id), url])?;
+        Ok(())
+    }
+
+    fn remove_music_track(&self, guild_id: u64, url: &str) -> Result<(), ServerError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM music_tracks WHERE id = (
+                SELECT id FROM music_tracks WHERE guild_id = ?1 AND url = ?2 ORDER BY id LIMIT 1
+            )",
+            params![to_sql_id(guild_id), url])?;
+        Ok(())
+    }
+
+    fn clear_music(&self, guild_id: u64) -> Result<(), ServerError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM music_tracks WHERE guild_id = ?1", params![to_sql_id(guild_id)])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// Offline stand-ins for Discord and the model APIs, shared by the tests of the handler and commands.
+
+use std::sync::Arc;
+
+use ogpt::{backend::{ChatBackend, MediaBackend}, client::OGptAsyncClient, testing::{FakeBackend, MockResponse, MockServer}};
+use serenity::{cache::Cache, client::bridge::gateway::ShardMessenger, http::{Http, HttpBuilder}, model::channel::Message, prelude::{Context, RwLock, TypeMap}};
+use serde_json::{json, Value};
+
+use crate::backend::{ChatRoute, ChatRoutes};
+use crate::config::{ChatSettings, ImageSettings, KnowledgeSettings, ModerationSettings, SpeechSettings, UsageSettings, VoiceMessageSettings};
+use crate::handler::{Handler, HandlerDeps, HandlerSettings};
+use crate::knowledge::KnowledgeBase;
+use crate::moderation::Moderator;
+use crate::storage::{MemoryStore, Store};
+use crate::usage::{Budgets, UsageLedger};
+
+pub const GUILD_ID: u64 = 1000;
+pub const CHANNEL_ID: u64 = 2000;
+pub const USER_ID: u64 = 3000;
+pub const BOT_ID: u64 = 4000;
+
+fn context_with(http: Http) -> Context {
+    let (tx, _rx) = serenity::futures::channel::mpsc::unbounded();
+    Context {
+        data: Arc::new(RwLock::new(TypeMap::new())),
+        shard: ShardMessenger::new(tx),
+        shard_id: 0,
+        http: Arc::new(http),
+        cache: Arc::new(Cache::new()),
+    }
+}
+
+// A context whose HTTP client and shard go nowhere, enough for code that only reads the cache.
+pub fn context() -> Context {
+    context_with(Http::new("test-token"))
+}
+
+// A context whose Discord API requests go to `server`, which has to have a response queued for each.
+pub fn discord_context(server: &MockServer) -> Context {
+    let http = HttpBuilder::new("test-token")
+        .proxy(server.url())
+        .unwrap()
+        .ratelimiter_disabled(true)
+        .build();
+    context_with(http)
+}
+
+// A guild message from a user, as the gateway would deliver it.
+pub fn message(id: u64, content: &str) -> Message {
+    serde_json::from_value(message_json(id, USER_ID, content)).unwrap()
+}
+
+// A guild message from a member with `roles`.
+pub fn member_message(id: u64, content: &str, roles: &[u64]) -> Message {
+    let mut json = message_json(id, USER_ID, content);
+    json["member"] = json!({ "roles": roles.iter().map(|role| role.to_string()).collect::<Vec<String>>() });
+    serde_json::from_value(json).unwrap()
+}
+
+// Discord's answer to the bot sending or editing message `id`.
+pub fn bot_message(id: u64, content: &str) -> MockResponse {
+    MockResponse::json(200, message_json(id, BOT_ID, content))
+}
+
+fn message_json(id: u64, author_id: u64, content: &str) -> Value {
+    json!({
+        "id": id.to_string(),
+        "channel_id": CHANNEL_ID.to_string(),
+        "guild_id": GUILD_ID.to_string(),
+        "author": { "id": author_id.to_string(), "username": "user", "discriminator": "0001", "avatar": null },
+        "content": content,
+        "timestamp": "2024-01-01T00:00:00.000Z",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    })
+}
+
+// A client for the parts of the handler that still talk to OpenAI directly. Tests must not reach them.
+fn unreachable_client() -> OGptAsyncClient {
+    OGptAsyncClient::builder(String::from("test-key"))
+        .base_url(String::from("http://127.0.0.1:9/v1"))
+        .build_async()
+        .unwrap()
+}
+
+pub fn chat_settings() -> ChatSettings {
+    ChatSettings::for_model(String::from("gpt-4o-mini"))
+}
+
+pub fn handler(backend: Arc<FakeBackend>, settings: ChatSettings) -> Handler {
+    handler_with(backend.clone(), backend, settings, Arc::new(MemoryStore::new()), Budgets::new(&UsageSettings { price_file: None, budget_file: None }).unwrap())
+}
+
+pub fn handler_with(backend: Arc<dyn ChatBackend>, media_backend: Arc<dyn MediaBackend>, settings: ChatSettings, store: Arc<dyn Store>, budgets: Budgets) -> Handler {
+    let client = unreachable_client();
+    let usage_settings = UsageSettings { price_file: None, budget_file: None };
+    let deps = HandlerDeps {
+        media_backend,
+        chat_routes: ChatRoutes::new(ChatRoute { backend, settings }),
+        knowledge_base: KnowledgeBase::new(client.clone(), KnowledgeSettings {
+            dir: std::env::temp_dir().join("gpt-discord-bot-test-knowledge"),
+            embedding_model: String::from("text-embedding-3-small"),
+            top_k: 4,
+        }),
+        moderator: Moderator::new(client, ModerationSettings { policy_file: None, model: String::from("omni-moderation-latest") }).unwrap(),
+        usage_ledger: UsageLedger::load(usage_settings, store.clone()).unwrap(),
+        budgets,
+        store,
+    };
+    Handler::new(deps, HandlerSettings {
+        image: ImageSettings { model: String::from("dall-e-3"), size: String::from("1024x1024"), quality: None },
+        speech: SpeechSettings { model: String::from("tts-1"), voice: String::from("alloy") },
+        voice_message: VoiceMessageSettings { transcription_model: String::from("whisper-1"), channels: vec![], answer: false },
+        lru_cache_size: 16,
+        default_prompt: None,
+    })
+}
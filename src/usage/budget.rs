@@ -0,0 +1,234 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+use serenity::model::channel::Message;
+
+use crate::ServerError;
+use crate::config::UsageSettings;
+
+use super::ledger::{UsageLedger, UsageRecord};
+use super::period::unix_time;
+
+// Fractions of the monthly cap at which the admin channel is told how much has been spent.
+const WARNING_THRESHOLDS: [f64; 2] = [0.8, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    GuildMonthly,
+    UserDaily,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct BudgetPolicy {
+    // Estimated spend in US dollars per calendar month (UTC), shared by everyone in the guild.
+    #[serde(default)]
+    pub monthly_cap: Option<f64>,
+    // Prompt and completion tokens per user per day (UTC).
+    #[serde(default)]
+    pub user_daily_tokens: Option<u64>,
+    // Members with any of these roles have no daily quota and may go on past the monthly cap. What they
+    // spend still counts towards the cap and its warnings.
+    #[serde(default)]
+    pub exempt_roles: Vec<u64>,
+    // Channel told when the guild has spent 80% and all of its monthly cap.
+    #[serde(default)]
+    pub admin_channel: Option<u64>,
+}
+
+// The budget file. A guild's own policy replaces the default one entirely.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct BudgetPolicies {
+    #[serde(default)]
+    pub default: Option<BudgetPolicy>,
+    #[serde(default)]
+    pub guilds: HashMap<u64, BudgetPolicy>,
+}
+
+impl BudgetPolicies {
+    pub fn load(path: &Path) -> Result<BudgetPolicies, ServerError> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn for_guild(&self, guild_id: Option<u64>) -> Option<&BudgetPolicy> {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id))
+            .or(self.default.as_ref())
+    }
+}
+
+// Limits what each guild and user may spend, based on the usage recorded so far.
+#[derive(Debug)]
+pub struct Budgets {
+    policies: BudgetPolicies,
+}
+
+impl Budgets {
+    pub fn new(settings: &UsageSettings) -> Result<Budgets, ServerError> {
+        let policies = match &settings.budget_file {
+            Some(path) => BudgetPolicies::load(path)?,
+            None => BudgetPolicies::default(),
+        };
+        Ok(Budgets { policies })
+    }
+
+    // Fails if `msg` may not start another request. Direct messages have no guild to cap, so only the
+    // default daily quota applies to them, counting the user's tokens everywhere. Images, speech and
+    // transcriptions have no tokens, so they are only limited by the cap, though a user over the quota
+    // can't start them either.
+    pub fn check(&self, ledger: &UsageLedger, msg: &Message) -> Result<(), ServerError> {
+        let guild_id = msg.guild_id.map(|id| id.0);
+        let policy = match self.policies.for_guild(guild_id) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let exempt = msg.member
+            .as_ref()
+            .is_some_and(|member| member.roles.iter().any(|role| policy.exempt_roles.contains(&role.0)));
+        if exempt {
+            return Ok(());
+        }
+        let now = unix_time();
+
+        if let (Some(guild_id), Some(cap)) = (guild_id, policy.monthly_cap) {
+            if ledger.guild_month_totals(guild_id, now).cost >= cap {
+                return Err(ServerError::BudgetExceeded(Budget::GuildMonthly));
+            }
+        }
+
+        if let Some(quota) = policy.user_daily_tokens {
+            if ledger.user_day_totals(guild_id, msg.author.id.0, now).total_tokens() >= quota {
+                return Err(ServerError::BudgetExceeded(Budget::UserDaily));
+            }
+        }
+        Ok(())
+    }
+
+    // The admin channel and message to send if `record` took its guild past a warning threshold.
+    pub fn warning(&self, ledger: &UsageLedger, record: &UsageRecord) -> Option<(u64, String)> {
+        let guild_id = record.guild_id?;
+        let policy = self.policies.for_guild(Some(guild_id))?;
+        let (cap, channel_id) = (policy.monthly_cap?, policy.admin_channel?);
+
+        let spent = ledger.guild_month_totals(guild_id, record.timestamp).cost;
+        let threshold = WARNING_THRESHOLDS
+            .iter()
+            .rev()
+            .find(|threshold| spent - record.cost < cap * *threshold && spent >= cap * *threshold)?;
+
+        let warning = if *threshold >= 1.0 {
+            format!("**Monthly budget reached**: this server has spent an estimated ${:.2} of its ${:.2} cap, questions are refused until next month", spent, cap)
+        } else {
+            format!("**Monthly budget warning**: this server has spent an estimated ${:.2} of its ${:.2} cap ({:.0}%)", spent, cap, spent / cap * 100.0)
+        };
+        Some((channel_id, warning))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::MemoryStore;
+    use crate::testing::{self, CHANNEL_ID, GUILD_ID, USER_ID};
+    use crate::usage::{Period, UsageKind};
+
+    use super::*;
+
+    const ADMIN_CHANNEL: u64 = 5000;
+    const EXEMPT_ROLE: u64 = 6000;
+
+    fn budgets() -> Budgets {
+        let policy = BudgetPolicy {
+            monthly_cap: Some(10.0),
+            user_daily_tokens: Some(1000),
+            exempt_roles: vec![EXEMPT_ROLE],
+            admin_channel: Some(ADMIN_CHANNEL),
+        };
+        Budgets { policies: BudgetPolicies { default: None, guilds: HashMap::from([(GUILD_ID, policy)]) } }
+    }
+
+    fn ledger() -> UsageLedger {
+        UsageLedger::load(UsageSettings { price_file: None, budget_file: None }, Arc::new(MemoryStore::new())).unwrap()
+    }
+
+    fn record(timestamp: u64, user_id: u64, tokens: u64, cost: f64) -> UsageRecord {
+        UsageRecord {
+            timestamp,
+            guild_id: Some(GUILD_ID),
+            channel_id: CHANNEL_ID,
+            user_id,
+            kind: UsageKind::Chat,
+            model: String::from("gpt-4o-mini"),
+            units: 0,
+            prompt_tokens: tokens,
+            cached_tokens: 0,
+            completion_tokens: 0,
+            cost,
+        }
+    }
+
+    #[test]
+    fn refuses_guilds_over_their_monthly_cap() {
+        let (budgets, ledger) = (budgets(), ledger());
+        let msg = testing::message(1, "!gpt Hi");
+        ledger.insert(record(unix_time(), USER_ID + 1, 0, 9.99));
+        budgets.check(&ledger, &msg).unwrap();
+
+        ledger.insert(record(unix_time(), USER_ID + 1, 0, 0.01));
+        assert!(matches!(budgets.check(&ledger, &msg), Err(ServerError::BudgetExceeded(Budget::GuildMonthly))));
+    }
+
+    #[test]
+    fn refuses_users_over_their_daily_quota() {
+        let (budgets, ledger) = (budgets(), ledger());
+        ledger.insert(record(unix_time(), USER_ID, 999, 0.0));
+        budgets.check(&ledger, &testing::message(1, "!gpt Hi")).unwrap();
+
+        ledger.insert(record(unix_time(), USER_ID, 1, 0.0));
+        assert!(matches!(budgets.check(&ledger, &testing::message(2, "!gpt Hi")), Err(ServerError::BudgetExceeded(Budget::UserDaily))));
+    }
+
+    #[test]
+    fn exempt_roles_skip_the_quota_and_the_cap() {
+        let (budgets, ledger) = (budgets(), ledger());
+        ledger.insert(record(unix_time(), USER_ID, 5000, 20.0));
+        assert!(budgets.check(&ledger, &testing::member_message(1, "!gpt Hi", &[EXEMPT_ROLE + 1])).is_err());
+        budgets.check(&ledger, &testing::member_message(2, "!gpt Hi", &[EXEMPT_ROLE + 1, EXEMPT_ROLE])).unwrap();
+    }
+
+    #[test]
+    fn budgets_start_over_with_each_period() {
+        let (budgets, ledger) = (budgets(), ledger());
+        let now = unix_time();
+        let last_month = Period::Month.start(now) - 1;
+        ledger.insert(record(last_month, USER_ID, 5000, 20.0));
+        let yesterday = Period::Day.start(now) - 1;
+        ledger.insert(record(yesterday, USER_ID, 5000, 0.0));
+
+        budgets.check(&ledger, &testing::message(1, "!gpt Hi")).unwrap();
+        ledger.insert(record(now, USER_ID, 600, 1.0));
+        budgets.check(&ledger, &testing::message(2, "!gpt Hi")).unwrap();
+        assert_eq!(ledger.user_day_totals(Some(GUILD_ID), USER_ID, now).total_tokens(), 600);
+        assert_eq!(ledger.guild_month_totals(GUILD_ID, now).cost, 1.0);
+    }
+
+    #[test]
+    fn warns_the_admin_channel_at_80_percent_and_at_the_cap() {
+        let (budgets, ledger) = (budgets(), ledger());
+        let now = unix_time();
+        let mut warnings = vec![];
+        for cost in [5.0, 2.0, 1.5, 1.0, 1.0] {
+            let record = record(now, USER_ID, 0, cost);
+            ledger.insert(record.clone());
+            warnings.push(budgets.warning(&ledger, &record));
+        }
+
+        assert_eq!(warnings[..2], [None, None]);
+        let (channel_id, warning) = warnings[2].clone().unwrap();
+        assert_eq!(channel_id, ADMIN_CHANNEL);
+        assert!(warning.starts_with("**Monthly budget warning**"), "{}", warning);
+        assert_eq!(warnings[3], None);
+        assert!(warnings[4].as_ref().unwrap().1.starts_with("**Monthly budget reached**"));
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::ServerError;
+use crate::storage::Store;
+
+use super::ledger::UsageRecord;
+
+// Moves the records of a JSON lines usage log, as written before usage was kept in the store, into the
+// store. The log is renamed afterwards so it isn't imported again. Returns the number of records imported.
+pub fn import_usage_log(path: &Path, store: &dyn Store) -> Result<usize, ServerError> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    // A line cut short by a crash only loses that record.
+    let records: Vec<UsageRecord> = data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(err) => {
+                eprintln!("Skipping invalid usage record in {} - {}", path.display(), err);
+                None
+            }
+        })
+        .collect();
+
+    store.import_usage(&records)?;
+    std::fs::rename(path, imported_path(path))?;
+    Ok(records.len())
+}
+
+fn imported_path(path: &Path) -> PathBuf {
+    let mut imported = path.as_os_str().to_owned();
+    imported.push(".imported");
+    PathBuf::from(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStore;
+
+    use super::*;
+
+    #[test]
+    fn imports_a_usage_log_once() {
+        let path = std::env::temp_dir().join(format!("gpt-discord-bot-test-usage-{}.jsonl", std::process::id()));
+        std::fs::write(&path, concat!(
+            r#"{"timestamp":1714521600,"guild_id":1,"channel_id":2,"user_id":3,"model":"gpt-4o-mini","prompt_tokens":10,"completion_tokens":5,"cost":0.25}"#, "\n",
+            r#"{"timestamp":1714521601,"guild_id":null,"channel_id":2,"user_id":3,"model":"gpt-4o","prompt_tokens":20,"cached_tokens":8,"#, "\n",
+            "\n",
+            r#"{"timestamp":1714521602,"guild_id":null,"channel_id":2,"user_id":3,"model":"gpt-4o","prompt_tokens":20,"cached_tokens":8,"completion_tokens":1,"cost":0.5}"#, "\n",
+        )).unwrap();
+        let store = MemoryStore::new();
+
+        assert_eq!(import_usage_log(&path, &store).unwrap(), 2);
+        let records = store.usage_records(0).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].guild_id, records[0].cached_tokens, records[0].cost), (Some(1), 0, 0.25));
+        assert_eq!((records[1].guild_id, records[1].cached_tokens, records[1].completion_tokens), (None, 8, 1));
+
+        assert!(!path.exists());
+        assert_eq!(import_usage_log(&path, &store).unwrap(), 0);
+        assert_eq!(store.usage_records(0).unwrap().len(), 2);
+        std::fs::remove_file(imported_path(&path)).unwrap();
+    }
+}
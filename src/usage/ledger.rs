@@ -0,0 +1,548 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+
+use ogpt::{model::chat_completions::Usage, pricing::{ModelPrice, PriceTable}};
+use serde::Deserialize;
+use serenity::model::channel::Message;
+
+use crate::ServerError;
+use crate::config::UsageSettings;
+use crate::storage::Store;
+
+use super::period::{format_time, unix_time, Period};
+
+const CSV_HEADER: &str = "time,guild_id,channel_id,user_id,kind,model,units,prompt_tokens,cached_tokens,completion_tokens,cost";
+
+// Who a request is billed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attribution {
+    pub guild_id: Option<u64>,
+    pub channel_id: u64,
+    pub user_id: u64,
+}
+
+impl Attribution {
+    pub fn from_msg(msg: &Message) -> Attribution {
+        Attribution {
+            guild_id: msg.guild_id.map(|id| id.0),
+            channel_id: msg.channel_id.0,
+            user_id: msg.author.id.0,
+        }
+    }
+}
+
+// What a request was for. Chat completions and embeddings are priced by their tokens, images, speech and
+// transcriptions by their units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageKind {
+    // The usage logs of earlier versions only have chat completions.
+    #[default]
+    Chat,
+    Embedding,
+    Image,
+    Speech,
+    Transcription,
+}
+
+impl UsageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageKind::Chat => "chat",
+            UsageKind::Embedding => "embedding",
+            UsageKind::Image => "image",
+            UsageKind::Speech => "speech",
+            UsageKind::Transcription => "transcription",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<UsageKind> {
+        [UsageKind::Chat, UsageKind::Embedding, UsageKind::Image, UsageKind::Speech, UsageKind::Transcription]
+            .into_iter()
+            .find(|usage_kind| usage_kind.as_str() == kind)
+    }
+
+    pub fn is_media(&self) -> bool {
+        matches!(self, UsageKind::Image | UsageKind::Speech | UsageKind::Transcription)
+    }
+}
+
+// Deserialized from the usage logs of earlier versions when they are imported.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: u64,
+    pub guild_id: Option<u64>,
+    pub channel_id: u64,
+    pub user_id: u64,
+    #[serde(default)]
+    pub kind: UsageKind,
+    pub model: String,
+    // Images generated, characters spoken or seconds transcribed by media requests, 0 for the others.
+    #[serde(default)]
+    pub units: u64,
+    pub prompt_tokens: u64,
+    // Included in `prompt_tokens`.
+    #[serde(default)]
+    pub cached_tokens: u64,
+    pub completion_tokens: u64,
+    // Estimated from list prices in US dollars, 0 for models without a known price.
+    pub cost: f64,
+}
+
+// Which records to sum up. Unset fields match every record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageFilter {
+    pub guild_id: Option<u64>,
+    pub channel_id: Option<u64>,
+    pub user_id: Option<u64>,
+    pub since: Option<u64>,
+}
+
+impl UsageFilter {
+    pub fn guild(mut self, guild_id: u64) -> Self {
+        self.guild_id = Some(guild_id);
+        self
+    }
+
+    pub fn channel(mut self, channel_id: u64) -> Self {
+        self.channel_id = Some(channel_id);
+        self
+    }
+
+    pub fn user(mut self, user_id: u64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn since(mut self, since: u64) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn matches(&self, record: &UsageRecord) -> bool {
+        self.guild_id.is_none_or(|guild_id| record.guild_id == Some(guild_id))
+            && self.channel_id.is_none_or(|channel_id| record.channel_id == channel_id)
+            && self.user_id.is_none_or(|user_id| record.user_id == user_id)
+            && self.since.is_none_or(|since| record.timestamp >= since)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageTotals {
+    // Chat completions. Embeddings are made for questions and knowledge base documents, so they only add
+    // to the tokens and cost.
+    pub requests: u64,
+    // Images, speech and transcriptions, which have no tokens.
+    pub media_requests: u64,
+    pub prompt_tokens: u64,
+    pub cached_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    pub fn add(&mut self, record: &UsageRecord) {
+        match record.kind {
+            UsageKind::Chat => self.requests += 1,
+            kind if kind.is_media() => self.media_requests += 1,
+            _ => {},
+        }
+        self.prompt_tokens += record.prompt_tokens;
+        self.cached_tokens += record.cached_tokens;
+        self.completion_tokens += record.completion_tokens;
+        self.cost += record.cost;
+    }
+
+    pub fn merge(&mut self, totals: &UsageTotals) {
+        self.requests += totals.requests;
+        self.media_requests += totals.media_requests;
+        self.prompt_tokens += totals.prompt_tokens;
+        self.cached_tokens += totals.cached_tokens;
+        self.completion_tokens += totals.completion_tokens;
+        self.cost += totals.cost;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+// Running totals of the current month per guild and of the current day per user, so budgets can be
+// checked before every request without going through the records.
+#[derive(Debug, Default)]
+struct Aggregates {
+    month_start: u64,
+    day_start: u64,
+    guild_month: HashMap<u64, UsageTotals>,
+    // Keyed by guild, None for direct messages, and user.
+    user_day: HashMap<(Option<u64>, u64), UsageTotals>,
+}
+
+impl Aggregates {
+    // Records of an earlier period than the current one are left out, one of a later period starts it.
+    fn add(&mut self, record: &UsageRecord) {
+        let month_start = Period::Month.start(record.timestamp);
+        if month_start > self.month_start {
+            self.month_start = month_start;
+            self.guild_month.clear();
+        }
+        if let (true, Some(guild_id)) = (month_start == self.month_start, record.guild_id) {
+            self.guild_month.entry(guild_id).or_default().add(record);
+        }
+
+        let day_start = Period::Day.start(record.timestamp);
+        if day_start > self.day_start {
+            self.day_start = day_start;
+            self.user_day.clear();
+        }
+        if day_start == self.day_start {
+            self.user_day.entry((record.guild_id, record.user_id)).or_default().add(record);
+        }
+    }
+}
+
+// The cost of every paid request, kept in the store and, for the current and previous month, in memory
+// for reports. Older records are only in the store.
+#[derive(Debug)]
+pub struct UsageLedger {
+    prices: PriceTable,
+    store: Arc<dyn Store>,
+    records: Mutex<Vec<UsageRecord>>,
+    aggregates: Mutex<Aggregates>,
+    // Models already warned about, so a missing price is reported once.
+    unpriced: Mutex<HashSet<String>>,
+}
+
+impl UsageLedger {
+    pub fn load(settings: UsageSettings, store: Arc<dyn Store>) -> Result<UsageLedger, ServerError> {
+        let prices = match &settings.price_file {
+            Some(path) => {
+                let overrides: HashMap<String, ModelPrice> = serde_json::from_slice(&std::fs::read(path)?)?;
+                PriceTable::with_overrides(overrides)
+            },
+            None => PriceTable::new(),
+        };
+        let records = store.usage_records(retained_since(unix_time()))?;
+        println!("Loaded {} usage records", records.len());
+
+        let ledger = UsageLedger {
+            prices,
+            store,
+            records: Mutex::new(vec![]),
+            aggregates: Mutex::new(Aggregates::default()),
+            unpriced: Mutex::new(HashSet::new()),
+        };
+        for record in records {
+            ledger.insert(record);
+        }
+        Ok(ledger)
+    }
+
+    // Records a chat completion or embeddings request, priced by its tokens.
+    pub fn record(&self, attribution: Attribution, kind: UsageKind, model: &str, usage: &Usage) -> UsageRecord {
+        self.append(attribution, kind, model, 0, usage, self.prices.cost(model, usage))
+    }
+
+    // Records an image, speech or transcription request at `cost`, None if its price isn't known. `units`
+    // are what it was priced by, see `UsageRecord`.
+    pub fn record_media(&self, attribution: Attribution, kind: UsageKind, model: &str, units: u64, cost: Option<f64>) -> UsageRecord {
+        self.append(attribution, kind, model, units, &Usage::new(0, 0), cost)
+    }
+
+    fn append(&self, attribution: Attribution, kind: UsageKind, model: &str, units: u64, usage: &Usage, cost: Option<f64>) -> UsageRecord {
+        let cost = match cost {
+            Some(cost) => cost,
+            None => {
+                if self.unpriced.lock().unwrap().insert(model.to_owned()) {
+                    eprintln!("No price for model {}, its usage is recorded without cost", model);
+                }
+                0.0
+            }
+        };
+        let record = UsageRecord {
+            timestamp: unix_time(),
+            guild_id: attribution.guild_id,
+            channel_id: attribution.channel_id,
+            user_id: attribution.user_id,
+            kind,
+            model: model.to_owned(),
+            units,
+            prompt_tokens: usage.prompt_tokens,
+            cached_tokens: usage.cached_prompt_tokens(),
+            completion_tokens: usage.completion_tokens,
+            cost,
+        };
+
+        // Losing a record is better than failing the answer it was for, or holding it up while the record
+        // is written.
+        let (store, stored) = (self.store.clone(), record.clone());
+        let append = move || {
+            if let Err(err) = store.append_usage(&stored) {
+                eprintln!("Error storing usage record - {}", err);
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(append)),
+            Err(_) => append(),
+        }
+        self.insert(record.clone());
+        record
+    }
+
+    // Adds a record that is already in the store. Records from before the previous month are dropped
+    // from memory once a new month starts.
+    pub fn insert(&self, record: UsageRecord) {
+        let mut aggregates = self.aggregates.lock().unwrap();
+        let month_start = aggregates.month_start;
+        aggregates.add(&record);
+
+        let mut records = self.records.lock().unwrap();
+        if aggregates.month_start > month_start {
+            let since = retained_since(aggregates.month_start);
+            records.retain(|record| record.timestamp >= since);
+        }
+        records.push(record);
+    }
+
+    pub fn records(&self, filter: &UsageFilter) -> Vec<UsageRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect()
+    }
+
+    pub fn totals(&self, filter: &UsageFilter) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        for record in self.records.lock().unwrap().iter().filter(|record| filter.matches(record)) {
+            totals.add(record);
+        }
+        totals
+    }
+
+    // What the guild has spent in the month of the unix time `now`.
+    pub fn guild_month_totals(&self, guild_id: u64, now: u64) -> UsageTotals {
+        let aggregates = self.aggregates.lock().unwrap();
+        if aggregates.month_start != Period::Month.start(now) {
+            return UsageTotals::default();
+        }
+        aggregates.guild_month.get(&guild_id).copied().unwrap_or_default()
+    }
+
+    // What the user has used in the guild on the day of the unix time `now`, or everywhere if `guild_id`
+    // is None.
+    pub fn user_day_totals(&self, guild_id: Option<u64>, user_id: u64, now: u64) -> UsageTotals {
+        let aggregates = self.aggregates.lock().unwrap();
+        let mut totals = UsageTotals::default();
+        if aggregates.day_start != Period::Day.start(now) {
+            return totals;
+        }
+        for ((record_guild_id, record_user_id), user_totals) in &aggregates.user_day {
+            if *record_user_id == user_id && guild_id.is_none_or(|guild_id| *record_guild_id == Some(guild_id)) {
+                totals.merge(user_totals);
+            }
+        }
+        totals
+    }
+
+    // Totals per user, the most expensive first.
+    pub fn totals_by_user(&self, filter: &UsageFilter) -> Vec<(u64, UsageTotals)> {
+        let mut by_user: HashMap<u64, UsageTotals> = HashMap::new();
+        for record in self.records.lock().unwrap().iter().filter(|record| filter.matches(record)) {
+            by_user.entry(record.user_id).or_default().add(record);
+        }
+
+        let mut by_user: Vec<(u64, UsageTotals)> = by_user.into_iter().collect();
+        by_user.sort_by(|(_, a), (_, b)| b.cost.total_cmp(&a.cost).then(b.total_tokens().cmp(&a.total_tokens())));
+        by_user
+    }
+}
+
+// Reports go back at most to the start of the current week or month, which is never before the start of
+// the previous month.
+fn retained_since(now: u64) -> u64 {
+    Period::Month.start(Period::Month.start(now).saturating_sub(1))
+}
+
+pub fn to_csv(records: &[UsageRecord]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for record in records {
+        csv.push_str(&format!("{},{},{},{},{},{},{},{},{},{},{:.6}\n",
+            format_time(record.timestamp),
+            record.guild_id.map(|id| id.to_string()).unwrap_or_default(),
+            record.channel_id,
+            record.user_id,
+            record.kind.as_str(),
+            csv_field(&record.model),
+            record.units,
+            record.prompt_tokens,
+            record.cached_tokens,
+            record.completion_tokens,
+            record.cost));
+    }
+    csv
+}
+
+// Model names come from config and API responses, quote them if they could break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStore;
+
+    use super::*;
+
+    const DAY: u64 = 86_400;
+    // 2024-05-01T00:00:00Z.
+    const MAY: u64 = 1_714_521_600;
+    // 2024-06-01T00:00:00Z and 2024-07-01T00:00:00Z.
+    const JUNE: u64 = MAY + 31 * DAY;
+    const JULY: u64 = JUNE + 30 * DAY;
+
+    fn ledger(store: Arc<dyn Store>) -> UsageLedger {
+        UsageLedger::load(UsageSettings { price_file: None, budget_file: None }, store).unwrap()
+    }
+
+    fn record(timestamp: u64, guild_id: Option<u64>, channel_id: u64, user_id: u64, tokens: u64) -> UsageRecord {
+        UsageRecord {
+            timestamp,
+            guild_id,
+            channel_id,
+            user_id,
+            kind: UsageKind::Chat,
+            model: String::from("gpt-4o-mini"),
+            units: 0,
+            prompt_tokens: tokens,
+            cached_tokens: 0,
+            completion_tokens: tokens,
+            cost: tokens as f64 / 1000.0,
+        }
+    }
+
+    #[test]
+    fn records_what_requests_cost() {
+        let store = Arc::new(MemoryStore::new());
+        let ledger = ledger(store.clone());
+        let attribution = Attribution { guild_id: Some(1), channel_id: 2, user_id: 3 };
+        let record = ledger.record(attribution, UsageKind::Chat, "gpt-4o-mini", &Usage::new(1_000_000, 1_000_000));
+        assert!((record.cost - 0.75).abs() < 1e-9);
+        assert_eq!(store.usage_records(0).unwrap(), vec![record.clone()]);
+
+        let unpriced = ledger.record(attribution, UsageKind::Chat, "llama3.1:8b", &Usage::new(10, 10));
+        assert_eq!(unpriced.cost, 0.0);
+        assert_eq!(ledger.totals(&UsageFilter::default().guild(1)).requests, 2);
+    }
+
+    #[test]
+    fn counts_media_and_embeddings_apart_from_chat_requests() {
+        let store = Arc::new(MemoryStore::new());
+        let ledger = ledger(store.clone());
+        let attribution = Attribution { guild_id: Some(1), channel_id: 2, user_id: 3 };
+        ledger.record(attribution, UsageKind::Embedding, "text-embedding-3-small", &Usage::new(100, 0));
+        ledger.record(attribution, UsageKind::Chat, "gpt-4o-mini", &Usage::new(100, 10));
+        let image = ledger.record_media(attribution, UsageKind::Image, "dall-e-3", 2, Some(0.08));
+        assert_eq!((image.kind, image.units, image.prompt_tokens), (UsageKind::Image, 2, 0));
+        assert_eq!(store.usage_records(0).unwrap()[2], image);
+
+        let totals = ledger.totals(&UsageFilter::default());
+        assert_eq!((totals.requests, totals.media_requests), (1, 1));
+        assert_eq!(totals.total_tokens(), 210);
+        assert_eq!(ledger.user_day_totals(Some(1), 3, unix_time()).total_tokens(), 210);
+        assert!((ledger.guild_month_totals(1, unix_time()).cost - totals.cost).abs() < 1e-12);
+        assert!(totals.cost > 0.08);
+    }
+
+    #[test]
+    fn filters_by_guild_channel_user_and_time() {
+        let ledger = ledger(Arc::new(MemoryStore::new()));
+        ledger.insert(record(MAY, Some(1), 10, 100, 1));
+        ledger.insert(record(MAY + DAY, Some(1), 11, 100, 2));
+        ledger.insert(record(MAY + DAY, Some(1), 10, 101, 4));
+        ledger.insert(record(MAY + 2 * DAY, Some(2), 20, 100, 8));
+
+        let tokens = |filter: UsageFilter| ledger.totals(&filter).prompt_tokens;
+        assert_eq!(tokens(UsageFilter::default()), 15);
+        assert_eq!(tokens(UsageFilter::default().guild(1)), 7);
+        assert_eq!(tokens(UsageFilter::default().guild(1).channel(10)), 5);
+        assert_eq!(tokens(UsageFilter::default().user(100)), 11);
+        assert_eq!(tokens(UsageFilter::default().guild(1).since(MAY + DAY)), 6);
+
+        let by_user = ledger.totals_by_user(&UsageFilter::default().guild(1));
+        assert_eq!(by_user.iter().map(|(user_id, _)| *user_id).collect::<Vec<u64>>(), vec![101, 100]);
+    }
+
+    #[test]
+    fn running_totals_roll_over_with_the_period() {
+        let ledger = ledger(Arc::new(MemoryStore::new()));
+        ledger.insert(record(MAY + 3600, Some(1), 10, 100, 10));
+        ledger.insert(record(MAY + 7200, Some(1), 10, 100, 20));
+        ledger.insert(record(MAY + 7200, None, 30, 100, 40));
+        ledger.insert(record(MAY + DAY, Some(1), 10, 101, 5));
+
+        let now = MAY + DAY + 3600;
+        assert_eq!(ledger.guild_month_totals(1, now).prompt_tokens, 35);
+        assert_eq!(ledger.guild_month_totals(2, now).requests, 0);
+        assert_eq!(ledger.user_day_totals(Some(1), 101, now).prompt_tokens, 5);
+        // The first day's totals were replaced by the second's.
+        assert_eq!(ledger.user_day_totals(None, 100, now).requests, 0);
+        assert_eq!(ledger.user_day_totals(None, 100, MAY + 3600).requests, 0);
+
+        // A new month starts from nothing, before and after its first record.
+        assert_eq!(ledger.guild_month_totals(1, JUNE).requests, 0);
+        ledger.insert(record(JUNE + 60, Some(1), 10, 100, 1));
+        assert_eq!(ledger.guild_month_totals(1, JUNE + 120).prompt_tokens, 1);
+        // Late records of an earlier period don't count towards the current one.
+        ledger.insert(record(MAY + 2 * DAY, Some(1), 10, 100, 100));
+        assert_eq!(ledger.guild_month_totals(1, JUNE + 120).prompt_tokens, 1);
+    }
+
+    #[test]
+    fn sums_a_users_day_across_guilds() {
+        let ledger = ledger(Arc::new(MemoryStore::new()));
+        ledger.insert(record(MAY + 60, Some(1), 10, 100, 1));
+        ledger.insert(record(MAY + 60, Some(2), 20, 100, 2));
+        ledger.insert(record(MAY + 60, None, 30, 100, 4));
+        ledger.insert(record(MAY + 60, Some(1), 10, 101, 8));
+
+        assert_eq!(ledger.user_day_totals(Some(1), 100, MAY + 120).prompt_tokens, 1);
+        assert_eq!(ledger.user_day_totals(None, 100, MAY + 120).prompt_tokens, 7);
+    }
+
+    #[test]
+    fn keeps_the_current_and_previous_month_in_memory() {
+        let ledger = ledger(Arc::new(MemoryStore::new()));
+        ledger.insert(record(MAY, Some(1), 10, 100, 1));
+        ledger.insert(record(JUNE, Some(1), 10, 100, 2));
+        assert_eq!(ledger.records(&UsageFilter::default()).len(), 2);
+
+        ledger.insert(record(JULY, Some(1), 10, 100, 4));
+        let timestamps: Vec<u64> = ledger.records(&UsageFilter::default()).iter().map(|record| record.timestamp).collect();
+        assert_eq!(timestamps, vec![JUNE, JULY]);
+    }
+
+    #[test]
+    fn loads_only_recent_records() {
+        let store = Arc::new(MemoryStore::new());
+        store.append_usage(&record(MAY, Some(1), 10, 100, 1)).unwrap();
+        store.append_usage(&record(unix_time(), Some(1), 10, 100, 2)).unwrap();
+
+        let ledger = ledger(store);
+        assert_eq!(ledger.totals(&UsageFilter::default()).prompt_tokens, 2);
+        assert_eq!(ledger.guild_month_totals(1, unix_time()).prompt_tokens, 2);
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        let mut record = record(MAY, None, 10, 100, 1000);
+        record.model = String::from("ft:gpt-4o-mini:org,\"custom\"");
+        assert_eq!(to_csv(&[record]), format!("{}\n2024-05-01T00:00:00Z,,10,100,chat,\"ft:gpt-4o-mini:org,\"\"custom\"\"\",0,1000,0,1000,1.000000\n", CSV_HEADER));
+    }
+}
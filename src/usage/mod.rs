@@ -0,0 +1,9 @@
+mod budget;
+mod import;
+mod ledger;
+mod period;
+
+pub use budget::{Budget, Budgets};
+pub use import::import_usage_log;
+pub use ledger::{to_csv, Attribution, UsageFilter, UsageKind, UsageLedger, UsageRecord, UsageTotals};
+pub use period::{unix_time, Period};
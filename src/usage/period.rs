@@ -0,0 +1,142 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Reporting periods, in UTC and starting at the beginning of the current day, week (Monday) or month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    pub fn parse(value: &str) -> Option<Period> {
+        match value.to_lowercase().as_str() {
+            "day" | "today" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            "month" => Some(Period::Month),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Period::Day => "today",
+            Period::Week => "this week",
+            Period::Month => "this month",
+        }
+    }
+
+    // Unix time at which the period containing `now` started.
+    pub fn start(&self, now: u64) -> u64 {
+        let days = now / SECONDS_PER_DAY;
+        let start_day = match self {
+            Period::Day => days,
+            // 1970-01-01 was a Thursday.
+            Period::Week => days.saturating_sub((days + 3) % 7),
+            Period::Month => {
+                let (year, month, _) = civil_from_days(days);
+                days_from_civil(year, month, 1)
+            },
+        };
+        start_day * SECONDS_PER_DAY
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Formats a unix time as an ISO 8601 UTC date and time, e.g. 2024-05-01T13:45:00Z.
+pub fn format_time(time: u64) -> String {
+    let (year, month, day) = civil_from_days(time / SECONDS_PER_DAY);
+    let seconds = time % SECONDS_PER_DAY;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Converts days since 1970-01-01 into a (year, month, day) date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unix time of a UTC date at midnight.
+    fn midnight(year: u64, month: u64, day: u64) -> u64 {
+        days_from_civil(year, month, day) * SECONDS_PER_DAY
+    }
+
+    #[test]
+    fn converts_civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+        assert_eq!(civil_from_days(20_088), (2024, 12, 31));
+        assert_eq!(civil_from_days(20_089), (2025, 1, 1));
+        assert_eq!(days_from_civil(2024, 1, 1), 19_723);
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in 0..60_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days, "{}-{}-{}", year, month, day);
+        }
+    }
+
+    #[test]
+    fn periods_start_at_midnight_utc() {
+        let now = midnight(2024, 5, 1) + 13 * 3600 + 45 * 60;
+        assert_eq!(Period::Day.start(now), midnight(2024, 5, 1));
+        // 2024-05-01 was a Wednesday.
+        assert_eq!(Period::Week.start(now), midnight(2024, 4, 29));
+        assert_eq!(Period::Month.start(now), midnight(2024, 5, 1));
+        assert_eq!(Period::Month.start(midnight(2024, 3, 1) - 1), midnight(2024, 2, 1));
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 2024-01-01 was a Monday.
+        for day in 1..=7 {
+            assert_eq!(Period::Week.start(midnight(2024, 1, day) + 3600), midnight(2024, 1, 1));
+        }
+        assert_eq!(Period::Week.start(midnight(2024, 1, 8)), midnight(2024, 1, 8));
+        // Thursday 1970-01-01 has no Monday before it.
+        assert_eq!(Period::Week.start(3600), 0);
+    }
+
+    #[test]
+    fn formats_times_as_iso_8601() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1_714_571_100), "2024-05-01T13:45:00Z");
+        assert_eq!(format_time(1_735_689_599), "2024-12-31T23:59:59Z");
+    }
+}